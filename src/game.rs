@@ -16,19 +16,58 @@
 
 pub mod ascii_art;
 
+mod actions;
+mod animation;
 mod atoms;
+mod bindings;
+mod ending;
 mod entities;
 mod game_objects;
-
+mod gamepad;
+mod keys;
+mod markup;
+mod particles;
+mod physics;
+mod post_process;
+mod replay;
+mod save;
+mod scenes;
+mod settings;
+mod speedrun;
+mod state_machine;
+mod virtual_keyboard;
+
+pub use actions::*;
 pub use atoms::*;
-
+pub use bindings::*;
+pub use ending::*;
+pub use game_objects::{FLAG_DASH, FLAG_LEDGE_GRAB, FLAG_WALL_JUMP};
+pub use gamepad::*;
+pub use keys::*;
+pub use replay::*;
+pub use save::*;
+pub use scenes::*;
+pub use settings::*;
+pub use speedrun::*;
+pub use virtual_keyboard::*;
+
+use animation::*;
 use entities::*;
 use ascii_art::*;
 use game_objects::*;
-use web_sys::console;
-
-use crate::special_key_codes::*;
-
+use markup::*;
+use particles::*;
+use physics::*;
+use post_process::*;
+use state_machine::*;
+
+
+/// A change that happens once a covering wipe has hidden the room, see WIPE_TICKS
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Transition {
+    Room(RoomID),
+    GameOver,
+}
 
 
 pub struct Game {
@@ -36,36 +75,80 @@ pub struct Game {
     
     pub stopped: bool,
     pub cursor_position: Position,
+
+    /// Number of updates since the start
+    pub ticks: u32,
+    /// Input and checksums are added while Some
+    pub recording: Option<Replay>,
+
+    scenes: SceneStack,
+    /// Whether there is a game to continue from the title screen
+    has_progress: bool,
+    /// Set when the runner should save the game, until take_autosave_request
+    is_autosave_requested: bool,
+
+    stats: Stats,
+    /// Measures stats.play_ticks
+    speedrun: SpeedrunTimer,
+    bindings: KeyBindings,
+    /// The action of the controls menu waiting for a key
+    rebinding_action: Option<Action>,
+    /// The last rejected binding, shown in the controls menu
+    binding_error: Option<BindingError>,
+    /// Set when the bindings change, until take_settings_save_request
+    is_settings_save_requested: bool,
+
+    /// Some from reaching the end sign until the end of the sequence
+    ending: Option<Ending>,
+    /// The transition and the number of ticks until it happens, the gameplay is frozen meanwhile
+    transition: Option<(Transition, u32)>,
+    /// Stats of the finished play-through, shown by the victory scene
+    completed_stats: Option<Stats>,
     
     rooms: Vec<Room>,
     current_room: RoomID,
     entities: Vec<Box<dyn Entity>>,
 
-    pub symbol_buffer: Vec<Vec<char>>,
-    pub color_buffer: Vec<Vec<Color>>,
+    /// The rendered screen, one row per line
+    pub frame: Vec<Vec<Cell>>,
 
     view_position: Position,
     is_recording_spell: bool,
+    /// Shown under the spell console while typing a spell
+    virtual_keyboard: VirtualKeyboard,
 
     console: SpellConsole,
     console_id: EntityID,
+    /// Spells that have had an effect, in the order of casting
+    discovered_spells: Vec<String>,
 
     debug_id: EntityID,
 
     sign_ids: Vec<EntityID>,
+    tutorial_id: EntityID,
+    boss_sign_id: EntityID,
+    end_sign_id: EntityID,
     floor_id: EntityID,
 
     hero_id: EntityID,
     hero_controller: HeroController,
+    progression: Progression,
 
     sword_id: EntityID,
+    is_sword_hitting: bool,
 
     spike_ids: Vec<EntityID>,
 
     hostile_ids: Vec<EntityID>,
 
+    pickup_ids: Vec<EntityID>,
+
     platform_id: EntityID,
+    firewall_id: EntityID,
     boss_id: EntityID,
+
+    particles: ParticleSystem,
+    post_process: PostProcessor,
 }
 
 impl Game {
@@ -77,12 +160,29 @@ impl Game {
             stopped: false,
             
             cursor_position: Position::origin(),
+
+            ticks: 0,
+            recording: None,
+
+            scenes: SceneStack::new(),
+            has_progress: false,
+            is_autosave_requested: false,
+
+            stats: Stats::new(),
+            speedrun: SpeedrunTimer::new(),
+            bindings: KeyBindings::new(),
+            rebinding_action: None,
+            binding_error: None,
+            is_settings_save_requested: false,
+            ending: None,
+            transition: None,
+            completed_stats: None,
             
-            size: size,
+            size,
             is_recording_spell: false,
+            virtual_keyboard: VirtualKeyboard::new(),
 
-            symbol_buffer: vec![],
-            color_buffer: vec![],
+            frame: vec![],
 
             view_position: Position::origin(),
 
@@ -90,26 +190,39 @@ impl Game {
 
             console: SpellConsole::new(),
             console_id: 0,
+            discovered_spells: vec![],
 
             sign_ids: vec![],
+            tutorial_id: 0,
+            boss_sign_id: 0,
+            end_sign_id: 0,
             floor_id: 0,
 
             hero_id: 0,
             hero_controller: HeroController::new(),
+            progression: Progression::new(),
             sword_id: 0,
+            is_sword_hitting: false,
 
             spike_ids: vec![],
 
             hostile_ids: vec![],
 
+            pickup_ids: vec![],
+
             platform_id: 0,
+            firewall_id: 0,
 
             boss_id: 0,
+
+            particles: ParticleSystem::new(),
+            post_process: PostProcessor::new(),
         };
 
         new_self.construct_entities();
         new_self.construct_rooms();
         new_self.resize_buffers(size);
+        new_self.manage_console();
 
         new_self
     }
@@ -117,6 +230,10 @@ impl Game {
     pub fn set_size(&mut self, size: Size) {
         if self.size != size {
            self.resize_buffers(size);
+
+           if let Some(recording) = &mut self.recording {
+               recording.events.push((self.ticks, ReplayEvent::Resize(size)));
+           }
         }
 
         self.size = size;
@@ -125,7 +242,7 @@ impl Game {
 
     fn manage_console(&mut self) {
         let fig = self.entities[self.console_id].get_figure_mut();
-        fig.sprites[0].content = self.console.get_spell();
+        fig.sprites[0].set_content(self.console.get_spell());
         fig.position = Position::new(self.view_position.x, Y_BOTTOM+3);
         
         self.cursor_position =
@@ -134,110 +251,385 @@ impl Game {
     }
 
     fn resize_buffers(&mut self, size: Size) {
-        self.symbol_buffer = vec![vec![' '; size.width as usize]; size.height as usize];
-        self.color_buffer = vec![vec![Color::white(); size.width as usize]; size.height as usize];
+        self.frame = vec![vec![Cell::blank(); size.width as usize]; size.height as usize];
     }
 
     pub fn is_expecting_text(&self) -> bool {
-        self.is_recording_spell
+        self.is_recording_spell && !self.is_in_menu()
+    }
+
+    /// Returns: the top left corner of the virtual keyboard on the screen, under the spell console
+    fn get_virtual_keyboard_position(&self) -> Position {
+        Position::new(0, Y_BOTTOM + 4 - self.view_position.y)
+    }
+
+    /// Lets a touch screen type spells and open the spell console.
+    /// Returns: the action of tapping the cell of the screen
+    pub fn get_tap_action(&self, cell: Position) -> Option<Action> {
+        if self.is_expecting_text() {
+            let offset = cell - self.get_virtual_keyboard_position();
+            return VirtualKeyboard::get_key_at(offset).map(|key| key.get_action());
+        }
+
+        // The row of the empty spell console
+        let is_console_row = cell.y == self.get_virtual_keyboard_position().y - 1;
+
+        if is_console_row && !self.is_in_menu() && self.scenes.top() != Scene::Ending {
+            Some(Action::StartSpell)
+        } else {
+            None
+        }
+    }
+
+    /// Returns: whether a menu gets the input instead of the hero
+    pub fn is_in_menu(&self) -> bool {
+        !self.scenes.top().is_simulated()
     }
 
-    pub fn process_key(&mut self, key: char, ctrl: bool) {       
+    /// Returns: whether the runner should save the game now, once per request
+    pub fn take_autosave_request(&mut self) -> bool {
+        std::mem::take(&mut self.is_autosave_requested)
+    }
+
+    /// Returns: whether the runner should save the settings now, once per request
+    pub fn take_settings_save_request(&mut self) -> bool {
+        std::mem::take(&mut self.is_settings_save_requested)
+    }
+
+    pub fn get_settings(&self) -> Settings {
+        Settings {
+            bindings: self.bindings.clone(),
+        }
+    }
+
+    pub fn load_settings(&mut self, settings: &Settings) {
+        self.bindings = settings.bindings.clone();
+        self.update_tutorial();
+    }
+
+    /// Returns: a new game with the size and the key bindings of the replay
+    pub fn from_replay(replay: &Replay) -> Self {
+        let mut game = Self::new(replay.size);
+        game.bindings = replay.bindings.clone();
+        game.update_tutorial();
+        game
+    }
+
+    fn make_tutorial_sprite(&self) -> Sprite {
+        let (content, colors) = self.bindings.make_tutorial();
+        Sprite::from_markup(&content, &colors, SIGN_PALETTE, Color::cyan()).unwrap()
+    }
+
+    /// Shows the current bindings on the tutorial sign
+    fn update_tutorial(&mut self) {
+        let sprite = self.make_tutorial_sprite();
+        self.entities[self.tutorial_id].get_figure_mut().sprites[0] = sprite;
+    }
+
+    fn set_bindings(&mut self, bindings: KeyBindings) {
+        self.bindings = bindings;
+        self.update_tutorial();
+        self.is_settings_save_requested = true;
+    }
+
+    /// Arguments:
+    /// ctrl - whether Ctrl is held
+    pub fn process_key(&mut self, key: Key, ctrl: bool) {
+        if let Some(action) = self.get_key_action(key, ctrl) {
+            self.process_action(action);
+        }
+    }
+
+    /// Returns: the action of the key in the bindings, or of a fixed key in menus, spells and for pausing
+    pub fn get_key_action(&self, key: Key, ctrl: bool) -> Option<Action> {
         if ctrl {
-            match key {
-                'q' => {
-                    self.stopped = true;
-                    return;
-                }
+            return match key {
+                Key::Letter('q') | Key::Letter('c') => Some(Action::Quit),
+                _ => None,
+            };
+        }
 
-                _ => {}
-            }
+        if self.rebinding_action.is_some() {
+            return match key {
+                Key::Escape => Some(Action::Back),
+                _ => Some(Action::BindKey(key)),
+            };
+        }
+
+        if self.is_in_menu() {
+            return match key {
+                Key::ArrowUp => Some(Action::MenuUp),
+                Key::ArrowDown => Some(Action::MenuDown),
+                Key::Enter | Key::Space => Some(Action::Confirm),
+                Key::Escape | Key::Backspace => Some(Action::Back),
+
+                // Also the keys of jumping and crouching, e.g. W and S
+                _ => match self.bindings.get_action(key) {
+                    Some(Action::Jump) => Some(Action::MenuUp),
+                    Some(Action::Crouch) => Some(Action::MenuDown),
+                    _ => None,
+                },
+            };
         }
 
-        if key == ' ' {
-            self.is_recording_spell = true;
+        if key == Key::Escape {
+            return Some(Action::Pause);
         }
 
         if self.is_recording_spell {
-            match key {
-                KEY_ENTER => {
-                    self.is_recording_spell = false;
-                    let spell = self.console.finish_spell();
+            return match key {
+                Key::Enter => Some(Action::Enter),
+                Key::Backspace => Some(Action::Backspace),
+
+                // The virtual keyboard
+                Key::ArrowUp => Some(Action::MenuUp),
+                Key::ArrowDown => Some(Action::MenuDown),
+                Key::ArrowLeft => Some(Action::MenuLeft),
+                Key::ArrowRight => Some(Action::MenuRight),
+                Key::Space => Some(Action::Confirm),
+
+                _ => key.get_letter().map(Action::Letter),
+            };
+        }
 
-                    if spell == "android" {
-                        self.entities[self.boss_id].get_figure_mut().visible = false;
-                    }
-                }
+        self.bindings.get_action(key)
+    }
 
-                KEY_BACKSPACE => {
-                    self.console.backspace();
-                }
+    pub fn process_action(&mut self, action: Action) {
+        if let Some(recording) = &mut self.recording {
+            recording.events.push((self.ticks, ReplayEvent::Action(action)));
+        }
 
-                'a'..='z' | 'A'..='Z' => {
-                    self.console.add_char(key);
-                }
+        if self.is_in_menu() {
+            self.process_menu_action(action);
+            return;
+        }
 
-                _ => {}
+        // The ending cannot be skipped or paused
+        if self.scenes.top() == Scene::Ending {
+            if action == Action::Quit {
+                self.stopped = true;
             }
+            return;
+        }
 
-            self.manage_console();
+        if action == Action::Pause {
+            self.scenes.push(Scene::Paused);
+            self.is_autosave_requested = true;
+            return;
+        }
 
+        if self.is_recording_spell {
+            self.process_spell_action(action);
             return;
         }
 
-        match key {
-            'a' | 'd' => {
+        match action {
+            Action::MoveLeft => {
+                self.hero_controller.input.horizontal = -1;
+            }
 
-                let delta = if key == 'd' {1} else {-1};
+            Action::MoveRight => {
+                self.hero_controller.input.horizontal = 1;
+            }
 
-                let hero_pos_abs = self.entities[self.hero_id].get_figure().position;
-                let hero_pos = hero_pos_abs.relative_to(self.view_position);
+            Action::Jump => {
+                self.hero_controller.input.jump = true;
+            }
 
-                let hero_size = self.entities[self.hero_id].get_size();
-                let room_size = self.rooms[self.current_room].size;
+            Action::Crouch => {
+                self.hero_controller.input.crouch = true;
+            }
 
-                if hero_pos.x + delta >= 0
-                && hero_pos_abs.x + hero_size.width + delta < room_size.width - WORLD_RIGHT_MARGIN {
-                    self.entities[self.hero_id].get_figure_mut().position.x += delta;
-                    self.entities[self.sword_id].get_figure_mut().position.x += delta;
-                    
-                    if self.collides_with_damage(self.hero_id) {
-                        self.entities[self.hero_id].get_figure_mut().position = Position::origin();
-                        self.view_position = Position::origin();
-                        self.hero_controller.jump_potential = 0;
-                        return;
-                    }
+            Action::Dash => {
+                self.hero_controller.input.dash = true;
+            }
 
-                    if self.collides(self.hero_id) {
-                        self.entities[self.hero_id].get_figure_mut().position.x -= delta;
-                        self.entities[self.sword_id].get_figure_mut().position.x -= delta;
-                    } else {
-                        self.hero_controller.move_entity(&mut self.entities[self.hero_id], delta);
-                    }
+            Action::Attack => {
+                self.hero_controller.input.attack = true;
+            }
+
+            Action::ToggleTimer => {
+                self.speedrun.is_visible = !self.speedrun.is_visible;
+            }
+
+            Action::StartSpell => {
+                self.is_recording_spell = true;
+                self.manage_console();
+            }
+
+            Action::Quit => {
+                self.stopped = true;
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Typing a spell, from a physical or the virtual keyboard
+    fn process_spell_action(&mut self, action: Action) {
+        match action {
+            Action::Enter => {
+                self.is_recording_spell = false;
+                let spell = self.console.finish_spell();
+
+                if !spell.is_empty() {
+                    self.stats.spells_cast += 1;
+                    let hero_center = self.get_center(self.hero_id);
+                    self.particles.spawn_burst(&SPELL_PARTICLES, hero_center);
                 }
 
-                if hero_pos.x + delta + hero_size.width > self.size.width - WORLD_RIGHT_MARGIN {
-                    self.view_position += Position::new(1, 0);
+                // The boss may have been defeated before, e.g. in a loaded game
+                let is_boss_here = self.rooms[self.current_room].entities.contains(&self.boss_id);
+
+                if spell == "android" && is_boss_here && self.entities[self.boss_id].get_figure().visible {
+                    self.entities[self.boss_id].set_state(BOSS_STATE_DEFEATED).unwrap();
+                    self.post_process.request(ScreenEffectKind::Glitch(0.3), 20);
+                    self.post_process.request(ScreenEffectKind::Shake(2), 20);
+                    self.stats.enemies_defeated += 1;
+                    self.speedrun.split("boss", self.stats.play_ticks);
+
+                    if !self.discovered_spells.contains(&spell) {
+                        self.discovered_spells.push(spell);
+                    }
                 }
             }
 
-            'w' => {
-                self.hero_controller.jump_entity(&mut self.entities[self.hero_id]);
+            Action::Backspace => {
+                self.console.backspace();
             }
 
-            's' => {
-                self.hero_controller.crouch_entity(&mut self.entities[self.hero_id]);
+            Action::Letter(letter) => {
+                self.console.add_char(letter);
             }
 
-            'v' => {
-                let mut fig = self.entities[self.sword_id].get_figure_mut();
-                fig.visible = true;
+            // The virtual keyboard
+            Action::MenuUp => self.virtual_keyboard.move_selection(Position::new(0, -1)),
+            Action::MenuDown => self.virtual_keyboard.move_selection(Position::new(0, 1)),
+            Action::MenuLeft => self.virtual_keyboard.move_selection(Position::new(-1, 0)),
+            Action::MenuRight => self.virtual_keyboard.move_selection(Position::new(1, 0)),
+
+            Action::Confirm => {
+                let key_action = self.virtual_keyboard.get_selected_key().get_action();
+                self.process_spell_action(key_action);
+            }
+
+            Action::Quit => {
+                self.stopped = true;
             }
 
             _ => {}
         }
 
+        self.manage_console();
+    }
+
+    fn process_menu_action(&mut self, action: Action) {
+        if let Some(rebinding_action) = self.rebinding_action {
+            match action {
+                Action::BindKey(key) => {
+                    let mut bindings = self.bindings.clone();
+
+                    match bindings.rebind(rebinding_action, key) {
+                        Ok(()) => self.set_bindings(bindings),
+                        Err(error) => self.binding_error = Some(error),
+                    }
+
+                    self.rebinding_action = None;
+                }
+
+                Action::Back | Action::Quit => self.rebinding_action = None,
+
+                _ => {}
+            }
+            return;
+        }
 
+        let scene = self.scenes.top();
+        let items = scene.get_menu_items(self.has_progress);
+
+        match action {
+            Action::MenuUp => self.scenes.move_selection(-1, items.len()),
+            Action::MenuDown => self.scenes.move_selection(1, items.len()),
+
+            Action::Confirm => {
+                if let Some(item) = items.get(self.scenes.selected) {
+                    self.choose_menu_item(*item);
+                }
+            }
+
+            Action::Back if matches!(scene, Scene::Paused | Scene::Credits | Scene::Controls) => self.scenes.pop(),
+
+            Action::Quit => self.stopped = true,
+
+            _ => {}
+        }
+    }
+
+    fn choose_menu_item(&mut self, item: MenuItem) {
+        match item {
+            MenuItem::Continue => {
+                self.post_process.request(ScreenEffectKind::UnwipeHorizontal, UNWIPE_TICKS);
+                self.scenes.push(Scene::Playing);
+            }
+
+            MenuItem::NewGame => {
+                self.restart();
+                self.has_progress = true;
+                self.post_process.request(ScreenEffectKind::UnwipeHorizontal, UNWIPE_TICKS);
+                self.scenes.push(Scene::Playing);
+            }
+
+            MenuItem::Resume | MenuItem::Back => self.scenes.pop(),
+
+            MenuItem::TryAgain => {
+                self.revive_hero();
+                self.post_process.request(ScreenEffectKind::UnwipeHorizontal, UNWIPE_TICKS);
+                self.scenes.replace(Scene::Playing);
+            }
+
+            MenuItem::Credits => self.scenes.push(Scene::Credits),
+
+            MenuItem::Controls => {
+                self.binding_error = None;
+                self.scenes.push(Scene::Controls);
+            }
+
+            MenuItem::Rebind(action) => {
+                self.binding_error = None;
+                self.rebinding_action = Some(action);
+            }
+
+            MenuItem::ResetControls => {
+                self.binding_error = None;
+                self.set_bindings(KeyBindings::new());
+            }
+
+            MenuItem::TitleScreen => {
+                // Otherwise Continue would bring back a hero without health
+                if self.scenes.top() == Scene::GameOver {
+                    self.revive_hero();
+                }
+                self.scenes.reset(Scene::Title);
+            }
+
+            MenuItem::Quit => self.stopped = true,
+        }
+    }
+
+    /// Starts over, keeping the scenes, the size, the recording, the best splits and the bindings
+    pub fn restart(&mut self) {
+        let new_game = Game::new(self.size);
+        let old_game = std::mem::replace(self, new_game);
+
+        self.ticks = old_game.ticks;
+        self.recording = old_game.recording;
+        self.scenes = old_game.scenes;
+        self.speedrun = old_game.speedrun;
+        self.speedrun.reset_run();
+        self.bindings = old_game.bindings;
+        self.is_settings_save_requested = old_game.is_settings_save_requested;
+        self.update_tutorial();
     }
 
     fn new_entity(&mut self, ent: Box<dyn Entity>) -> EntityID {
@@ -248,8 +640,7 @@ impl Game {
     /// Returns: index for the new room
     fn new_room(&mut self, room_size: Size) -> RoomID {
         self.rooms.push(Room::new(room_size));
-        let index = self.rooms.len() - 1;
-        index
+        self.rooms.len() - 1
     }
 
     fn construct_entities(&mut self) {
@@ -261,30 +652,31 @@ impl Game {
         self.console_id = self.new_entity(Box::new(StaticEntity::new(
             "".into(), Color::white(), Position::origin()
         )));
+        self.entities[self.console_id].get_figure_mut().layer = Layer::Hud;
+
+        self.tutorial_id = self.new_entity(Box::new(StaticEntity::from_sprite(
+            self.make_tutorial_sprite(),
+            Position::new(10, 3)
+        )));
 
         self.sign_ids = vec![
-            self.new_entity(Box::new(StaticEntity::new(
-                TUTORIAL.into(),
-                Color::cyan(),
-                Position::new(10, 3)
-            ))),
-            self.new_entity(Box::new(StaticEntity::new(
-                GO_RIGHT_SIGN.into(),
-                Color::cyan(),
+            self.tutorial_id,
+            self.new_entity(Box::new(StaticEntity::from_sprite(
+                Sprite::from_markup(GO_RIGHT_SIGN, GO_RIGHT_SIGN_COLORS, SIGN_PALETTE, Color::cyan()).unwrap(),
                 Position::new(78, 15)
             ))),
-            self.new_entity(Box::new(StaticEntity::new(
-                BOSS_SIGN.into(),
-                Color::cyan(),
-                Position::new(140, 3)
-            ))),
-            self.new_entity(Box::new(StaticEntity::new(
-                END_SIGN.into(),
-                Color::cyan(),
-                Position::new(215, 15)
-            ))),
         ];
 
+        // In the boss room
+        self.boss_sign_id = self.new_entity(Box::new(StaticEntity::from_sprite(
+            Sprite::from_markup(BOSS_SIGN, BOSS_SIGN_COLORS, SIGN_PALETTE, Color::cyan()).unwrap(),
+            Position::new(20, 3)
+        )));
+        self.end_sign_id = self.new_entity(Box::new(StaticEntity::from_sprite(
+            Sprite::from_markup(END_SIGN, END_SIGN_COLORS, RAINBOW_PALETTE, Color::cyan()).unwrap(),
+            Position::new(95, 15)
+        )));
+
         self.floor_id = self.new_entity(Box::new(
             StaticEntity::new(
                 String::from_iter(FLOOR),
@@ -296,6 +688,7 @@ impl Game {
         self.hero_id = self.new_entity(self.hero_controller.new_entity());
 
         self.sword_id = self.new_entity(SwordController::new_entity());
+        self.entities[self.sword_id].get_figure_mut().z = 1; // Over the hero
 
         self.spike_ids = vec![
             self.new_entity(Box::new(HostileStaticEntity::new(
@@ -334,11 +727,19 @@ impl Game {
         ];
 
         self.hostile_ids = vec![
-            self.new_entity(Box::new(HostileStaticDefeatableEntity::new(
-                ENEMY.into(), Position::new(70, Y_BOTTOM-2), Color::red()
+            self.new_entity(EnemyController::new_entity(Position::new(70, Y_BOTTOM-2))),
+            self.new_entity(EnemyController::new_entity(Position::new(120, Y_BOTTOM-7))),
+        ];
+
+        self.pickup_ids = vec![
+            self.new_entity(Box::new(PickupEntity::new(
+                ABILITY_CHIP.into(), Position::new(30, Y_BOTTOM-1), Color::green(), FLAG_DASH
+            ))),
+            self.new_entity(Box::new(PickupEntity::new(
+                ABILITY_CHIP.into(), Position::new(55, Y_BOTTOM-1), Color::green(), FLAG_WALL_JUMP
             ))),
-            self.new_entity(Box::new(HostileStaticDefeatableEntity::new(
-                ENEMY.into(), Position::new(120, Y_BOTTOM-7), Color::red()
+            self.new_entity(Box::new(PickupEntity::new(
+                ABILITY_CHIP.into(), Position::new(90, Y_BOTTOM-1), Color::green(), FLAG_LEDGE_GRAB
             ))),
         ];
 
@@ -346,21 +747,41 @@ impl Game {
             PLATFORM.into(), Color::new(100, 100, 100), Position::new(100, Y_BOTTOM-5)
         )));
 
-        self.boss_id = self.new_entity(Box::new(HostileStaticEntity::new(
-            BOSS.into(), Position::new(170, 13), Color::blue()
+        let firewall = Sprite::from_content(FIREWALL, Color::new(255, 100, 0));
+        let firewall_height = firewall.size.height;
+        self.firewall_id = self.new_entity(Box::new(StaticEntity::from_sprite(
+            firewall, Position::new(170, Y_BOTTOM + 1 - firewall_height)
         )));
 
+        self.boss_id = self.new_entity(BossController::new_entity(Position::new(50, 13)));
+
     }
 
     fn construct_rooms(&mut self) {
         let start_room_id = self.new_room(Size::new(WORLD_MIN_WIDTH*3, WORLD_HEIGHT));
+        let boss_room_id = self.new_room(Size::new(WORLD_MIN_WIDTH*3/2, WORLD_HEIGHT));
+
+        for room_id in [start_room_id, boss_room_id] {
+            let skyline = Sprite::from_content(CITY_SKYLINE, Color::new(160, 80, 255));
+            let skyline_height = skyline.size.height;
+            self.rooms[room_id].backgrounds.push(
+                BackgroundLayer::new(skyline, Position::new(0, Y_BOTTOM + 1 - skyline_height), 0.25)
+            );
+
+            self.rooms[room_id].backgrounds.push(
+                BackgroundLayer::new(Sprite::from_content(DATA_STREAMS, Color::green()), Position::new(0, 8), 0.5)
+            );
+
+            // Present everywhere
+            self.rooms[room_id].entities.push(self.debug_id);
+            self.rooms[room_id].entities.push(self.console_id);
+            self.rooms[room_id].entities.push(self.floor_id);
+            self.rooms[room_id].entities.push(self.hero_id);
+            self.rooms[room_id].entities.push(self.sword_id);
+        }
 
-        self.rooms[start_room_id].entities.push(self.debug_id);
-        self.rooms[start_room_id].entities.push(self.console_id);
-        self.rooms[start_room_id].entities.push(self.floor_id);
-        self.rooms[start_room_id].entities.push(self.hero_id);
-        self.rooms[start_room_id].entities.push(self.sword_id);
         self.rooms[start_room_id].entities.push(self.platform_id);
+        self.rooms[start_room_id].entities.push(self.firewall_id);
 
         for id in &self.sign_ids {
             self.rooms[start_room_id].entities.push(*id);
@@ -374,7 +795,13 @@ impl Game {
             self.rooms[start_room_id].entities.push(*id);
         }
 
-        self.rooms[start_room_id].entities.push(self.boss_id);
+        for id in &self.pickup_ids {
+            self.rooms[start_room_id].entities.push(*id);
+        }
+
+        self.rooms[boss_room_id].entities.push(self.boss_sign_id);
+        self.rooms[boss_room_id].entities.push(self.boss_id);
+        self.rooms[boss_room_id].entities.push(self.end_sign_id);
 
         self.current_room = start_room_id;
     }
@@ -388,125 +815,688 @@ impl Game {
     }
 
     pub fn render(&mut self) {
+        // Menus freeze the world
+        let is_simulated = !self.is_in_menu();
 
-        self.update_before_render();
+        if is_simulated {
+            self.update_before_render();
+        }
 
-        for row in &mut self.symbol_buffer {
-            for symbol in row {
-                *symbol = ' ';
+        for row in &mut self.frame {
+            for cell in row {
+                *cell = Cell::blank();
             }
         }
 
-        for entity_id in &self.rooms[self.current_room].entities {
-            let figure = &self.entities[*entity_id].get_figure();
+        self.draw_backgrounds();
+
+        let (below_effects, above_effects): (Vec<EntityID>, Vec<EntityID>) = self
+            .get_draw_order()
+            .into_iter()
+            .partition(|id| self.entities[*id].get_figure().layer <= Layer::Effects);
+
+        for entity_id in below_effects {
+            self.draw_figure(entity_id);
+        }
+
+        // Particles are the top of the effects layer
+        self.draw_particles();
+
+        for entity_id in above_effects {
+            self.draw_figure(entity_id);
+        }
+
+        if let Some(ending) = &self.ending {
+            ending.draw(&mut self.frame, self.size);
+        }
+
+        // Effects are random, so they are only applied on ticks to keep replays deterministic
+        if is_simulated {
+            self.post_process.apply(&mut self.frame);
+        }
+
+        self.speedrun.draw(&mut self.frame, self.size, self.stats.play_ticks);
+
+        if self.is_expecting_text() {
+            let position = self.get_virtual_keyboard_position();
+            self.virtual_keyboard.draw(&mut self.frame, position);
+        }
+
+        self.draw_scene();
+
+        if is_simulated && self.ticks.is_multiple_of(AUTOSAVE_INTERVAL) {
+            self.is_autosave_requested = true;
+        }
+
+        if is_simulated && self.ticks.is_multiple_of(CHECKSUM_INTERVAL) {
+            let checksum = self.get_checksum();
+
+            if let Some(recording) = &mut self.recording {
+                recording.checksums.push((self.ticks, checksum));
+            }
+        }
+    }
+
+    /// Starts a replay from the current state, which is only reproducible from the start of the game
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Replay::new(self.size, self.bindings.clone()));
+    }
+
+    /// Returns: the replay recorded since start_recording, if any
+    pub fn take_recording(&mut self) -> Option<Replay> {
+        let mut recording = self.recording.take()?;
+        recording.length = self.ticks;
+        Some(recording)
+    }
+
+    pub fn get_save_data(&self) -> SaveData {
+        let hero = &self.entities[self.hero_id];
+        let is_hidden = |id: &EntityID| !self.entities[*id].get_figure().visible;
+
+        SaveData {
+            has_progress: self.has_progress,
+            room: self.current_room,
+            hero_position: hero.get_figure().position,
+            hero_facing_right: self.hero_controller.direction_right,
+            hero_health: self.hero_controller.health,
+            hero_state: hero.get_state(),
+            hero_velocity: hero.get_body().unwrap().velocity,
+            view_position: self.view_position,
+            progression_flags: self.progression.flags,
+            defeated_enemies: (0..self.hostile_ids.len()).filter(|index| is_hidden(&self.hostile_ids[*index])).collect(),
+            is_boss_defeated: is_hidden(&self.boss_id),
+            collected_pickups: (0..self.pickup_ids.len()).filter(|index| is_hidden(&self.pickup_ids[*index])).collect(),
+            discovered_spells: self.discovered_spells.clone(),
+            stats: self.stats,
+            speedrun: self.speedrun.clone(),
+        }
+    }
+
+    /// Applies a save to a game that has just been created.
+    /// Unknown indices are ignored, so that a save survives changes of the level.
+    pub fn load_save_data(&mut self, data: &SaveData) {
+        if data.room < self.rooms.len() {
+            self.current_room = data.room;
+        }
 
-            if !figure.visible {
+        let hero = &mut self.entities[self.hero_id];
+        hero.get_figure_mut().position = data.hero_position;
+        hero.get_figure_mut().facing_right = data.hero_facing_right;
+        hero.get_body_mut().unwrap().reset(data.hero_position);
+        hero.get_body_mut().unwrap().velocity = data.hero_velocity;
+
+        // A state of an older level may not exist anymore
+        match hero.start_state(data.hero_state) {
+            Ok(()) | Err(StateError::UnknownState(_)) => {}
+            Err(error) => panic!("hero: {}", error),
+        }
+
+        self.hero_controller.direction_right = data.hero_facing_right;
+        self.hero_controller.health = data.hero_health;
+
+        self.view_position = data.view_position;
+        self.progression.flags = data.progression_flags;
+
+        for index in &data.defeated_enemies {
+            if let Some(id) = self.hostile_ids.get(*index) {
+                self.entities[*id].set_state(ENEMY_STATE_DEFEATED).unwrap();
+            }
+        }
+
+        if data.is_boss_defeated {
+            self.entities[self.boss_id].set_state(BOSS_STATE_DEFEATED).unwrap();
+        }
+
+        for index in &data.collected_pickups {
+            if let Some(id) = self.pickup_ids.get(*index) {
+                self.entities[*id].get_figure_mut().visible = false;
+            }
+        }
+
+        self.discovered_spells = data.discovered_spells.clone();
+        self.stats = data.stats;
+        self.speedrun = data.speedrun.clone();
+        self.has_progress = data.has_progress;
+        self.manage_console();
+    }
+
+    /// Returns: a hash of the state that affects the further simulation and the picture
+    pub fn get_checksum(&self) -> u32 {
+        let mut checksum = Checksum::new();
+
+        checksum.add_u32(self.ticks);
+        checksum.add_i32(self.view_position.x);
+        checksum.add_i32(self.view_position.y);
+        checksum.add_u32(self.progression.flags);
+        checksum.add_str(&self.console.get_spell());
+        checksum.add_bool(self.is_recording_spell);
+        checksum.add_i32(self.virtual_keyboard.selected.x);
+        checksum.add_i32(self.virtual_keyboard.selected.y);
+        checksum.add_u32(self.particles.particles.len() as u32);
+        checksum.add_u32(self.stats.deaths);
+        checksum.add_u32(self.stats.spells_cast);
+        checksum.add_u32(self.stats.enemies_defeated);
+        checksum.add_u32(self.speedrun.splits.len() as u32);
+
+        for ent in &self.entities {
+            let figure = ent.get_figure();
+            checksum.add_i32(figure.position.x);
+            checksum.add_i32(figure.position.y);
+            checksum.add_bool(figure.visible);
+            checksum.add_bool(figure.facing_right);
+
+            if let Some(body) = ent.get_body() {
+                checksum.add_f32(body.position.x);
+                checksum.add_f32(body.position.y);
+                checksum.add_f32(body.velocity.x);
+                checksum.add_f32(body.velocity.y);
+            }
+        }
+
+        for cell in self.frame.iter().flatten() {
+            checksum.add_u32(cell.glyph as u32);
+            checksum.add_bytes(&[cell.fg.r, cell.fg.g, cell.fg.b]);
+        }
+
+        checksum.value
+    }
+
+    /// Draws the menu of the top scene over the dimmed world
+    fn draw_scene(&mut self) {
+        let scene = self.scenes.top();
+
+        if scene.is_simulated() {
+            return;
+        }
+
+        let mut text = scene.get_text();
+
+        if let (Scene::Victory, Some(stats)) = (scene, &self.completed_stats) {
+            text.push(String::new());
+            text.extend(stats.get_summary());
+        }
+
+        if scene == Scene::Controls {
+            text.push(match (self.rebinding_action, &self.binding_error) {
+                (Some(action), _) => format!("Press a key for {}, ESC to cancel", get_action_label(action)),
+                (None, Some(error)) => error.to_string(),
+                (None, None) => "Choose an action to rebind".into(),
+            });
+        }
+
+        let labels: Vec<String> = scene.get_menu_items(self.has_progress)
+            .iter()
+            .map(|item| self.get_menu_label(*item))
+            .collect();
+
+        dim_frame(&mut self.frame, 0.6);
+        draw_panel(
+            &mut self.frame,
+            self.size,
+            &scene.get_heading(),
+            &text,
+            &labels,
+            self.scenes.selected
+        );
+    }
+
+    fn get_menu_label(&self, item: MenuItem) -> String {
+        match item {
+            MenuItem::Rebind(action) if self.rebinding_action == Some(action) =>
+                format!("{:<16}{:>12}", item.get_label(), "..."),
+            MenuItem::Rebind(action) =>
+                format!("{:<16}{:>12}", item.get_label(), self.bindings.get_keys_label(action)),
+            _ => item.get_label().into(),
+        }
+    }
+
+    fn draw_figure(&mut self, entity_id: EntityID) {
+        let figure = &self.entities[entity_id].get_figure();
+
+        if !figure.visible {
+            return;
+        }
+        
+        for sprite in &figure.sprites {
+
+            if !sprite.active {
                 continue;
             }
+
+            let mut pos = figure.get_sprite_position(sprite).relative_to(self.view_position);
+            let initial_x = pos.x;
+
+            let mirrored_cells;
+            let cells = if figure.facing_right {
+                &sprite.cells
+            } else {
+                mirrored_cells = sprite.get_mirrored_cells();
+                &mirrored_cells
+            };
             
-            for sprite in &figure.sprites {
+            for line in cells {
 
-                if !sprite.active {
-                    continue;
+                for (cell, x) in line.iter().zip(initial_x .. initial_x + line.len() as i32) {
+                    pos.x = x;
+
+                    if self.is_not_in_view_yet(pos) {
+                        continue; // Discard only this character
+                    }
+
+                    if self.is_not_in_view_already(pos) {
+                        break; // Discard the rest of the line
+                    }
+
+                    if cell.is_transparent() {
+                        continue; // Invisible
+                    }
+
+                    self.frame[pos.y as usize][pos.x as usize].draw(cell, sprite.alpha);
                 }
 
-                let mut pos = (figure.position + sprite.offset).relative_to(self.view_position);
-                let initial_x = pos.x;
-                
-                for line in sprite.content.split('\n') {
+                pos.y += 1;
+            }
+        }
+    }
 
-                    for (symbol, x) in line.chars().zip(initial_x .. initial_x + line.len() as i32) {
-                        pos.x = x;
+    fn draw_particles(&mut self) {
+        for particle in &self.particles.particles {
+            let pos = particle.position.to_position().relative_to(self.view_position);
 
-                        if self.is_not_in_view_yet(pos) {
-                            continue; // Discard only this character
-                        }
+            if self.is_not_in_view_yet(pos) || self.is_not_in_view_already(pos) {
+                continue;
+            }
 
-                        if self.is_not_in_view_already(pos) {
-                            break; // Discard the rest of the line
-                        }
+            let cell = Cell::new(particle.glyph, particle.get_color());
+            self.frame[pos.y as usize][pos.x as usize].draw(&cell, 1.);
+        }
+    }
+
+    fn draw_backgrounds(&mut self) {
+        let room = &self.rooms[self.current_room];
+
+        for background in &room.backgrounds {
+            let start = background.get_screen_position(self.view_position);
+            let period = background.sprite.size.width;
 
-                        if symbol == ' ' {
-                            continue; // Invisible
+            let mut tile_x = start.x;
+            loop {
+                for (row, line) in background.sprite.cells.iter().enumerate() {
+                    for (col, cell) in line.iter().enumerate() {
+                        let pos = Position::new(tile_x + col as i32, start.y + row as i32);
+
+                        if cell.is_transparent() || self.is_not_in_view_yet(pos) || self.is_not_in_view_already(pos) {
+                            continue;
                         }
 
-                        let row = pos.y as usize;
-                        let col = pos.x as usize;
+                        let dimmed = Cell {
+                            fg: BACKGROUND_COLOR.blend(cell.fg, background.brightness),
+                            bg: cell.bg.map(|bg| BACKGROUND_COLOR.blend(bg, background.brightness)),
+                            ..*cell
+                        };
 
-                        self.symbol_buffer[row][col] = symbol;
-                        self.color_buffer[row][col] = sprite.color.clone();
+                        self.frame[pos.y as usize][pos.x as usize].draw(&dimmed, background.sprite.alpha);
                     }
+                }
+
+                tile_x += period;
 
-                    pos.y += 1;
+                if !background.tiled || period <= 0 || tile_x >= self.size.width {
+                    break;
                 }
             }
         }
+    }
+
+    /// Returns: entities of the current room sorted by layer and z.
+    /// Entities with equal layer and z keep the order in which they were added to the room.
+    fn get_draw_order(&self) -> Vec<EntityID> {
+        let mut order = self.rooms[self.current_room].entities.clone();
 
-        self.update_after_render();
+        order.sort_by_key(|id| {
+            let figure = self.entities[*id].get_figure();
+            (figure.layer, figure.z)
+        });
 
+        order
     }
 
     fn update_before_render(&mut self) {
+        self.ticks += 1;
+
         for ent in &mut self.entities {
             ent.animate();
         }
 
-        if self.hero_controller.jump_potential > 0 {
-            self.entities[self.hero_id].get_figure_mut().position.y -= 1;
+        self.particles.update();
+        self.post_process.update();
+
+        if self.ending.is_some() {
+            self.update_ending();
+            return;
+        }
+
+        if self.transition.is_some() {
+            self.update_transition();
+            return;
+        }
+
+        self.stats.play_ticks += 1;
+
+        let is_attacking = self.hero_controller.input.attack;
+
+        self.update_hero();
+
+        let hero_figure = self.entities[self.hero_id].get_figure();
+        let facing_right = hero_figure.facing_right;
+        let sword_pos = hero_figure.position + Position::new(if facing_right {3} else {-3}, 0);
 
-            self.hero_controller.jump_potential -= 1;
+        let sword = &mut self.entities[self.sword_id];
+        sword.get_figure_mut().position = sword_pos;
+        sword.get_figure_mut().facing_right = facing_right;
 
-            if self.collides_with_damage(self.hero_id) {
-                self.entities[self.hero_id].get_figure_mut().position = Position::origin();
-                self.hero_controller.jump_potential = 0;
-                self.view_position = Position::origin();
+        // Rejected by the guard until the swing is finished
+        match sword.set_state(SWORD_STATE_HIDDEN) {
+            Ok(()) | Err(StateError::Rejected { .. }) => {}
+            Err(error) => panic!("sword: {}", error),
+        }
+
+        if is_attacking {
+            sword.set_state(SWORD_STATE_SWINGING).unwrap();
+        }
+
+        for event in sword.get_animator_mut().unwrap().take_events() {
+            match event.as_str() {
+                "hit_start" => self.is_sword_hitting = true,
+                "hit_end" => self.is_sword_hitting = false,
+                _ => {}
             }
+        }
+
+        if self.is_sword_hitting {
+            self.defeat_with_sword();
+        }
+
+    }
+
+    fn update_hero(&mut self) {
+        self.hero_controller.control_entity(&mut self.entities[self.hero_id], &self.progression);
 
-            else if self.collides(self.hero_id) {
-                self.hero_controller.jump_potential = 0;
-                self.entities[self.hero_id].get_figure_mut().position.y += 1;
-                self.entities[self.hero_id].set_state(HERO_STATE_NORMAL);
+        let room_size = self.rooms[self.current_room].size;
+        let hero_size = self.entities[self.hero_id].get_size();
+        let min_x = self.view_position.x;
+        let max_x = room_size.width - WORLD_RIGHT_MARGIN - hero_size.width - 1;
+
+        let was_on_ground = self.entities[self.hero_id].get_body().unwrap().on_ground;
+
+        if self.move_body(self.hero_id, min_x, max_x) {
+            let hero_center = self.get_center(self.hero_id);
+            self.particles.spawn_burst(&DAMAGE_SPARK_PARTICLES, hero_center);
+
+            self.hero_controller.health = self.hero_controller.health.saturating_sub(1);
+            self.stats.deaths += 1;
+            self.post_process.request(ScreenEffectKind::Shake(1), 6);
+            self.post_process.request(ScreenEffectKind::Glitch(0.1), 6);
+
+            // The hero stays on the spikes until the game over
+            if self.hero_controller.health == 0 {
+                self.start_transition(Transition::GameOver, ScreenEffectKind::WipeVertical);
+                return;
             }
 
-        } else {
+            self.respawn_hero();
+            self.post_process.request(ScreenEffectKind::FadeIn, 12);
+            return;
+        }
+
+        if !was_on_ground && self.entities[self.hero_id].get_body().unwrap().on_ground {
+            let hero_pos = self.entities[self.hero_id].get_figure().position;
+            let feet = hero_pos + Position::new(hero_size.width / 2, hero_size.height - 1);
+            self.particles.spawn_burst(&LANDING_DUST_PARTICLES, Vector::from_position(feet));
+        }
 
-            self.entities[self.hero_id].get_figure_mut().position.y += 1;
+        self.hero_controller.animate_entity(&mut self.entities[self.hero_id]);
+
+        self.collect_pickups();
+
+        let hero_pos = self.entities[self.hero_id].get_figure().position.relative_to(self.view_position);
+        let overflow = hero_pos.x + hero_size.width - (self.size.width - WORLD_RIGHT_MARGIN);
+        if overflow > 0 {
+            self.view_position += Position::new(overflow, 0);
+        }
 
-            if self.collides_with_damage(self.hero_id) {
-                self.entities[self.hero_id].get_figure_mut().position = Position::origin();
-                self.hero_controller.jump_potential = 0;
-                self.view_position = Position::origin();
+        // Walking into the right end of a room leads to the next one
+        let next_room = self.current_room + 1;
+        if self.entities[self.hero_id].get_figure().position.x >= max_x && next_room < self.rooms.len() {
+            self.start_transition(Transition::Room(next_room), ScreenEffectKind::WipeHorizontal);
+            return;
+        }
+
+        if self.current_room != self.rooms.len() - 1 {
+            return;
+        }
+
+        let end_sign_x = self.entities[self.end_sign_id].get_figure().position.x;
+        let hero_right = self.entities[self.hero_id].get_figure().position.x + hero_size.width;
+
+        if hero_right >= end_sign_x - VICTORY_DISTANCE {
+            self.start_ending();
+        }
+    }
+
+    /// Freezes the gameplay while the wipe covers the screen
+    fn start_transition(&mut self, transition: Transition, wipe: ScreenEffectKind) {
+        self.transition = Some((transition, WIPE_TICKS));
+        self.post_process.request(wipe, WIPE_TICKS);
+    }
+
+    /// Counts down to the tick at which the covering wipe ends
+    fn update_transition(&mut self) {
+        let (transition, ticks) = match &mut self.transition {
+            Some(transition) => transition,
+            None => return,
+        };
+
+        *ticks -= 1;
+        if *ticks > 0 {
+            return;
+        }
+
+        match *transition {
+            Transition::Room(room_id) => self.change_room(room_id),
+            Transition::GameOver => {
+                self.scenes.replace(Scene::GameOver);
+                self.respawn_hero();
             }
-            else if self.collides(self.hero_id) {
-                self.entities[self.hero_id].get_figure_mut().position.y -= 1;
+        }
+
+        self.transition = None;
+    }
+
+    /// Puts the hero at the left end of another room, uncovering it with a wipe
+    fn change_room(&mut self, room_id: RoomID) {
+        self.speedrun.split(&get_room_split(self.current_room), self.stats.play_ticks);
+        self.current_room = room_id;
+
+        let hero = &mut self.entities[self.hero_id];
+        let position = Position::new(0, hero.get_figure().position.y);
+        hero.get_figure_mut().position = position;
+        hero.get_body_mut().unwrap().reset(position);
+        self.hero_controller.reset();
+
+        self.view_position = Position::origin();
+        self.particles.clear();
+        self.post_process.request(ScreenEffectKind::UnwipeHorizontal, UNWIPE_TICKS);
+    }
+
+    /// Stops the gameplay and plays the ending, which pans to the end sign first
+    fn start_ending(&mut self) {
+        let sign = &self.entities[self.end_sign_id];
+        let sign_center = sign.get_figure().position.x + sign.get_size().width / 2;
+        let room_width = self.rooms[self.current_room].size.width;
+        let target_x = (sign_center - self.size.width / 2).clamp(0, (room_width - self.size.width).max(0));
+
+        self.speedrun.split(&get_room_split(self.current_room), self.stats.play_ticks);
+        self.ending = Some(Ending::new(Position::new(target_x, self.view_position.y)));
+        self.hero_controller.reset();
+        self.is_recording_spell = false;
+        self.manage_console();
+        self.scenes.replace(Scene::Ending);
+    }
+
+    fn update_ending(&mut self) {
+        let ending = match &mut self.ending {
+            Some(ending) => ending,
+            None => return,
+        };
+
+        match ending.update(&mut self.view_position, self.size.height) {
+            Some(EndingPhase::FadeOut) => self.post_process.request(ScreenEffectKind::FadeOut, ENDING_FADE_TICKS),
+            Some(EndingPhase::Finished) => self.finish_ending(),
+            _ => {}
+        }
+    }
 
-                if self.entities[self.hero_id].get_state() != HERO_STATE_CROUCHING {
-                    self.entities[self.hero_id].set_state(HERO_STATE_NORMAL);
+    /// Shows the stats over a new game, so that the finished one is not continued.
+    /// The autosave replaces the finished game with one without progress, which keeps the best splits.
+    fn finish_ending(&mut self) {
+        let stats = self.stats;
+
+        self.restart();
+        self.completed_stats = Some(stats);
+        self.scenes.reset(Scene::Victory);
+        self.is_autosave_requested = true;
+    }
+
+    /// Returns: the middle of the entity in world coordinates
+    fn get_center(&self, entity_id: EntityID) -> Vector {
+        let ent = &self.entities[entity_id];
+        let position = ent.get_figure().position;
+        let size = ent.get_size();
+
+        Vector::new(
+            position.x as f32 + size.width as f32 / 2.,
+            position.y as f32 + size.height as f32 / 2.
+        )
+    }
+
+    /// Restores the health after a game over and puts the hero at the start
+    fn revive_hero(&mut self) {
+        self.hero_controller.health = HERO_MAX_HEALTH;
+        self.respawn_hero();
+    }
+
+    fn respawn_hero(&mut self) {
+        let hero = &mut self.entities[self.hero_id];
+        hero.get_figure_mut().position = Position::origin();
+        hero.get_body_mut().unwrap().reset(Position::origin());
+        hero.set_state(HERO_STATE_NORMAL).unwrap();
+        self.hero_controller.reset();
+
+        self.view_position = Position::origin();
+    }
+
+    /// Moves the figure cell by cell towards the rounded position of its body,
+    /// stopping at obstacles.
+    /// Returns: whether the entity has touched something that deals damage
+    fn move_body(&mut self, entity_id: EntityID, min_x: i32, max_x: i32) -> bool {
+        let body = self.entities[entity_id].get_body().unwrap();
+        let mut target = body.get_cell();
+
+        if target.x < min_x || target.x > max_x {
+            target.x = target.x.clamp(min_x, max_x);
+            self.entities[entity_id].get_body_mut().unwrap().stop_x(target.x);
+        }
+
+        for (is_x, target_coord) in [(true, target.x), (false, target.y)] {
+            loop {
+                let pos = self.entities[entity_id].get_figure().position;
+                let coord = if is_x { pos.x } else { pos.y };
+
+                if coord == target_coord {
+                    break;
                 }
-            } else {
-                self.entities[self.hero_id].set_state(HERO_STATE_FALLING);
+
+                let step = (target_coord - coord).signum();
+                let delta = if is_x { Position::new(step, 0) } else { Position::new(0, step) };
+
+                self.entities[entity_id].get_figure_mut().position += delta;
+
+                if self.collides_with_damage(entity_id) {
+                    return true;
+                }
+
+                if self.collides(entity_id) {
+                    self.entities[entity_id].get_figure_mut().position = pos;
+
+                    let body = self.entities[entity_id].get_body_mut().unwrap();
+                    if is_x {
+                        body.stop_x(pos.x);
+                    } else {
+                        body.stop_y(pos.y);
+                    }
+
+                    break;
+                }
+            }
+        }
+
+        let height = self.entities[entity_id].get_size().height;
+
+        let on_ground = self.collides_shifted(entity_id, Position::new(0, 1));
+        let mut wall_side = 0;
+        let mut ledge_side = 0;
+
+        for side in [-1, 1] {
+            if !self.collides_shifted(entity_id, Position::new(side, 0)) {
+                continue;
+            }
+
+            wall_side = side;
+
+            if self.collides_shifted(entity_id, Position::new(side, 1 - height))
+            && !self.collides_shifted(entity_id, Position::new(side, -height)) {
+                ledge_side = side;
             }
         }
 
-        let sword_pos =
-            self.entities[self.hero_id].get_figure().position
-            + Position::new(3, 0);
-        let mut fig = self.entities[self.sword_id].get_figure_mut();
-        fig.position = sword_pos;
+        let body = self.entities[entity_id].get_body_mut().unwrap();
+        body.set_on_ground(on_ground);
+        body.wall_side = wall_side;
+        body.ledge_side = ledge_side;
+
+        false
+    }
+
+    fn collides_shifted(&mut self, entity_id: EntityID, delta: Position) -> bool {
+        let pos = self.entities[entity_id].get_figure().position;
 
-        self.defeat_with_sword();
+        self.entities[entity_id].get_figure_mut().position = pos + delta;
+        let result = self.collides(entity_id);
+        self.entities[entity_id].get_figure_mut().position = pos;
 
+        result
     }
 
-    fn update_after_render(&mut self) {
-        let mut fig = self.entities[self.sword_id].get_figure_mut();
-        fig.visible = false;
+    fn collect_pickups(&mut self) {
+        for id in &self.pickup_ids {
+            if !self.entities[*id].get_figure().visible { continue; }
+            if !self.rooms[self.current_room].entities.contains(id) { continue; }
+
+            if collides(self.entities[self.hero_id].get_figure(), self.entities[*id].get_figure()) {
+                let flags = self.entities[*id].get_pickup_flags();
+                self.progression.unlock(flags);
+                self.speedrun.split(&get_pickup_split(flags), self.stats.play_ticks);
+                self.entities[*id].get_figure_mut().visible = false;
+            }
+        }
     }
 
     fn collides(&self, entity_id: EntityID) -> bool {
         for ent_id in &self.rooms[self.current_room].entities {
-            if *ent_id != entity_id {
+            if *ent_id != entity_id && *ent_id != self.sword_id {
                 if !self.entities[*ent_id].get_figure().visible { continue; }
+                if !self.entities[*ent_id].is_solid() { continue; }
                 if collides(self.entities[entity_id].get_figure(), self.entities[*ent_id].get_figure()) {
                     return true;
                 }
@@ -539,7 +1529,11 @@ impl Game {
                 if !self.entities[*ent_id].is_defeatable() { continue; }
         
                 if collides(self.entities[self.sword_id].get_figure(), self.entities[*ent_id].get_figure()) {
-                    self.entities[*ent_id].get_figure_mut().visible = false;
+                    self.entities[*ent_id].set_state(ENEMY_STATE_DEFEATED).unwrap();
+                    self.stats.enemies_defeated += 1;
+
+                    let center = self.get_center(*ent_id);
+                    self.particles.spawn_burst(&ENEMY_DEFEAT_PARTICLES, center);
                 }
             }
         }
@@ -0,0 +1,128 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::Key;
+
+/// What the player wants to do, independent of the backend and the keys
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Crouch,
+    Dash,
+    Attack,
+
+    /// Start typing a spell
+    StartSpell,
+    /// A letter of a spell
+    Letter(char),
+    Backspace,
+    /// Cast the spell
+    Enter,
+
+    /// Show or hide the speedrun timer
+    ToggleTimer,
+
+    /// Open the pause menu
+    Pause,
+    /// Also move the selection of the virtual keyboard while typing a spell
+    MenuUp,
+    MenuDown,
+    MenuLeft,
+    MenuRight,
+    /// Choose the selected menu item, or press the selected key of the virtual keyboard
+    Confirm,
+    /// Close the menu, e.g. resume from the pause menu
+    Back,
+    /// The key pressed for the action being rebound in the controls menu
+    BindKey(Key),
+
+    Quit,
+}
+
+impl Action {
+    /// Held actions are applied once per tick for as long as the key is held.
+    /// Other actions are applied once per key press.
+    pub fn is_held(&self) -> bool {
+        matches!(
+            self,
+            Self::MoveLeft | Self::MoveRight | Self::Jump | Self::Crouch | Self::Dash | Self::Attack
+        )
+    }
+
+    /// Returns: a short name that from_code turns back into the action, e.g. for replay files
+    pub fn get_code(&self) -> String {
+        match self {
+            Self::MoveLeft => "left".into(),
+            Self::MoveRight => "right".into(),
+            Self::Jump => "jump".into(),
+            Self::Crouch => "crouch".into(),
+            Self::Dash => "dash".into(),
+            Self::Attack => "attack".into(),
+            Self::StartSpell => "spell".into(),
+            Self::Letter(letter) => format!("letter:{}", letter),
+            Self::Backspace => "backspace".into(),
+            Self::Enter => "enter".into(),
+            Self::ToggleTimer => "timer".into(),
+            Self::Pause => "pause".into(),
+            Self::MenuUp => "menu_up".into(),
+            Self::MenuDown => "menu_down".into(),
+            Self::MenuLeft => "menu_left".into(),
+            Self::MenuRight => "menu_right".into(),
+            Self::Confirm => "confirm".into(),
+            Self::Back => "back".into(),
+            Self::BindKey(key) => format!("bind:{}", key.get_name()),
+            Self::Quit => "quit".into(),
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        if let Some(letter) = code.strip_prefix("letter:") {
+            let mut chars = letter.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(letter), None) => Some(Self::Letter(letter)),
+                _ => None,
+            };
+        }
+
+        if let Some(key) = code.strip_prefix("bind:") {
+            return Key::from_name(key).map(Self::BindKey);
+        }
+
+        match code {
+            "left" => Some(Self::MoveLeft),
+            "right" => Some(Self::MoveRight),
+            "jump" => Some(Self::Jump),
+            "crouch" => Some(Self::Crouch),
+            "dash" => Some(Self::Dash),
+            "attack" => Some(Self::Attack),
+            "spell" => Some(Self::StartSpell),
+            "backspace" => Some(Self::Backspace),
+            "enter" => Some(Self::Enter),
+            "timer" => Some(Self::ToggleTimer),
+            "pause" => Some(Self::Pause),
+            "menu_up" => Some(Self::MenuUp),
+            "menu_down" => Some(Self::MenuDown),
+            "menu_left" => Some(Self::MenuLeft),
+            "menu_right" => Some(Self::MenuRight),
+            "confirm" => Some(Self::Confirm),
+            "back" => Some(Self::Back),
+            "quit" => Some(Self::Quit),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,401 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Events that nobody takes are dropped, oldest first, beyond this number
+const MAX_PENDING_EVENTS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimationMode {
+    Loop,
+    /// Stops at the last frame
+    Once,
+    /// Goes back and forth
+    PingPong,
+}
+
+pub struct AnimationFrame {
+    pub sprites: Vec<SpriteID>,
+    /// Number of ticks the frame is shown
+    pub ticks: u32,
+    /// Fired when the frame is shown
+    pub events: Vec<String>,
+}
+
+pub struct AnimationClip {
+    pub name: String,
+    pub mode: AnimationMode,
+    pub frames: Vec<AnimationFrame>,
+}
+
+
+#[derive(Debug)]
+pub enum AnimationError {
+    UnknownClip(String),
+    /// Errors in animation data. Line numbers start from 1.
+    UnknownSprite { line: usize, name: String },
+    UnknownMode { line: usize, mode: String },
+    MissingClipName { line: usize },
+    BadDuration { line: usize },
+    FrameOutsideClip { line: usize },
+    EmptyClip(String),
+}
+
+impl fmt::Display for AnimationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownClip(name) => write!(f, "unknown clip '{}'", name),
+            Self::UnknownSprite { line, name } => write!(f, "line {}: unknown sprite '{}'", line, name),
+            Self::UnknownMode { line, mode } => write!(f, "line {}: unknown mode '{}'", line, mode),
+            Self::MissingClipName { line } => write!(f, "line {}: clip has no name", line),
+            Self::BadDuration { line } => write!(f, "line {}: duration must be a positive number", line),
+            Self::FrameOutsideClip { line } => write!(f, "line {}: frame does not belong to a clip", line),
+            Self::EmptyClip(name) => write!(f, "clip '{}' has no frames", name),
+        }
+    }
+}
+
+
+/// Plays animation clips by enabling and disabling the sprites of a figure.
+///
+/// Clips are described in text, one clip header followed by its frames:
+///
+///     clip <name> [loop|once|ping-pong]
+///     <sprite>[,<sprite>...] <ticks> [event...]
+///
+/// Empty lines and lines starting with # are ignored.
+pub struct Animator {
+    sprite_names: HashMap<String, SpriteID>,
+    clips: HashMap<String, AnimationClip>,
+
+    current_clip: Option<String>,
+    current_frame: usize,
+    ticks_left: u32,
+    is_reversed: bool,
+    is_finished: bool,
+
+    events: Vec<String>,
+}
+
+impl Animator {
+    pub fn new() -> Self {
+        Self {
+            sprite_names: HashMap::new(),
+            clips: HashMap::new(),
+            current_clip: None,
+            current_frame: 0,
+            ticks_left: 0,
+            is_reversed: false,
+            is_finished: false,
+            events: vec![],
+        }
+    }
+
+    pub fn name_sprite(&mut self, name: &str, sprite_id: SpriteID) {
+        self.sprite_names.insert(name.into(), sprite_id);
+    }
+
+    pub fn add_clip(&mut self, clip: AnimationClip) -> Result<(), AnimationError> {
+        if clip.frames.is_empty() {
+            return Err(AnimationError::EmptyClip(clip.name));
+        }
+
+        self.clips.insert(clip.name.clone(), clip);
+        Ok(())
+    }
+
+    /// Parses clips from text. Sprites must be named before.
+    pub fn load(&mut self, data: &str) -> Result<(), AnimationError> {
+        let mut clip: Option<AnimationClip> = None;
+
+        for (index, line) in data.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let first = words.next().unwrap();
+
+            if first == "clip" {
+                if let Some(finished) = clip.take() {
+                    self.add_clip(finished)?;
+                }
+
+                let name = words.next().ok_or(AnimationError::MissingClipName { line: line_number })?;
+
+                let mode = match words.next() {
+                    None | Some("once") => AnimationMode::Once,
+                    Some("loop") => AnimationMode::Loop,
+                    Some("ping-pong") => AnimationMode::PingPong,
+                    Some(mode) => return Err(AnimationError::UnknownMode {
+                        line: line_number, mode: mode.into()
+                    }),
+                };
+
+                clip = Some(AnimationClip { name: name.into(), mode, frames: vec![] });
+                continue;
+            }
+
+            let mut sprites = vec![];
+            for name in first.split(',') {
+                match self.sprite_names.get(name) {
+                    Some(id) => sprites.push(*id),
+                    None => return Err(AnimationError::UnknownSprite {
+                        line: line_number, name: name.into()
+                    }),
+                }
+            }
+
+            let ticks = match words.next().map(|word| word.parse::<u32>()) {
+                Some(Ok(ticks)) if ticks > 0 => ticks,
+                _ => return Err(AnimationError::BadDuration { line: line_number }),
+            };
+
+            let events = words.map(String::from).collect();
+
+            match clip.as_mut() {
+                Some(clip) => clip.frames.push(AnimationFrame { sprites, ticks, events }),
+                None => return Err(AnimationError::FrameOutsideClip { line: line_number }),
+            }
+        }
+
+        if let Some(finished) = clip.take() {
+            self.add_clip(finished)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn has_clip(&self, name: &str) -> bool {
+        self.clips.contains_key(name)
+    }
+
+    /// Starts the clip from the first frame
+    pub fn play(&mut self, name: &str, sprites: &mut [Sprite]) -> Result<(), AnimationError> {
+        if !self.has_clip(name) {
+            return Err(AnimationError::UnknownClip(name.into()));
+        }
+
+        self.stop(sprites);
+
+        self.current_clip = Some(name.into());
+        self.is_finished = false;
+        self.show_frame(0, sprites);
+
+        Ok(())
+    }
+
+    /// Hides the sprites of the current frame
+    pub fn stop(&mut self, sprites: &mut [Sprite]) {
+        self.set_current_frame_active(sprites, false);
+        self.current_clip = None;
+        self.current_frame = 0;
+        self.is_reversed = false;
+    }
+
+    pub fn tick(&mut self, sprites: &mut [Sprite]) {
+        let (mode, last) = match self.get_clip() {
+            Some(clip) => (clip.mode, clip.frames.len() - 1),
+            None => return,
+        };
+
+        if self.is_finished {
+            return;
+        }
+
+        if self.ticks_left > 1 {
+            self.ticks_left -= 1;
+            return;
+        }
+
+        let next_frame = match mode {
+            AnimationMode::Loop => if self.current_frame == last { 0 } else { self.current_frame + 1 },
+
+            AnimationMode::Once => {
+                if self.current_frame == last {
+                    self.is_finished = true;
+                    return;
+                }
+                self.current_frame + 1
+            }
+
+            AnimationMode::PingPong => {
+                if last == 0 {
+                    return;
+                }
+
+                if self.current_frame == last {
+                    self.is_reversed = true;
+                } else if self.current_frame == 0 {
+                    self.is_reversed = false;
+                }
+
+                if self.is_reversed { self.current_frame - 1 } else { self.current_frame + 1 }
+            }
+        };
+
+        self.set_current_frame_active(sprites, false);
+        self.show_frame(next_frame, sprites);
+    }
+
+    fn get_clip(&self) -> Option<&AnimationClip> {
+        self.current_clip.as_ref().and_then(|name| self.clips.get(name))
+    }
+
+    fn show_frame(&mut self, frame_index: usize, sprites: &mut [Sprite]) {
+        self.current_frame = frame_index;
+        self.set_current_frame_active(sprites, true);
+
+        let frame = &self.clips[self.current_clip.as_ref().unwrap()].frames[frame_index];
+        self.ticks_left = frame.ticks;
+        self.events.extend(frame.events.iter().cloned());
+
+        // Most entities never take their events
+        let excess = self.events.len().saturating_sub(MAX_PENDING_EVENTS);
+        self.events.drain(..excess);
+    }
+
+    fn set_current_frame_active(&self, sprites: &mut [Sprite], active: bool) {
+        for sprite_id in self.get_current_sprites() {
+            sprites[*sprite_id].active = active;
+        }
+    }
+
+    pub fn get_clip_name(&self) -> Option<&str> {
+        self.current_clip.as_deref()
+    }
+
+    pub fn get_frame(&self) -> usize {
+        self.current_frame
+    }
+
+    pub fn get_current_sprites(&self) -> &[SpriteID] {
+        match self.get_clip() {
+            Some(clip) => &clip.frames[self.current_frame].sprites,
+            None => &[],
+        }
+    }
+
+    /// A clip played in the Once mode has reached the end of its last frame
+    pub fn is_finished(&self) -> bool {
+        self.is_finished
+    }
+
+    /// Returns: events fired since the last call
+    pub fn take_events(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIPS: &str = "
+        # Comments and empty lines are skipped
+
+        clip blink loop
+        on 2 shown
+        off 1
+
+        clip swing once
+        on,off 1 start
+        off 1 end
+
+        clip wave ping-pong
+        a 1
+        b 1
+        c 1
+    ";
+
+    fn make_animator() -> (Animator, Vec<Sprite>) {
+        let mut animator = Animator::new();
+        let names = ["on", "off", "a", "b", "c"];
+
+        for (id, name) in names.iter().enumerate() {
+            animator.name_sprite(name, id);
+        }
+
+        let sprites = names.iter().map(|_| Sprite::from_content("x", Color::white())).collect();
+        animator.load(CLIPS).unwrap();
+        (animator, sprites)
+    }
+
+    /// Returns: the frame index after each tick
+    fn play(animator: &mut Animator, sprites: &mut [Sprite], name: &str, ticks: usize) -> Vec<usize> {
+        animator.play(name, sprites).unwrap();
+        (0..ticks).map(|_| { animator.tick(sprites); animator.get_frame() }).collect()
+    }
+
+    #[test]
+    fn parses_clips() {
+        let (animator, _) = make_animator();
+        assert!(animator.has_clip("blink") && animator.has_clip("swing") && animator.has_clip("wave"));
+        assert_eq!(animator.clips["swing"].frames[0].sprites, vec![0, 1]);
+        assert_eq!(animator.clips["blink"].mode, AnimationMode::Loop);
+    }
+
+    #[test]
+    fn rejects_bad_data() {
+        let load = |data: &str| {
+            let mut animator = Animator::new();
+            animator.name_sprite("on", 0);
+            animator.load(data).unwrap_err()
+        };
+
+        assert!(matches!(load("clip\non 1"), AnimationError::MissingClipName { line: 1 }));
+        assert!(matches!(load("clip a sideways"), AnimationError::UnknownMode { line: 1, .. }));
+        assert!(matches!(load("clip a\nnone 1"), AnimationError::UnknownSprite { line: 2, .. }));
+        assert!(matches!(load("clip a\non 0"), AnimationError::BadDuration { line: 2 }));
+        assert!(matches!(load("on 1"), AnimationError::FrameOutsideClip { line: 1 }));
+        assert!(matches!(load("clip a\nclip b\non 1"), AnimationError::EmptyClip(_)));
+    }
+
+    #[test]
+    fn modes() {
+        let (mut animator, mut sprites) = make_animator();
+
+        // The first frame lasts 2 ticks
+        assert_eq!(play(&mut animator, &mut sprites, "blink", 5), vec![0, 1, 0, 0, 1]);
+        assert!(sprites[1].active && !sprites[0].active);
+
+        assert_eq!(play(&mut animator, &mut sprites, "swing", 3), vec![1, 1, 1]);
+        assert!(animator.is_finished());
+
+        assert_eq!(play(&mut animator, &mut sprites, "wave", 5), vec![1, 2, 1, 0, 1]);
+    }
+
+    #[test]
+    fn events() {
+        let (mut animator, mut sprites) = make_animator();
+
+        play(&mut animator, &mut sprites, "swing", 1);
+        assert_eq!(animator.take_events(), vec!["start", "end"]);
+        assert!(animator.take_events().is_empty());
+
+        // Not taken, e.g. by enemies
+        play(&mut animator, &mut sprites, "blink", 100);
+        assert_eq!(animator.take_events().len(), MAX_PENDING_EVENTS);
+    }
+}
@@ -1,109 +1,284 @@
 // Art by Black Sheep
 
-use super::Position;
+use super::{Position, Color, Palette, ParticlePreset, ATTR_BOLD, ATTR_DIM, ATTR_BLINK};
+
+use std::f32::consts::{PI, FRAC_PI_2};
 
 pub const WORLD_HEIGHT: i32 = 30;
 pub const WORLD_MIN_WIDTH: i32 = 80;
 pub const WORLD_RIGHT_MARGIN: i32 = 40;
 pub const Y_BOTTOM: i32 = WORLD_HEIGHT - 2;
 
-pub const HERO_JUMPING_HEIGHT: i32 = 7;
+/// Same as the page background in web/style.css
+pub const BACKGROUND_COLOR: Color = Color::new(0x1e, 0x1a, 0x25);
+
+type Str = &'static str;
 
-type STR = &'static str;
+pub const DEBUG: Str = "?";
 
-pub const DEBUG: STR = "?";
+pub const TITLE_LOGO: Str = "\
+╔═╗╦ ╦╔╗ ╔═╗╦═╗╔═╗╔═╗╔═╗╔═╗╔═╗
+║  ╚╦╝╠╩╗║╣ ╠╦╝╚═╗╠═╝╠═╣║  ║╣
+╚═╝ ╩ ╚═╝╚═╝╩╚═╚═╝╩  ╩ ╩╚═╝╚═╝
+    ╔═╗╔╦╗╦╔═╗╔═╗╔═╗╦═╗╦ ╦
+    ║╣ ║║║║╚═╗╚═╗╠═╣╠╦╝╚╦╝
+    ╚═╝╩ ╩╩╚═╝╚═╝╩ ╩╩╚═ ╩";
 
 
-pub const HERO: STR =
+pub const HERO: Str =
 r" 0
 /#\
 / \";
 
-pub const HERO_CROUCHING_1: STR =
+pub const HERO_CROUCHING_1: Str =
 r" o
 /#\
 | |";
 
-pub const HERO_CROUCHING_2: STR = 
+pub const HERO_CROUCHING_2: Str = 
 r"/o\
 /#\";
 
-pub const HERO_JUMPING_RIGHT: STR =
+/// Colour keys of the hero sprites: head, body and legs
+pub const HERO_COLORS: Str = "\
+hhh
+
+lll";
+
+pub const HERO_CROUCHING_2_COLORS: Str = "hhh";
+
+pub const HERO_PALETTE: &Palette = &[
+    ('h', Some(Color::new(255, 200, 160)), None, 0),
+    ('l', Some(Color::cyan()), None, 0),
+];
+
+// Sprites face right, they are mirrored automatically
+
+pub const HERO_JUMPING: Str =
 r" 0
 /#‾
 / \";
 
-pub const HERO_JUMPING_LEFT: STR = 
-r" 0
-‾#\
+pub const HERO_FALL: Str = 
+r"\0/
+ #
 / \";
 
-pub const HERO_FALL: STR = 
-r"\0/
+/// The wall is on the right
+pub const HERO_WALL_SLIDE: Str =
+r" 0/
+/#]
+ \>";
+
+pub const HERO_DASH: Str =
+r"  0
+=#‾
+ /‾";
+
+/// The ledge is on the right
+pub const HERO_HANGING: Str =
+r" 0/
+ #/
+ | ";
+
+pub const HERO_CLIMBING: Str =
+r"_0_
  #
 / \";
 
+/// Clip names match the hero states
+pub const HERO_ANIMATIONS: Str = "
+clip normal
+    hero 1
 
-#[cfg(feature = "terminal_backend")]
-pub const TUTORIAL: STR = "\
-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*
-| Quit: Ctrl + Q                          |
-* Move left: LEFT ARROW or [              *
-| Move right: RIGHT ARROW or ]            |
-* Crouch: DOWN ARROW or .                 *
-| Jump or Stop crouching: TOP ARROW or /  |
-* Cast a spell: any letters + ENTER       *
-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*
+clip crouching once
+    crouching_1 5
+    crouching_2 1
+
+clip jumping
+    jumping 1
+
+clip falling
+    falling 1
+
+clip wall_sliding
+    wall_sliding 1
+
+clip dashing
+    dashing 1
+
+clip hanging
+    hanging 1
+
+clip climbing
+    climbing 1
 ";
 
+
+/// Lines of the tutorial sign, see KeyBindings::make_tutorial.
+/// Action codes in braces are replaced by the bound keys, other text in braces is a fixed key.
+pub const TUTORIAL_TEMPLATE: Str = "\
+Move: {left} {right}
+      (jump - {jump}, crouch - {crouch})
+Fight with your sword: {attack}
+Dash: {dash} (once unlocked)
+Cast a spell: {spell} + letters + {ENTER}
+Pause and controls: {ESC}
+Speedrun timer: {timer}";
+
+/// A browser tab cannot be closed by the game
+#[cfg(feature = "terminal_backend")]
+pub const TUTORIAL_EXTRA_LINES: Str = "Quit: {Ctrl + Q}";
+
 #[cfg(feature = "wasm_backend")]
-pub const TUTORIAL: STR = "\
-╔═════════════════════════════════════════╗
-║ Move: WASD or ARROWS                    ║
-║       (W/Up - jump, S/Down - crouch)    ║
-║ Fight with your sword: V                ║
-║ Cast a spell: SPACE + letters + ENTER   ║
-╚═════════════════════════════════════════╝
-";
+pub const TUTORIAL_EXTRA_LINES: Str = "Gamepad: stick, {A} jump, {X} sword, {Y} spell";
+
+/// Shown next to the virtual keyboard while typing a spell
+#[cfg(feature = "terminal_backend")]
+pub const VIRTUAL_KEYBOARD_HINT: Str = "Arrows choose a key, SPACE presses it";
+
+#[cfg(feature = "wasm_backend")]
+pub const VIRTUAL_KEYBOARD_HINT: Str = "Tap a key, or choose it and press SPACE/A";
+
+/// Used by all signs: border, key names
+pub const SIGN_PALETTE: &Palette = &[
+    ('b', Some(Color::new(0, 128, 255)), None, 0),
+    ('k', Some(Color::yellow()), None, ATTR_BOLD),
+];
+
+
+// Background layers, see Room::backgrounds
+
+pub const CITY_SKYLINE: Str =
+"        ┌┐                             ╷
+   ▄▄   ││       ┌──┐                ┌┴┐         ▄▄▄
+  ████  ││   ▄▄  │▪▪│     ┌─┐        │▪│        █████
+  █▪▪█ ┌┘└┐  ██  │▪▪│ ▄▄  │▪│  ┌──┐  │▪│   ▄▄   █▪▪▪█
+  █▪▪█ │▪▪│  ██  │▪▪│ ██  │▪│  │▪▪│  │▪│   ██   █▪▪▪█
+  ████ │▪▪│  ██▄▄│▪▪│ ██  │▪│  │▪▪│ ┌┘▪└┐  ██▄▄ █████    ";
+
+pub const DATA_STREAMS: Str = "
+               1               0
+               0               1
+               0               0
+               0               0
+  1            0               0
+  0            1               0
+  0            0               1     1
+  0      0     0       1       1     1
+  1      0     0       1       1     1
+  0      1             0             0
+         1             0             0
+         0             1             0
+         0             0             0
+                                     1
+                                     1";
 
 
 pub const FLOOR: [char; 300] = ['-'; 300];
 
 
-pub const SWORD_1: STR = "
+pub const SWORD_1: Str = "
 
 ▛";
-pub const SWORD_2: STR = "
+pub const SWORD_2: Str = "
 ■■
  ▔";
-pub const SWORD_3: STR = "
+pub const SWORD_3: Str = "
 ■■■";
-pub const SWORD_4: STR = "\
+pub const SWORD_4: Str = "\
  ▁
 ■■";
-pub const SWORD_5: STR = "\
+pub const SWORD_5: Str = "\
 ▙";
 
+/// The sword only hurts between hit_start and hit_end
+pub const SWORD_ANIMATIONS: Str = "
+clip swinging once
+    sword_1 2
+    sword_2 2
+    sword_3 2 hit_start
+    sword_4 2
+    sword_5 2 hit_end
+";
+
+
+pub const ABILITY_CHIP: Str = "◆";
+
 
-pub const SPIKE_UP: STR = "▲";
-pub const SPIKE_DOWN: STR = "▼";
+pub const SPIKE_UP: Str = "▲";
+pub const SPIKE_DOWN: Str = "▼";
 
 
-pub const ENEMY: STR = "\
+pub const ENEMY: Str = "\
 ◢█ █◣
 ◥█ █◤";
 
-pub const GO_RIGHT_SIGN: STR = "Go, hero, go! ->";
+pub const ENEMY_COLORS: Str = "\
+e   e
+e   e";
 
-pub const BOSS_SIGN: STR = "\
+pub const ENEMY_PALETTE: &Palette = &[
+    ('e', Some(Color::new(128, 0, 0)), None, 0),
+];
+
+pub const ENEMY_ANIMATIONS: Str = "
+clip alive
+    enemy 1
+";
+
+pub const GO_RIGHT_SIGN: Str = "Go, hero, go! ->";
+pub const GO_RIGHT_SIGN_COLORS: Str = "              kk";
+
+pub const BOSS_SIGN: Str = "\
 The new iPhone 19S Pro, now with 100 front-facing cameras!
 Defeating spell: the name of who defeats iOS.";
 
-pub const END_SIGN: STR = "Congratulations!";
+pub const BOSS_SIGN_COLORS: Str = "
+kkkkkkkkkkkkkkk";
+
+pub const END_SIGN: Str = "Congratulations!";
+pub const END_SIGN_COLORS: Str = "rygcbmrygcbmrygc";
+
+/// Scrolls up after the hero reaches the end sign
+pub const ENDING_CRAWL: Str = "\
+The phone falls silent.
+Its hundred cameras close, one by one.
+
+The data streams run clean again,
+and the skyline lights up window by window.
+
+The emissary logs out,
+leaving a single line in the system log:
 
-pub const PLATFORM: STR = "==================================";
+    android > ios
 
-pub const BOSS: STR =
+THE END";
+
+pub const RAINBOW_PALETTE: &Palette = &[
+    ('r', Some(Color::red()), None, 0),
+    ('y', Some(Color::yellow()), None, 0),
+    ('g', Some(Color::green()), None, 0),
+    ('c', Some(Color::cyan()), None, 0),
+    ('b', Some(Color::blue()), None, 0),
+    ('m', Some(Color::magenta()), None, 0),
+];
+
+pub const PLATFORM: Str = "==================================";
+
+/// Too high to jump over, so it takes the ledge grab to leave the first room
+pub const FIREWALL: Str = "\
+▓▓▓
+▓▓▓
+▓▓▓
+▓▓▓
+▓▓▓
+▓▓▓
+▓▓▓
+▓▓▓
+▓▓▓";
+
+pub const BOSS: Str =
 " ◢██████████████████████████████████████████████◣
 ◢████████████████████████████████████████████████◣
 ██████████████████████████████████████████████████▒
@@ -123,4 +298,97 @@ pub const BOSS: STR =
 ██████████████████████████████████████████████████▒
 ██████████████████████████████████████████████████▒
 ██████████████████████████████████████████████████▒
-██████████████████████████████████████████████████▒";
\ No newline at end of file
+██████████████████████████████████████████████████▒";
+
+/// s - side, g - glass reflection, c - camera block, l - lens
+pub const BOSS_COLORS: Str = "
+
+                                                  s
+ggggggggggggggggggggggggggggggggggggggggggggggggggs
+                                                  s
+                                                  s
+                                                  s
+          clc  ccc                                s
+          ccc  clc                                s
+          clc  ccc                                s
+                                                  s
+                                                  s
+                                                  s
+                                                  s
+                                                  s
+                                                  s
+                                                  s
+                                                  s
+                                                  s
+                                                  s";
+
+pub const BOSS_PALETTE: &Palette = &[
+    ('s', Some(Color::blue()), None, ATTR_DIM),
+    ('g', Some(Color::new(180, 180, 255)), Some(Color::new(0, 0, 96)), 0),
+    ('c', Some(Color::new(160, 160, 160)), None, 0),
+    ('l', Some(Color::red()), None, ATTR_BLINK),
+];
+
+pub const BOSS_ANIMATIONS: Str = "
+clip active
+    boss 1
+";
+
+
+// Particle effects
+
+pub const ENEMY_DEFEAT_PARTICLES: ParticlePreset = ParticlePreset {
+    glyphs: &['*', '+', '·', '░'],
+    start_color: Color::red(),
+    end_color: BACKGROUND_COLOR,
+    count: 14,
+    min_lifetime: 8,
+    max_lifetime: 14,
+    min_speed: 0.3,
+    max_speed: 0.9,
+    direction: 0.,
+    spread: PI,
+    gravity: 0.05,
+};
+
+pub const SPELL_PARTICLES: ParticlePreset = ParticlePreset {
+    glyphs: &['✦', '*', '·'],
+    start_color: Color::cyan(),
+    end_color: Color::magenta(),
+    count: 10,
+    min_lifetime: 10,
+    max_lifetime: 18,
+    min_speed: 0.2,
+    max_speed: 0.5,
+    direction: -FRAC_PI_2,
+    spread: FRAC_PI_2,
+    gravity: -0.01,
+};
+
+pub const LANDING_DUST_PARTICLES: ParticlePreset = ParticlePreset {
+    glyphs: &['.', '·', ','],
+    start_color: Color::new(160, 160, 160),
+    end_color: BACKGROUND_COLOR,
+    count: 6,
+    min_lifetime: 4,
+    max_lifetime: 7,
+    min_speed: 0.3,
+    max_speed: 0.6,
+    direction: -FRAC_PI_2,
+    spread: FRAC_PI_2 * 0.9,
+    gravity: 0.05,
+};
+
+pub const DAMAGE_SPARK_PARTICLES: ParticlePreset = ParticlePreset {
+    glyphs: &['*', 'x', '+'],
+    start_color: Color::yellow(),
+    end_color: Color::red(),
+    count: 8,
+    min_lifetime: 5,
+    max_lifetime: 9,
+    min_speed: 0.5,
+    max_speed: 1.0,
+    direction: 0.,
+    spread: PI,
+    gravity: 0.1,
+};
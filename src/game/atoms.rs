@@ -14,7 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -22,50 +22,141 @@ pub struct Color {
 }
 
 impl Color {
-    pub fn new(r: u8, g: u8, b: u8) -> Self {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
         Self { r, g, b }
     }
 
-    pub fn black() -> Self {
+    pub const fn black() -> Self {
         Self::new(0, 0, 0)
     }
 
-    pub fn white() -> Self {
+    pub const fn white() -> Self {
         Self::new(255, 255, 255)
     }
 
-    pub fn red() -> Self {
+    pub const fn red() -> Self {
         Self::new(255, 0, 0)
     }
     
-    pub fn green() -> Self {
+    pub const fn green() -> Self {
         Self::new(0, 255, 0)
     }
 
-    pub fn blue() -> Self {
+    pub const fn blue() -> Self {
         Self::new(0, 0, 255)
     }
 
-    pub fn magenta() -> Self {
+    pub const fn magenta() -> Self {
         Self::new(255, 0, 255)
     }
 
-    pub fn yellow() -> Self {
+    pub const fn yellow() -> Self {
         Self::new(255, 255, 0)
     }
 
-    pub fn cyan() -> Self {
+    pub const fn cyan() -> Self {
         Self::new(0, 255, 255)
     }
+
+    /// Arguments:
+    /// alpha - opacity of `other`, from 0 (only self is seen) to 1 (only other is seen)
+    pub fn blend(self, other: Color, alpha: f32) -> Color {
+        let alpha = alpha.clamp(0., 1.);
+        let mix = |below: u8, above: u8| {
+            (below as f32 * (1. - alpha) + above as f32 * alpha).round() as u8
+        };
+
+        Color::new(mix(self.r, other.r), mix(self.g, other.g), mix(self.b, other.b))
+    }
+}
+
+
+pub const ATTR_BOLD: u32 = 1 << 0;
+pub const ATTR_DIM: u32 = 1 << 1;
+pub const ATTR_UNDERLINE: u32 = 1 << 2;
+pub const ATTR_BLINK: u32 = 1 << 3;
+
+/// A character on the screen or in a sprite
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub glyph: char,
+    pub fg: Color,
+    /// None means the default background (BACKGROUND_COLOR on the canvas,
+    /// the terminal's own background in the terminal).
+    /// In sprites None means transparent.
+    pub bg: Option<Color>,
+    /// ATTR_* flags
+    pub attrs: u32,
+}
+
+impl Cell {
+    pub fn new(glyph: char, fg: Color) -> Self {
+        Self { glyph, fg, bg: None, attrs: 0 }
+    }
+
+    pub fn blank() -> Self {
+        Self::new(' ', Color::white())
+    }
+
+    /// Spaces without background are not drawn
+    pub fn is_transparent(&self) -> bool {
+        self.glyph == ' ' && self.bg.is_none()
+    }
+
+    pub fn has_attr(&self, attr: u32) -> bool {
+        self.attrs & attr != 0
+    }
+
+    /// Draws another cell over this one.
+    /// A translucent space with a background tints this cell without replacing its glyph.
+    ///
+    /// Arguments:
+    /// alpha - opacity of `above`, from 0 to 1
+    pub fn draw(&mut self, above: &Cell, alpha: f32) {
+        if above.is_transparent() || alpha <= 0. {
+            return;
+        }
+
+        let below_bg = self.bg.unwrap_or(BACKGROUND_COLOR);
+
+        if alpha >= 1. {
+            if above.glyph != ' ' {
+                self.glyph = above.glyph;
+                self.fg = above.fg;
+                self.attrs = above.attrs;
+            }
+        } else if above.glyph != ' ' {
+            self.glyph = above.glyph;
+            self.fg = below_bg.blend(above.fg, alpha);
+            self.attrs = above.attrs;
+        } else if let Some(tint) = above.bg {
+            self.fg = self.fg.blend(tint, alpha);
+        }
+
+        if let Some(bg) = above.bg {
+            self.bg = Some(below_bg.blend(bg, alpha));
+        }
+    }
 }
 
 
 pub struct Sprite {
+    /// Default colour of the glyphs
     pub color: Color,
     pub content: String,
+    /// Parsed content, one row per line. Must be rebuilt when the content changes.
+    pub cells: Vec<Vec<Cell>>,
     pub offset: Position,
     pub size: Size,
     pub active: bool,
+    /// Opacity, from 0 to 1
+    pub alpha: f32,
+}
+
+impl Default for Sprite {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Sprite {
@@ -73,12 +164,44 @@ impl Sprite {
         Self {
             color: Color::white(),
             content: "".into(),
+            cells: vec![],
             offset: Position::origin(), 
             size: Size::new(0, 0),
             active: true,
+            alpha: 1.,
+        }
+    }
+
+    /// A sprite of a single colour
+    pub fn from_content(content: &str, color: Color) -> Self {
+        Self {
+            color,
+            content: content.into(),
+            cells: parse_plain(content, color),
+            offset: Position::origin(),
+            size: Self::get_content_size(content),
+            active: true,
+            alpha: 1.,
         }
     }
 
+    /// A sprite coloured per glyph, see parse_markup()
+    pub fn from_markup(content: &str, color_keys: &str, palette: &Palette, color: Color)
+        -> Result<Self, MarkupError>
+    {
+        Ok(Self {
+            cells: parse_markup(content, color_keys, palette, color)?,
+            ..Self::from_content(content, color)
+        })
+    }
+
+    /// Replaces the content with plain text of the default colour
+    pub fn set_content(&mut self, content: String) {
+        self.cells = parse_plain(&content, self.color);
+        self.size = Self::get_content_size(&content);
+        self.content = content;
+    }
+
     pub fn get_content_size(content: &str) -> Size {
         let mut size = Size::new(0, 0);
     
@@ -93,9 +216,51 @@ impl Sprite {
     pub fn get_sprite_size(sprite: &Self) -> Size {
         Self::get_content_size(sprite.content.as_str())
     }
+
+    /// Flips the cells horizontally, so that e.g. "/#‾" becomes "‾#\".
+    /// Rows are padded with transparent cells to the sprite width first.
+    pub fn get_mirrored_cells(&self) -> Vec<Vec<Cell>> {
+        let width = self.size.width.max(0) as usize;
+        let padding = Cell::new(' ', self.color);
+
+        self.cells
+            .iter()
+            .map(|row| {
+                std::iter::repeat_n(padding, width.saturating_sub(row.len()))
+                    .chain(row.iter().rev().map(|cell| Cell {
+                        glyph: mirror_glyph(cell.glyph),
+                        ..*cell
+                    }))
+                    .collect()
+            })
+            .collect()
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Glyphs that turn into each other when flipped horizontally
+const MIRRORED_GLYPHS: [(char, char); 27] = [
+    ('/', '\\'), ('(', ')'), ('[', ']'), ('{', '}'), ('<', '>'),
+    ('◢', '◣'), ('◥', '◤'), ('▛', '▜'), ('▙', '▟'), ('▌', '▐'),
+    ('▖', '▗'), ('▘', '▝'), ('▞', '▚'), ('◀', '▶'), ('◄', '►'),
+    ('╔', '╗'), ('╚', '╝'), ('╠', '╣'), ('┌', '┐'), ('└', '┘'),
+    ('├', '┤'), ('╭', '╮'), ('╰', '╯'), ('←', '→'), ('«', '»'),
+    ('‹', '›'), ('⌐', '¬'),
+];
+
+pub fn mirror_glyph(glyph: char) -> char {
+    for (left, right) in MIRRORED_GLYPHS {
+        if glyph == left {
+            return right;
+        }
+        if glyph == right {
+            return left;
+        }
+    }
+
+    glyph
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
@@ -117,7 +282,11 @@ impl Position {
 
 use std::ops;
 
-use super::ascii_art::{WORLD_HEIGHT, WORLD_MIN_WIDTH};
+use super::ascii_art::{WORLD_HEIGHT, WORLD_MIN_WIDTH, BACKGROUND_COLOR};
+use super::physics::Body;
+use super::state_machine::StateError;
+use super::animation::Animator;
+use super::markup::{Palette, MarkupError, parse_plain, parse_markup};
 impl ops::Add<Position> for Position {
     type Output = Position;
 
@@ -167,23 +336,73 @@ impl PartialEq for Size {
     fn eq(&self, other: &Self) -> bool {
         self.width == other.width && self.height == other.height
     }
-
-    fn ne(&self, other: &Self) -> bool {
-        self.width != other.width || self.height != other.height
-    }
 }
 
 
 /// Graphical Object
+/// Layers are drawn from the first to the last, so later layers are never occluded by earlier ones
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    Background,
+    /// Floor, platforms, spikes, signs
+    World,
+    /// Hero, enemies, pickups
+    Entities,
+    Effects,
+    /// Console and other interface
+    Hud,
+    /// Drawn over everything, e.g. screen transitions
+    Overlay,
+}
+
+
 pub struct Figure {
     pub sprites: Vec<Sprite>,
     pub position: Position,
     pub visible: bool,
+
+    /// Sprites are drawn facing right. If false, the whole figure is mirrored.
+    pub facing_right: bool,
+
+    pub layer: Layer,
+    /// Figures with a greater z are drawn over other figures in the same layer
+    pub z: i32,
+}
+
+impl Default for Figure {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Figure {
     pub fn new() -> Self {
-        Self { sprites: vec![], position: Position::origin(), visible: true }
+        Self {
+            sprites: vec![],
+            position: Position::origin(),
+            visible: true,
+            facing_right: true,
+            layer: Layer::Entities,
+            z: 0,
+        }
+    }
+
+    pub fn get_width(&self) -> i32 {
+        self.sprites
+            .iter()
+            .map(|sprite| sprite.offset.x + sprite.size.width)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns: absolute position of the sprite, taking the facing into account
+    pub fn get_sprite_position(&self, sprite: &Sprite) -> Position {
+        if self.facing_right {
+            return self.position + sprite.offset;
+        }
+
+        let mirrored_x = self.get_width() - sprite.offset.x - sprite.size.width;
+        self.position + Position::new(mirrored_x, sprite.offset.y)
     }
 }
 
@@ -192,17 +411,67 @@ pub trait Entity {
     fn get_figure(&self) -> &Figure;
     fn get_figure_mut(&mut self) -> &mut Figure;
     fn animate(&mut self) { }
-    fn set_state(&mut self, state_id: StateID) { }
-    fn get_state(&mut self) -> StateID { 0 }
+    fn set_state(&mut self, state_id: StateID) -> Result<(), StateError> { Err(StateError::NoStates) }
+    /// Enters a state without checking transitions, e.g. when loading a save
+    fn start_state(&mut self, state_id: StateID) -> Result<(), StateError> { Err(StateError::NoStates) }
+    fn get_state(&self) -> StateID { 0 }
     fn get_size(&self) -> Size;
     fn get_damage(&self) -> i32 { 0 }
     fn is_defeatable(&self) -> bool { false }
+    fn is_solid(&self) -> bool { true }
+    /// Progression flags unlocked when the hero picks this entity up
+    fn get_pickup_flags(&self) -> u32 { 0 }
+    fn get_body(&self) -> Option<&Body> { None }
+    fn get_body_mut(&mut self) -> Option<&mut Body> { None }
+    fn get_animator(&self) -> Option<&Animator> { None }
+    fn get_animator_mut(&mut self) -> Option<&mut Animator> { None }
+}
+
+
+/// Decoration drawn behind all figures. Does not collide with anything.
+pub struct BackgroundLayer {
+    pub sprite: Sprite,
+    /// Position on the screen when the view is at the origin
+    pub position: Position,
+    /// How fast the layer moves relative to the view: 0 - not at all, 1 - same as the world
+    pub scroll_factor: f32,
+    /// Repeat the sprite horizontally across the screen
+    pub tiled: bool,
+    /// Colours are blended with BACKGROUND_COLOR, 0 - invisible, 1 - original colours
+    pub brightness: f32,
+}
+
+impl BackgroundLayer {
+    pub fn new(sprite: Sprite, position: Position, scroll_factor: f32) -> Self {
+        Self {
+            sprite,
+            position,
+            scroll_factor,
+            tiled: true,
+            brightness: 0.4,
+        }
+    }
+
+    /// Returns: position of the (first) sprite on the screen
+    pub fn get_screen_position(&self, view_position: Position) -> Position {
+        let shift_x = (view_position.x as f32 * self.scroll_factor).round() as i32;
+        let shift_y = (view_position.y as f32 * self.scroll_factor).round() as i32;
+        let mut pos = self.position - Position::new(shift_x, shift_y);
+
+        if self.tiled && self.sprite.size.width > 0 {
+            pos.x = pos.x.rem_euclid(self.sprite.size.width) - self.sprite.size.width;
+        }
+
+        pos
+    }
 }
 
 
 pub struct Room {
     pub entities: Vec<EntityID>,
     pub size: Size,
+    /// Drawn from the first (most distant) to the last
+    pub backgrounds: Vec<BackgroundLayer>,
 }
 
 impl Room {
@@ -210,6 +479,7 @@ impl Room {
         Self {
             entities: vec![],
             size,
+            backgrounds: vec![],
         }
     }
 }
@@ -237,8 +507,9 @@ fn is_point_in_rect(point: &Position, rect_pos: &Position, rect_size: &Size) ->
 pub fn collides(fig1: &Figure, fig2: &Figure) -> bool {
     for sprite1 in &fig1.sprites {
         if !sprite1.active { continue; }
+        if sprite1.size.width == 0 || sprite1.size.height == 0 { continue; }
 
-        let sprite1_pos = fig1.position + sprite1.offset;
+        let sprite1_pos = fig1.get_sprite_position(sprite1);
         let x1 = sprite1_pos.x;
         let y1 = sprite1_pos.y;
         let w1 = sprite1.size.width;
@@ -246,8 +517,9 @@ pub fn collides(fig1: &Figure, fig2: &Figure) -> bool {
 
         for sprite2 in &fig2.sprites {
             if !sprite2.active { continue; }
+            if sprite2.size.width == 0 || sprite2.size.height == 0 { continue; }
 
-            let sprite2_pos = fig2.position + sprite2.offset;
+            let sprite2_pos = fig2.get_sprite_position(sprite2);
             let x2 = sprite2_pos.x;
             let y2 = sprite2_pos.y;
             let w2 = sprite2.size.width;
@@ -262,7 +534,7 @@ pub fn collides(fig1: &Figure, fig2: &Figure) -> bool {
         }
     }
 
-    return false;
+    false
 }
 
 // pub fn touches(fig1: &Figure, fig2: &Figure) -> bool {
@@ -294,4 +566,80 @@ pub fn collides(fig1: &Figure, fig2: &Figure) -> bool {
 //     }
 
 //     return false;
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirroring_twice_restores_sprites() {
+        for (left, right) in MIRRORED_GLYPHS {
+            assert_eq!(mirror_glyph(mirror_glyph(left)), left);
+            assert_eq!(mirror_glyph(right), left);
+        }
+
+        let sprite = Sprite::from_content("/#‾\n(<[", Color::white());
+        let mirrored = Sprite { cells: sprite.get_mirrored_cells(), ..Sprite::from_content("", Color::white()) };
+        let glyphs = |cells: &Vec<Vec<Cell>>| -> Vec<String> {
+            cells.iter().map(|row| row.iter().map(|cell| cell.glyph).collect()).collect()
+        };
+
+        assert_eq!(glyphs(&mirrored.cells), vec!["‾#\\", "]>)"]);
+        assert_eq!(Sprite { size: sprite.size, ..mirrored }.get_mirrored_cells(), sprite.cells);
+    }
+
+    fn below() -> Cell {
+        Cell { glyph: '#', fg: Color::new(100, 100, 100), bg: Some(Color::new(0, 0, 0)), attrs: ATTR_BOLD }
+    }
+
+    #[test]
+    fn drawing_transparent_cells() {
+        let mut cell = below();
+        cell.draw(&Cell::blank(), 1.);
+        assert_eq!(cell, below());
+
+        cell.draw(&Cell::new('@', Color::white()), 0.);
+        assert_eq!(cell, below());
+    }
+
+    #[test]
+    fn drawing_opaque_cells() {
+        let mut cell = below();
+        cell.draw(&Cell::new('@', Color::new(200, 0, 0)), 1.);
+        assert_eq!(cell, Cell { glyph: '@', fg: Color::new(200, 0, 0), bg: Some(Color::new(0, 0, 0)), attrs: 0 });
+
+        // A space with a background keeps the glyph
+        let mut cell = below();
+        cell.draw(&Cell { bg: Some(Color::new(0, 0, 200)), ..Cell::blank() }, 1.);
+        assert_eq!(cell, Cell { bg: Some(Color::new(0, 0, 200)), ..below() });
+    }
+
+    #[test]
+    fn drawing_translucent_cells() {
+        let mut cell = below();
+        cell.draw(&Cell { glyph: '@', fg: Color::new(200, 0, 0), bg: Some(Color::new(0, 200, 0)), attrs: 0 }, 0.5);
+        assert_eq!(cell, Cell { glyph: '@', fg: Color::new(100, 0, 0), bg: Some(Color::new(0, 100, 0)), attrs: 0 });
+
+        // A tint only changes the colours
+        let mut cell = below();
+        cell.draw(&Cell { bg: Some(Color::new(0, 0, 200)), ..Cell::blank() }, 0.5);
+        assert_eq!(cell, Cell { fg: Color::new(50, 50, 150), bg: Some(Color::new(0, 0, 100)), ..below() });
+    }
+
+    #[test]
+    fn mirrored_sprite_positions() {
+        let mut figure = Figure::new();
+        figure.position = Position::new(10, 5);
+        figure.sprites.push(Sprite::from_content("###", Color::white()));
+        figure.sprites.push(Sprite { offset: Position::new(3, 1), ..Sprite::from_content("##", Color::white()) });
+
+        assert_eq!(figure.get_sprite_position(&figure.sprites[0]), Position::new(10, 5));
+        assert_eq!(figure.get_sprite_position(&figure.sprites[1]), Position::new(13, 6));
+
+        // The figure is 5 cells wide, the second sprite moves to its left edge
+        figure.facing_right = false;
+        assert_eq!(figure.get_sprite_position(&figure.sprites[0]), Position::new(12, 5));
+        assert_eq!(figure.get_sprite_position(&figure.sprites[1]), Position::new(10, 6));
+    }
+}
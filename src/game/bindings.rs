@@ -0,0 +1,281 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+use std::fmt;
+
+/// Gameplay actions that can be rebound, in the order of the controls menu.
+/// Menus, spells and pausing use fixed keys, see Game::get_key_action.
+pub const BINDABLE_ACTIONS: [Action; 8] = [
+    Action::MoveLeft,
+    Action::MoveRight,
+    Action::Jump,
+    Action::Crouch,
+    Action::Attack,
+    Action::Dash,
+    Action::StartSpell,
+    Action::ToggleTimer,
+];
+
+/// Keys with fixed meanings: pause and back, confirm and cast, erase a letter
+pub const RESERVED_KEYS: [Key; 3] = [Key::Escape, Key::Enter, Key::Backspace];
+
+const DEFAULT_BINDINGS: [(Key, Action); 12] = [
+    (Key::Letter('a'), Action::MoveLeft),
+    (Key::ArrowLeft, Action::MoveLeft),
+    (Key::Letter('d'), Action::MoveRight),
+    (Key::ArrowRight, Action::MoveRight),
+    (Key::Letter('w'), Action::Jump),
+    (Key::ArrowUp, Action::Jump),
+    (Key::Letter('s'), Action::Crouch),
+    (Key::ArrowDown, Action::Crouch),
+    (Key::Letter('v'), Action::Attack),
+    (Key::Letter('c'), Action::Dash),
+    (Key::Space, Action::StartSpell),
+    (Key::Letter('t'), Action::ToggleTimer),
+];
+
+/// Returns: the name of a bindable action for the tutorial and the controls menu
+pub fn get_action_label(action: Action) -> &'static str {
+    match action {
+        Action::MoveLeft => "Move left",
+        Action::MoveRight => "Move right",
+        Action::Jump => "Jump",
+        Action::Crouch => "Crouch",
+        Action::Attack => "Sword",
+        Action::Dash => "Dash",
+        Action::StartSpell => "Cast a spell",
+        Action::ToggleTimer => "Speedrun timer",
+        _ => "",
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BindingError {
+    /// The key is bound to another action
+    Conflict { key: Key, action: Action },
+    Reserved(Key),
+}
+
+impl fmt::Display for BindingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Conflict { key, action } =>
+                write!(f, "{} is already bound to {}", key.get_label(), get_action_label(*action)),
+            Self::Reserved(key) =>
+                write!(f, "{} cannot be bound", key.get_label()),
+        }
+    }
+}
+
+
+/// Maps physical keys to gameplay actions, an action may have several keys
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyBindings {
+    /// In the order the keys are shown
+    pub bindings: Vec<(Key, Action)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyBindings {
+    /// Returns: WASD and arrows, V for the sword, C to dash, SPACE for spells and T for the timer
+    pub fn new() -> Self {
+        Self { bindings: DEFAULT_BINDINGS.to_vec() }
+    }
+
+    pub fn empty() -> Self {
+        Self { bindings: vec![] }
+    }
+
+    pub fn get_action(&self, key: Key) -> Option<Action> {
+        self.bindings.iter().find(|(bound, _)| *bound == key).map(|(_, action)| *action)
+    }
+
+    pub fn get_keys(&self, action: Action) -> Vec<Key> {
+        self.bindings.iter().filter(|(_, bound)| *bound == action).map(|(key, _)| *key).collect()
+    }
+
+    /// Returns: e.g. "W/Up", or "-" if nothing is bound
+    pub fn get_keys_label(&self, action: Action) -> String {
+        let labels: Vec<String> = self.get_keys(action).iter().map(Key::get_label).collect();
+
+        if labels.is_empty() { "-".into() } else { labels.join("/") }
+    }
+
+    /// Adds a key to the action
+    pub fn bind(&mut self, key: Key, action: Action) -> Result<(), BindingError> {
+        self.check(key, action)?;
+
+        if self.get_action(key).is_none() {
+            self.bindings.push((key, action));
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the first key of the action, e.g. WASD, while the other ones, e.g. arrows, stay
+    pub fn rebind(&mut self, action: Action, key: Key) -> Result<(), BindingError> {
+        self.check(key, action)?;
+
+        if self.get_action(key).is_some() {
+            return Ok(());
+        }
+
+        match self.bindings.iter_mut().find(|(_, bound)| *bound == action) {
+            Some(binding) => binding.0 = key,
+            None => self.bindings.push((key, action)),
+        }
+
+        Ok(())
+    }
+
+    fn check(&self, key: Key, action: Action) -> Result<(), BindingError> {
+        if RESERVED_KEYS.contains(&key) {
+            return Err(BindingError::Reserved(key));
+        }
+
+        match self.get_action(key) {
+            Some(bound) if bound != action => Err(BindingError::Conflict { key, action: bound }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns: the content and the colour keys of the tutorial sign, see TUTORIAL_TEMPLATE.
+    /// The keys are marked with 'k' and the border with 'b', as in SIGN_PALETTE.
+    pub fn make_tutorial(&self) -> (String, String) {
+        let mut lines: Vec<(String, String)> = TUTORIAL_TEMPLATE
+            .lines()
+            .chain(TUTORIAL_EXTRA_LINES.lines())
+            .map(|line| self.fill_template_line(line))
+            .collect();
+
+        let width = lines.iter().map(|(text, _)| text.chars().count()).max().unwrap_or(0);
+
+        for (text, colors) in &mut lines {
+            let padding = width - text.chars().count();
+            *text = format!("║ {}{} ║", text, " ".repeat(padding));
+            *colors = format!("b {}{} b", colors, " ".repeat(padding));
+        }
+
+        let horizontal = "═".repeat(width + 2);
+        let border_colors = "b".repeat(width + 4);
+
+        let mut content = vec![format!("╔{}╗", horizontal)];
+        let mut colors = vec![border_colors.clone()];
+
+        for (text, line_colors) in lines {
+            content.push(text);
+            colors.push(line_colors);
+        }
+
+        content.push(format!("╚{}╝", horizontal));
+        colors.push(border_colors);
+
+        (content.join("\n"), colors.join("\n"))
+    }
+
+    /// Returns: the line with the placeholders replaced and its colour keys
+    fn fill_template_line(&self, line: &str) -> (String, String) {
+        let mut text = String::new();
+        let mut colors = String::new();
+        let mut rest = line;
+
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+
+            text.push_str(&rest[..start]);
+            colors.push_str(&" ".repeat(rest[..start].chars().count()));
+
+            let placeholder = &rest[start + 1..end];
+            let keys = match Action::from_code(placeholder) {
+                Some(action) => self.get_keys_label(action),
+                None => placeholder.to_string(),
+            };
+
+            text.push_str(&keys);
+            colors.push_str(&"k".repeat(keys.chars().count()));
+
+            rest = &rest[end + 1..];
+        }
+
+        text.push_str(rest);
+        colors.push_str(&" ".repeat(rest.chars().count()));
+
+        (text, colors)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_names_round_trip() {
+        let keys = [Key::Letter('q'), Key::Digit('7'), Key::ArrowUp, Key::Space, Key::Escape, Key::Tab];
+
+        for key in keys {
+            assert_eq!(Key::from_name(&key.get_name()), Some(key));
+        }
+
+        assert_eq!(Key::from_name("KeyA"), Some(Key::Letter('a')));
+        assert_eq!(Key::from_name("Keya"), None);
+        assert_eq!(Key::from_name("F13"), None);
+    }
+
+    #[test]
+    fn rebinding_detects_conflicts() {
+        let mut bindings = KeyBindings::new();
+
+        assert_eq!(
+            bindings.rebind(Action::Jump, Key::Letter('c')),
+            Err(BindingError::Conflict { key: Key::Letter('c'), action: Action::Dash })
+        );
+        assert_eq!(bindings.rebind(Action::Jump, Key::Enter), Err(BindingError::Reserved(Key::Enter)));
+
+        bindings.rebind(Action::Jump, Key::Letter('k')).unwrap();
+        assert_eq!(bindings.get_keys(Action::Jump), vec![Key::Letter('k'), Key::ArrowUp]);
+        assert_eq!(bindings.get_action(Key::Letter('w')), None);
+
+        // Binding a key of the same action again changes nothing
+        bindings.rebind(Action::Jump, Key::ArrowUp).unwrap();
+        assert_eq!(bindings.get_keys(Action::Jump), vec![Key::Letter('k'), Key::ArrowUp]);
+    }
+
+    #[test]
+    fn tutorial_shows_the_bindings() {
+        let mut bindings = KeyBindings::new();
+        bindings.rebind(Action::Attack, Key::Letter('j')).unwrap();
+
+        let (content, colors) = bindings.make_tutorial();
+        let attack_line = content.lines().find(|line| line.contains("sword")).unwrap();
+        assert!(attack_line.contains("sword: J "));
+
+        for (line, line_colors) in content.lines().zip(colors.lines()) {
+            assert_eq!(line.chars().count(), line_colors.chars().count());
+        }
+    }
+}
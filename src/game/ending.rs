@@ -0,0 +1,208 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+/// Same as the update interval of the runners
+pub const TICK_MILLIS: u32 = 30;
+
+/// The ending starts when the right side of the hero gets this close to the end sign
+pub const VICTORY_DISTANCE: i32 = 20;
+
+const PAN_SPEED: i32 = 1;
+const CRAWL_TICKS_PER_ROW: u32 = 3;
+pub const ENDING_FADE_TICKS: u32 = 30;
+
+
+/// Counters of a play-through, shown after the ending
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    /// Ticks of gameplay, without menus and the ending
+    pub play_ticks: u32,
+    pub deaths: u32,
+    /// Only non-empty spells count
+    pub spells_cast: u32,
+    /// Including the boss
+    pub enemies_defeated: u32,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            play_ticks: 0,
+            deaths: 0,
+            spells_cast: 0,
+            enemies_defeated: 0,
+        }
+    }
+
+    pub fn get_summary(&self) -> Vec<String> {
+        vec![
+            format!("Time              {:>5}", format_time(self.play_ticks, false)),
+            format!("Deaths            {:>5}", self.deaths),
+            format!("Spells cast       {:>5}", self.spells_cast),
+            format!("Enemies defeated  {:>5}", self.enemies_defeated),
+        ]
+    }
+}
+
+/// Returns: "M:SS" or "H:MM:SS", measured in simulation ticks
+/// Arguments:
+/// has_hundredths - appends ".hh", e.g. for the speedrun timer
+pub fn format_time(ticks: u32, has_hundredths: bool) -> String {
+    let hundredths = ticks as u64 * TICK_MILLIS as u64 / 10;
+    let seconds = hundredths / 100;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    let mut text = if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    };
+
+    if has_hundredths {
+        text += &format!(".{:02}", hundredths % 100);
+    }
+
+    text
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EndingPhase {
+    /// The camera moves to the end sign
+    Pan,
+    /// ENDING_CRAWL scrolls up from the bottom
+    Crawl,
+    FadeOut,
+    Finished,
+}
+
+/// The scripted sequence after the hero reaches the end sign, played by Scene::Ending
+pub struct Ending {
+    pub phase: EndingPhase,
+    /// Ticks since the start of the phase
+    pub ticks: u32,
+    /// Where the camera stops
+    pub view_target: Position,
+}
+
+impl Ending {
+    pub fn new(view_target: Position) -> Self {
+        Self {
+            phase: EndingPhase::Pan,
+            ticks: 0,
+            view_target,
+        }
+    }
+
+    /// Advances the sequence by a tick.
+    /// Arguments:
+    /// view_position - moved towards view_target during the pan
+    /// screen_height - the crawl ends when the text has left the screen
+    /// Returns: the phase that has just started, if any
+    pub fn update(&mut self, view_position: &mut Position, screen_height: i32) -> Option<EndingPhase> {
+        self.ticks += 1;
+
+        let next = match self.phase {
+            EndingPhase::Pan => {
+                let step = (self.view_target.x - view_position.x).clamp(-PAN_SPEED, PAN_SPEED);
+                view_position.x += step;
+
+                if step == 0 { Some(EndingPhase::Crawl) } else { None }
+            }
+
+            EndingPhase::Crawl => {
+                let rows = screen_height as u32 + Self::get_crawl_lines().len() as u32;
+
+                if self.ticks >= rows * CRAWL_TICKS_PER_ROW { Some(EndingPhase::FadeOut) } else { None }
+            }
+
+            EndingPhase::FadeOut => {
+                if self.ticks >= ENDING_FADE_TICKS { Some(EndingPhase::Finished) } else { None }
+            }
+
+            EndingPhase::Finished => None,
+        };
+
+        if let Some(phase) = next {
+            self.phase = phase;
+            self.ticks = 0;
+        }
+
+        next
+    }
+
+    fn get_crawl_lines() -> Vec<&'static str> {
+        ENDING_CRAWL.lines().collect()
+    }
+
+    /// Draws the crawl over the world, the lines start below the screen and go up
+    pub fn draw(&self, frame: &mut [Vec<Cell>], size: Size) {
+        if self.phase != EndingPhase::Crawl {
+            return;
+        }
+
+        let top = size.height - (self.ticks / CRAWL_TICKS_PER_ROW) as i32;
+
+        for (index, line) in Self::get_crawl_lines().into_iter().enumerate() {
+            let width = line.chars().count() as i32;
+            let position = Position::new((size.width - width) / 2, top + index as i32);
+            put_text(frame, position, line, Color::cyan(), ATTR_BOLD);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_format() {
+        assert_eq!(format_time(0, false), "0:00");
+        // 30 ms per tick
+        assert_eq!(format_time(2000, false), "1:00");
+        assert_eq!(format_time(2034, false), "1:01");
+        assert_eq!(format_time(120_000, false), "1:00:00");
+
+        assert_eq!(format_time(0, true), "0:00.00");
+        assert_eq!(format_time(2034, true), "1:01.02");
+        assert_eq!(format_time(120_001, true), "1:00:00.03");
+    }
+
+    #[test]
+    fn phases_follow_each_other() {
+        let mut view_position = Position::new(10, 0);
+        let mut ending = Ending::new(Position::new(13, 0));
+        let mut started = vec![];
+
+        for _ in 0..1000 {
+            if let Some(phase) = ending.update(&mut view_position, 20) {
+                started.push(phase);
+            }
+        }
+
+        assert_eq!(view_position, Position::new(13, 0));
+        assert_eq!(started, vec![EndingPhase::Crawl, EndingPhase::FadeOut, EndingPhase::Finished]);
+    }
+}
@@ -16,32 +16,27 @@
 
 use super::*;
 
-use std::collections::HashMap;
-
 pub struct StaticEntity {
     pub figure: Figure,
 }
 
 impl StaticEntity {
     pub fn new(content: String, color: Color, position: Position) -> Self {
-        let size = Sprite::get_content_size(&content);
+        Self::from_sprite(Sprite::from_content(&content, color), position)
+    }
 
+    pub fn from_sprite(sprite: Sprite, position: Position) -> Self {
         Self {
             figure: Figure {
                 position,
-                sprites: vec![
-                    Sprite {
-                        color,
-                        content,
-                        offset: Position::origin(),
-                        size,
-                        active: true
-                    }
-                ],
-                visible: true
+                sprites: vec![sprite],
+                visible: true,
+                facing_right: true,
+                layer: Layer::World,
+                z: 0,
             }
         }
-    }    
+    }
 }
 
 
@@ -60,32 +55,19 @@ impl Entity for StaticEntity {
 }
 
 
-struct AnimationPoint {
-    pub duration: u32,
-    pub enabled_sprites: Vec<SpriteID>
-}
-
-impl AnimationPoint {
-    pub fn new(duration: u32, sprite_ids: Vec<SpriteID>) -> Self {
-        Self { duration, enabled_sprites: sprite_ids }
-    }
-}
-
-pub const ANIMATE_FOREVER: u32 = 0;
-
-
 pub struct AnimatableEntity {
     pub figure: Figure,
 
     /// E.g. running/crouching/flying
-    current_state: StateID,
+    pub states: StateMachine<AnimatableEntity>,
     
     /// E.g. Running 1 / Running 2 / Running 3
-    current_animation_point: usize,
+    pub animator: Animator,
 
-    animations: HashMap<StateID, Vec<AnimationPoint>>,
+    pub body: Option<Body>,
 
-    animation_call_counter: u32,
+    pub damage: i32,
+    pub defeatable: bool,
 }
 
 impl AnimatableEntity {
@@ -95,92 +77,72 @@ impl AnimatableEntity {
                 sprites: vec![],
                 position,
                 visible: true,
+                facing_right: true,
+                layer: Layer::Entities,
+                z: 0,
             },
-            current_state: 0,
-            current_animation_point: 0,
-            animations: HashMap::new(),
-            animation_call_counter: 0,
+            states: StateMachine::new(),
+            animator: Animator::new(),
+            body: None,
+            damage: 0,
+            defeatable: false,
         } 
     }
 
-    pub fn add_sprite(&mut self, mut sprite: Sprite) -> SpriteID {
+    /// Arguments:
+    /// name - used to refer to the sprite in animation data
+    pub fn add_sprite(&mut self, name: &str, mut sprite: Sprite) -> SpriteID {
         sprite.active = false;
         self.figure.sprites.push(sprite);
-        self.figure.sprites.len() - 1
+        let id = self.figure.sprites.len() - 1;
+        self.animator.name_sprite(name, id);
+        id
     }
 
-    /// Arguments:
-    /// duration - number of animate() calls that the given point should be retained.
-    /// If duration is ANIMATE_FOREVER (0), indicates that it is the final animation point.
-    pub fn add_animation_point(&mut self, state_id: StateID, sprite_ids: Vec<SpriteID>, duration: u32) {
-        if !self.animations.contains_key(&state_id) {
-            self.animations.insert(state_id, vec![]);
-        }
-        self.animations
-            .get_mut(&state_id)
-            .unwrap()
-            .push(
-                AnimationPoint::new(duration, sprite_ids)
-            );
-    }
-
-    pub fn get_state(&mut self) -> StateID {
-        self.current_state
-    }
-
-    fn set_current_animation_point_active(&mut self, active: bool) {
-        let animation_point =
-            &self.animations
-            .get_mut(&self.current_state)
-            .unwrap()
-            [self.current_animation_point];
-        
-        for sprite_id in &animation_point.enabled_sprites {
-            self.figure.sprites[*sprite_id].active = active;
-        }
+    /// Enters the first state without checking transitions
+    pub fn start(&mut self, state_id: StateID) -> Result<(), StateError> {
+        self.states.start(state_id)?;
+        self.play_state_clip();
+        Ok(())
     }
 
-    fn get_current_animation_point_duration(&self) -> u32 {
-        self.animations
-            [&self.current_state]
-            [self.current_animation_point]
-            .duration
+    pub fn get_state(&self) -> StateID {
+        self.states.get_current()
     }
 
-    fn next_animation_point(&mut self) {
-        self.current_animation_point += 1;
-        if self.current_animation_point >= self.animations[&self.current_state].len() {
-            self.current_animation_point = 0;
-        }
-    }
+    pub fn set_state(&mut self, new_state: StateID) -> Result<(), StateError> {
+        let hooks = self.states.check_transition(new_state, self)?;
 
-    pub fn animate(&mut self, new_state: Option<StateID>) {
-        if self.animations.len() == 0 || self.figure.sprites.len() == 0 {
-            return;
-        }
+        let (on_exit, on_enter) = match hooks {
+            Some(hooks) => hooks,
+            None => return Ok(()), // Already there
+        };
 
-        if (new_state == None && self.animation_call_counter > 0) {
-            self.animation_call_counter -= 1;
-            return;
+        if let Some(hook) = on_exit {
+            hook(self);
         }
 
-        let duration = self.get_current_animation_point_duration();
-        if new_state == None && duration == ANIMATE_FOREVER {
-            return; // Stop animating
-        }
-        
-        self.set_current_animation_point_active(false);
-
-        if new_state == None {
-            self.next_animation_point();
-        } else if new_state.unwrap() != self.current_state {
-            self.current_state = new_state.unwrap();
-            self.current_animation_point = 0;
+        self.states.finish_transition(new_state);
+        self.play_state_clip();
+
+        if let Some(hook) = on_enter {
+            hook(self);
         }
 
-        self.animation_call_counter = self.get_current_animation_point_duration();
+        Ok(())
+    }
+
+    /// States without a clip of the same name show nothing
+    fn play_state_clip(&mut self) {
+        let name = self.states.get_current_state().name;
 
-        self.set_current_animation_point_active(true);
+        if self.animator.play(name, &mut self.figure.sprites).is_err() {
+            self.animator.stop(&mut self.figure.sprites);
+        }
+    }
+
+    pub fn animate(&mut self) {
+        self.animator.tick(&mut self.figure.sprites);
     }
 }
 
@@ -194,23 +156,52 @@ impl Entity for AnimatableEntity {
         &mut self.figure
     }
 
-    fn set_state(&mut self, state_id: StateID) {
-        self.animate(Some(state_id));
+    fn set_state(&mut self, state_id: StateID) -> Result<(), StateError> {
+        self.set_state(state_id)
     }
 
-    fn get_state(&mut self) -> StateID {
-        self.current_state
+    fn start_state(&mut self, state_id: StateID) -> Result<(), StateError> {
+        self.start(state_id)
+    }
+
+    fn get_state(&self) -> StateID {
+        self.states.get_current()
     }
 
     fn animate(&mut self) {
-        self.animate(None);
+        self.animate();
+    }
+
+    fn get_animator(&self) -> Option<&Animator> {
+        Some(&self.animator)
+    }
+
+    fn get_animator_mut(&mut self) -> Option<&mut Animator> {
+        Some(&mut self.animator)
+    }
+
+    fn get_body(&self) -> Option<&Body> {
+        self.body.as_ref()
     }
 
-    /// HACK We get only the size of the first (0th) sprite in current state ;)
+    fn get_body_mut(&mut self) -> Option<&mut Body> {
+        self.body.as_mut()
+    }
+
+    /// HACK We get only the size of the first (0th) sprite in current frame ;)
     fn get_size(&self) -> Size {
-        self.figure.sprites[
-            self.animations[&self.current_state][self.current_animation_point].enabled_sprites[0]
-        ].size
+        match self.animator.get_current_sprites().first() {
+            Some(sprite_id) => self.figure.sprites[*sprite_id].size,
+            None => Size::new(0, 0),
+        }
+    }
+
+    fn get_damage(&self) -> i32 {
+        self.damage
+    }
+
+    fn is_defeatable(&self) -> bool {
+        self.defeatable
     }
 }
 
@@ -222,21 +213,16 @@ pub struct HostileStaticEntity {
 
 impl HostileStaticEntity {
     pub fn new(sprite_str: String, position: Position, color: Color) -> Self {
-        let size = Sprite::get_content_size(&sprite_str);
-
         Self {
             figure: Figure {
                 position,
                 sprites: vec![
-                    Sprite {
-                        color,
-                        content: sprite_str,
-                        offset: Position::origin(),
-                        size,
-                        active: true
-                    }
+                    Sprite::from_content(&sprite_str, color)
                 ],
-                visible: true
+                visible: true,
+                facing_right: true,
+                layer: Layer::World,
+                z: 0,
             }
         }
     }
@@ -261,33 +247,31 @@ impl Entity for HostileStaticEntity {
 }
 
 
-pub struct HostileStaticDefeatableEntity {
+/// Not solid, disappears when touched by the hero
+pub struct PickupEntity {
     pub figure: Figure,
+    pub flags: u32,
 }
 
-impl HostileStaticDefeatableEntity {
-    pub fn new(sprite_str: String, position: Position, color: Color) -> Self {
-        let size = Sprite::get_content_size(&sprite_str);
-
+impl PickupEntity {
+    pub fn new(sprite_str: String, position: Position, color: Color, flags: u32) -> Self {
         Self {
             figure: Figure {
                 position,
                 sprites: vec![
-                    Sprite {
-                        color,
-                        content: sprite_str,
-                        offset: Position::origin(),
-                        size,
-                        active: true
-                    }
+                    Sprite::from_content(&sprite_str, color)
                 ],
-                visible: true
-            }
+                visible: true,
+                facing_right: true,
+                layer: Layer::Entities,
+                z: 0,
+            },
+            flags,
         }
     }
 }
 
-impl Entity for HostileStaticDefeatableEntity {
+impl Entity for PickupEntity {
     fn get_figure(&self) -> &Figure {
         &self.figure
     }
@@ -300,11 +284,11 @@ impl Entity for HostileStaticDefeatableEntity {
         self.figure.sprites[0].size
     }
 
-    fn get_damage(&self) -> i32 {
-        1
+    fn is_solid(&self) -> bool {
+        false
     }
 
-    fn is_defeatable(&self) -> bool {
-        true
+    fn get_pickup_flags(&self) -> u32 {
+        self.flags
     }
-}
\ No newline at end of file
+}
@@ -31,7 +31,7 @@ impl SpellConsole {
     }
 
     pub fn backspace(&mut self) {
-        if self.spell.len() > 0 {
+        if !self.spell.is_empty() {
             self.spell.pop();
         }
     }
@@ -52,132 +52,339 @@ impl SpellConsole {
 }
 
 
+pub const FLAG_WALL_JUMP: u32 = 1 << 0;
+pub const FLAG_DASH: u32 = 1 << 1;
+pub const FLAG_LEDGE_GRAB: u32 = 1 << 2;
+
+/// Progression flags, e.g. unlocked abilities.
+/// Levels can gate areas on them.
+pub struct Progression {
+    pub flags: u32,
+}
+
+impl Progression {
+    pub fn new() -> Self {
+        Self { flags: 0 }
+    }
+
+    pub fn has(&self, flags: u32) -> bool {
+        self.flags & flags == flags
+    }
+
+    pub fn unlock(&mut self, flags: u32) {
+        self.flags |= flags;
+    }
+}
+
+
+/// Input collected between two ticks
+pub struct HeroInput {
+    /// -1 for left, 1 for right, 0 for none
+    pub horizontal: i32,
+    pub jump: bool,
+    pub crouch: bool,
+    pub dash: bool,
+    pub attack: bool,
+}
+
+impl HeroInput {
+    pub fn new() -> Self {
+        Self { horizontal: 0, jump: false, crouch: false, dash: false, attack: false }
+    }
+}
+
+
+/// Each hit by something dangerous takes one, the game is over at zero
+pub const HERO_MAX_HEALTH: u32 = 10;
+
 pub struct HeroController {
     pub direction_right: bool,
     pub health: u32,
-    pub jump_potential: i32,
+    pub input: HeroInput,
+    was_jump_held: bool,
+    was_dash_held: bool,
+
+    dash_ticks: u32,
+    dash_cooldown_ticks: u32,
+    wall_jump_lock_ticks: u32,
+    is_wall_sliding: bool,
+
+    /// -1 if hanging on a ledge to the left, 1 if to the right, 0 if not hanging
+    hang_side: i32,
+    climb_ticks: u32,
 }
 
 pub const HERO_STATE_NORMAL: usize = 0;
 pub const HERO_STATE_CROUCHING: usize = 1;
-pub const HERO_STATE_JUMPING_RIGHT: usize = 2;
-pub const HERO_STATE_JUMPING_LEFT: usize = 3;
-pub const HERO_STATE_FALLING: usize = 4;
+pub const HERO_STATE_JUMPING: usize = 2;
+pub const HERO_STATE_FALLING: usize = 3;
+pub const HERO_STATE_WALL_SLIDING: usize = 4;
+pub const HERO_STATE_DASHING: usize = 5;
+pub const HERO_STATE_HANGING: usize = 6;
+pub const HERO_STATE_CLIMBING: usize = 7;
 
 impl HeroController {
     pub fn new() -> Self {
-        Self { health: 10, direction_right: true, jump_potential: 0 }
+        Self {
+            health: HERO_MAX_HEALTH,
+            direction_right: true,
+            input: HeroInput::new(),
+            was_jump_held: false,
+            was_dash_held: false,
+            dash_ticks: 0,
+            dash_cooldown_ticks: 0,
+            wall_jump_lock_ticks: 0,
+            is_wall_sliding: false,
+            hang_side: 0,
+            climb_ticks: 0,
+        }
     }
 
     pub fn new_entity(&self) -> Box<dyn Entity> {
-        let hero_size = Sprite::get_content_size(HERO);
-        let hero_crouching2_size = Sprite::get_content_size(HERO_CROUCHING_2);
+        let hero_sprite = |content, color_keys| {
+            Sprite::from_markup(content, color_keys, HERO_PALETTE, Color::magenta()).unwrap()
+        };
         
         let mut ent = Box::new(
             AnimatableEntity::new(Position::origin())
         );
 
-        let staying = ent.add_sprite(Sprite {
-            color: Color::magenta(),
-            content: HERO.into(),
-            offset: Position::origin(), 
-            size: hero_size,
-            active: true
-        });
+        ent.body = Some(Body::new(Position::origin()));
 
-        let crouching_1 = ent.add_sprite(Sprite {
-            color: Color::magenta(),
-            content: HERO_CROUCHING_1.into(),
-            offset: Position::origin(), 
-            size: hero_size,
-            active: true
-        });
-
-        let crouching_2 = ent.add_sprite(Sprite {
-            color: Color::magenta(),
-            content: HERO_CROUCHING_2.into(),
-            offset: Position::new(0, 1), 
-            size: hero_crouching2_size,
-            active: true
-        });
-
-        let jumping_left = ent.add_sprite(Sprite {
-            color: Color::magenta(),
-            content: HERO_JUMPING_LEFT.into(),
-            offset: Position::origin(), 
-            size: hero_size,
-            active: true
+        ent.add_sprite("hero", hero_sprite(HERO, HERO_COLORS));
+        ent.add_sprite("crouching_1", hero_sprite(HERO_CROUCHING_1, HERO_COLORS));
+        ent.add_sprite("crouching_2", Sprite {
+            offset: Position::new(0, 1),
+            ..hero_sprite(HERO_CROUCHING_2, HERO_CROUCHING_2_COLORS)
         });
+        ent.add_sprite("jumping", hero_sprite(HERO_JUMPING, HERO_COLORS));
+        ent.add_sprite("falling", hero_sprite(HERO_FALL, HERO_COLORS));
+        ent.add_sprite("wall_sliding", hero_sprite(HERO_WALL_SLIDE, HERO_COLORS));
+        ent.add_sprite("dashing", hero_sprite(HERO_DASH, HERO_COLORS));
+        ent.add_sprite("hanging", hero_sprite(HERO_HANGING, HERO_COLORS));
+        ent.add_sprite("climbing", hero_sprite(HERO_CLIMBING, HERO_COLORS));
+
+        let states = &mut ent.states;
+        states.add_state(HERO_STATE_NORMAL, "normal");
+        states.add_state(HERO_STATE_CROUCHING, "crouching");
+        states.add_state(HERO_STATE_JUMPING, "jumping");
+        states.add_state(HERO_STATE_FALLING, "falling");
+        states.add_state(HERO_STATE_WALL_SLIDING, "wall_sliding");
+        states.add_state(HERO_STATE_DASHING, "dashing");
+        states.add_state(HERO_STATE_HANGING, "hanging");
+        states.add_state(HERO_STATE_CLIMBING, "climbing");
+
+        let airborne = [
+            HERO_STATE_JUMPING, HERO_STATE_FALLING, HERO_STATE_WALL_SLIDING, HERO_STATE_DASHING,
+        ];
 
-        let jumping_right = ent.add_sprite(Sprite {
-            color: Color::magenta(),
-            content: HERO_JUMPING_RIGHT.into(),
-            offset: Position::origin(), 
-            size: hero_size,
-            active: true
-        });
+        states.allow(None, HERO_STATE_NORMAL, None);
+        states.allow(Some(HERO_STATE_NORMAL), HERO_STATE_CROUCHING, Some(Self::is_on_ground));
+        states.allow_all(&[HERO_STATE_NORMAL, HERO_STATE_CROUCHING, HERO_STATE_CLIMBING], &airborne);
+        states.allow_all(&airborne, &airborne);
+        states.allow_all(&airborne, &[HERO_STATE_HANGING]);
+        states.allow_all(&[HERO_STATE_HANGING], &[HERO_STATE_CLIMBING, HERO_STATE_FALLING]);
 
-        let falling = ent.add_sprite(Sprite {
-            color: Color::magenta(),
-            content: HERO_FALL.into(),
-            offset: Position::origin(), 
-            size: hero_size,
-            active: true
-        });
+        ent.animator.load(HERO_ANIMATIONS).unwrap();
 
-        ent.add_animation_point(HERO_STATE_NORMAL, vec![staying], ANIMATE_FOREVER);
+        ent.start(HERO_STATE_NORMAL).unwrap();
         
-        ent.add_animation_point(HERO_STATE_CROUCHING, vec![crouching_1], 4);
-        ent.add_animation_point(HERO_STATE_CROUCHING, vec![crouching_2], ANIMATE_FOREVER);
+        ent
+    }
 
-        ent.add_animation_point(HERO_STATE_JUMPING_LEFT, vec![jumping_left], ANIMATE_FOREVER);
-        ent.add_animation_point(HERO_STATE_JUMPING_RIGHT, vec![jumping_right], ANIMATE_FOREVER);
+    fn is_on_ground(ent: &AnimatableEntity) -> bool {
+        ent.body.as_ref().is_some_and(|body| body.on_ground)
+    }
 
-        ent.add_animation_point(HERO_STATE_FALLING, vec![falling], ANIMATE_FOREVER);
+    pub fn reset(&mut self) {
+        self.dash_ticks = 0;
+        self.wall_jump_lock_ticks = 0;
+        self.is_wall_sliding = false;
+        self.hang_side = 0;
+        self.climb_ticks = 0;
+    }
 
-        ent.set_state(HERO_STATE_NORMAL);
-        
-        ent
+    fn get_direction(&self) -> i32 {
+        if self.direction_right {1} else {-1}
     }
 
-    pub fn move_entity(&mut self, _self: &mut Box<dyn Entity>, dir: i32) {
-        self.direction_right = dir > 0;
+    /// Applies the collected input to the hero's body. Must be called before the body is moved.
+    pub fn control_entity(&mut self, ent: &mut Box<dyn Entity>, progression: &Progression) {
+        let jump_pressed = self.input.jump && !self.was_jump_held;
+        self.was_jump_held = self.input.jump;
+
+        let dash_pressed = self.input.dash && !self.was_dash_held;
+        self.was_dash_held = self.input.dash;
 
-        if vec![HERO_STATE_JUMPING_LEFT, HERO_STATE_JUMPING_RIGHT].contains(&_self.get_state()) {
-            if self.direction_right {
-                _self.set_state(HERO_STATE_JUMPING_RIGHT);
+        if self.dash_cooldown_ticks > 0 {
+            self.dash_cooldown_ticks -= 1;
+        }
+
+        if self.wall_jump_lock_ticks > 0 {
+            self.wall_jump_lock_ticks -= 1;
+        }
+
+        if self.input.horizontal != 0 && self.wall_jump_lock_ticks == 0 && self.hang_side == 0 {
+            self.direction_right = self.input.horizontal > 0;
+        }
+
+        let is_crouching = if self.input.crouch && self.hang_side == 0 {
+            ent.set_state(HERO_STATE_CROUCHING).is_ok()
+        } else {
+            ent.get_state() == HERO_STATE_CROUCHING
+        };
+
+        if jump_pressed && is_crouching {
+            ent.set_state(HERO_STATE_NORMAL).unwrap();
+        }
+
+        let direction = self.get_direction();
+        let input = std::mem::replace(&mut self.input, HeroInput::new());
+        let body = ent.get_body_mut().unwrap();
+
+        self.is_wall_sliding = false;
+        body.is_sliding = false;
+
+        if self.climb_ticks > 0 {
+            self.climb_ticks -= 1;
+
+            body.velocity = if self.climb_ticks >= CLIMB_OVER_TICKS {
+                Vector::new(0., -1.)
             } else {
-                _self.set_state(HERO_STATE_JUMPING_LEFT);
+                Vector::new(self.hang_side as f32, 0.)
+            };
+
+            body.integrate();
+
+            if self.climb_ticks == 0 {
+                self.hang_side = 0;
+                body.is_floating = false;
+                body.velocity = Vector::zero();
             }
+
+            return;
         }
-    }
 
-    pub fn jump_entity(&mut self, _self: &mut Box<dyn Entity>) {
-        if _self.get_state() == HERO_STATE_CROUCHING {
-            _self.set_state(HERO_STATE_NORMAL);
-        } else {
-            if self.jump_potential == 0
-            && _self.get_state() != HERO_STATE_FALLING
-            && _self.get_state() != HERO_STATE_JUMPING_LEFT
-            && _self.get_state() != HERO_STATE_JUMPING_RIGHT {
-                self.jump_potential = HERO_JUMPING_HEIGHT;
+        if self.hang_side != 0 {
+            if jump_pressed || input.horizontal == self.hang_side {
+                self.climb_ticks = CLIMB_UP_TICKS + CLIMB_OVER_TICKS;
+            } else if input.crouch || input.horizontal == -self.hang_side {
+                self.hang_side = 0;
+                body.is_floating = false;
             }
 
-            if self.direction_right {
-                _self.set_state(HERO_STATE_JUMPING_RIGHT);
+            body.velocity = Vector::zero();
+            body.integrate();
+            return;
+        }
+
+        if progression.has(FLAG_LEDGE_GRAB)
+        && !body.on_ground
+        && body.ledge_side != 0
+        && body.velocity.y >= 0. {
+            self.hang_side = body.ledge_side;
+            self.dash_ticks = 0;
+            body.is_floating = true;
+            body.velocity = Vector::zero();
+            body.position.y = body.position.y.round();
+            return;
+        }
+
+        if dash_pressed
+        && progression.has(FLAG_DASH)
+        && self.dash_cooldown_ticks == 0 {
+            self.dash_ticks = DASH_TICKS;
+            self.dash_cooldown_ticks = DASH_COOLDOWN_TICKS;
+        }
+
+        if self.dash_ticks > 0 {
+            self.dash_ticks -= 1;
+
+            body.is_floating = true;
+            body.velocity = Vector::new(direction as f32 * DASH_SPEED, 0.);
+            body.integrate();
+
+            if self.dash_ticks == 0 {
+                body.is_floating = false;
+                body.velocity.x = body.velocity.x.clamp(-RUN_SPEED, RUN_SPEED);
+            }
+
+            return;
+        }
+
+        let is_on_wall = progression.has(FLAG_WALL_JUMP) && !body.on_ground && body.wall_side != 0;
+
+        if jump_pressed && is_on_wall {
+            body.wall_jump();
+            self.wall_jump_lock_ticks = WALL_JUMP_LOCK_TICKS;
+            self.direction_right = body.wall_side < 0;
+        } else {
+            if self.wall_jump_lock_ticks > 0 {
+                body.run(0);
             } else {
-                _self.set_state(HERO_STATE_JUMPING_LEFT);
+                body.run(input.horizontal);
             }
+
+            if jump_pressed && !is_crouching {
+                body.buffer_jump();
+            }
+
+            body.try_jump();
         }
+
+        if is_on_wall && input.horizontal == body.wall_side && body.velocity.y > 0. {
+            self.is_wall_sliding = true;
+            body.is_sliding = true;
+        }
+
+        if !input.jump {
+            body.cut_jump();
+        }
+
+        body.integrate();
     }
 
-    pub fn crouch_entity(&self, _self: &mut Box<dyn Entity>) {
-        _self.set_state(HERO_STATE_CROUCHING);
+    /// Chooses the hero's state. Must be called after the body has been moved.
+    pub fn animate_entity(&mut self, ent: &mut Box<dyn Entity>) {
+        let body = ent.get_body().unwrap();
+        let on_ground = body.on_ground;
+        let rising = body.velocity.y < 0.;
+        let wall_side = body.wall_side;
+
+        let mut facing_right = self.direction_right;
+
+        let state = if self.climb_ticks > 0 {
+            facing_right = self.hang_side > 0;
+            HERO_STATE_CLIMBING
+        } else if self.hang_side != 0 {
+            facing_right = self.hang_side > 0;
+            HERO_STATE_HANGING
+        } else if self.dash_ticks > 0 {
+            HERO_STATE_DASHING
+        } else if self.is_wall_sliding && !on_ground {
+            facing_right = wall_side > 0;
+            HERO_STATE_WALL_SLIDING
+        } else if on_ground {
+            if ent.get_state() == HERO_STATE_CROUCHING { HERO_STATE_CROUCHING } else { HERO_STATE_NORMAL }
+        } else if rising {
+            HERO_STATE_JUMPING
+        } else {
+            HERO_STATE_FALLING
+        };
+
+        ent.get_figure_mut().facing_right = facing_right;
+
+        // If the transition is not allowed, the hero keeps the current state;
+        // the state is derived from the body again on the next tick
+        if let Err(error) = ent.set_state(state) {
+            debug_assert!(error.is_refusal(), "hero: {}", error);
+        }
     }
 }
 
 
-pub const SWORD_STATE_NORMAL: usize = 0;
+pub const SWORD_STATE_HIDDEN: usize = 0;
+pub const SWORD_STATE_SWINGING: usize = 1;
 
 pub struct SwordController {
 
@@ -192,30 +399,190 @@ impl SwordController {
 
         ent.figure.visible = false;
 
-        let common = Sprite {
-            color: Color::yellow(),
-            content: "".into(),
-            offset: Position::origin(),
+        let sword_sprite = |content| Sprite {
             size,
-            active: false
+            active: false,
+            ..Sprite::from_content(content, Color::yellow())
         };
 
-        let mut sprite_ids = vec![
-            ent.add_sprite(Sprite { content: SWORD_1.into(), ..common }),
-            ent.add_sprite(Sprite { content: SWORD_2.into(), ..common }),
-            ent.add_sprite(Sprite { content: SWORD_3.into(), ..common }),
-            ent.add_sprite(Sprite { content: SWORD_4.into(), ..common }),
-            ent.add_sprite(Sprite { content: SWORD_5.into(), ..common }),
-        ];
+        ent.add_sprite("sword_1", sword_sprite(SWORD_1));
+        ent.add_sprite("sword_2", sword_sprite(SWORD_2));
+        ent.add_sprite("sword_3", sword_sprite(SWORD_3));
+        ent.add_sprite("sword_4", sword_sprite(SWORD_4));
+        ent.add_sprite("sword_5", sword_sprite(SWORD_5));
 
-        for id in &sprite_ids {
-            ent.add_animation_point(SWORD_STATE_NORMAL, vec![*id], 2);
-        }
+        ent.states.add_state(SWORD_STATE_HIDDEN, "hidden");
+        ent.states.add_state(SWORD_STATE_SWINGING, "swinging");
+        ent.states.allow(Some(SWORD_STATE_HIDDEN), SWORD_STATE_SWINGING, None);
+        ent.states.allow(Some(SWORD_STATE_SWINGING), SWORD_STATE_HIDDEN, Some(Self::has_finished_swinging));
+
+        let swinging = ent.states.get_state_mut(SWORD_STATE_SWINGING).unwrap();
+        swinging.on_enter = Some(show);
+        swinging.on_exit = Some(hide);
+
+        ent.animator.load(SWORD_ANIMATIONS).unwrap();
 
-        ent.set_state(SWORD_STATE_NORMAL);
+        ent.start(SWORD_STATE_HIDDEN).unwrap();
 
         ent
     }
+
+    fn has_finished_swinging(ent: &AnimatableEntity) -> bool {
+        ent.animator.is_finished()
+    }
 }
 
 
+pub const ENEMY_STATE_ALIVE: usize = 0;
+pub const ENEMY_STATE_DEFEATED: usize = 1;
+
+pub struct EnemyController {
+
+}
+
+impl EnemyController {
+    pub fn new_entity(position: Position) -> Box<dyn Entity> {
+        let mut ent = Box::new(AnimatableEntity::new(position));
+
+        ent.damage = 1;
+        ent.defeatable = true;
+
+        ent.add_sprite("enemy",
+            Sprite::from_markup(ENEMY, ENEMY_COLORS, ENEMY_PALETTE, Color::red()).unwrap()
+        );
+
+        ent.states.add_state(ENEMY_STATE_ALIVE, "alive");
+        ent.states.add_state(ENEMY_STATE_DEFEATED, "defeated");
+        ent.states.allow(Some(ENEMY_STATE_ALIVE), ENEMY_STATE_DEFEATED, None);
+        ent.states.get_state_mut(ENEMY_STATE_DEFEATED).unwrap().on_enter = Some(hide);
+
+        ent.animator.load(ENEMY_ANIMATIONS).unwrap();
+
+        ent.start(ENEMY_STATE_ALIVE).unwrap();
+
+        ent
+    }
+}
+
+
+pub const BOSS_STATE_ACTIVE: usize = 0;
+pub const BOSS_STATE_DEFEATED: usize = 1;
+
+pub struct BossController {
+
+}
+
+impl BossController {
+    pub fn new_entity(position: Position) -> Box<dyn Entity> {
+        let mut ent = Box::new(AnimatableEntity::new(position));
+
+        ent.damage = 1;
+
+        ent.add_sprite("boss",
+            Sprite::from_markup(BOSS, BOSS_COLORS, BOSS_PALETTE, Color::blue()).unwrap()
+        );
+
+        ent.states.add_state(BOSS_STATE_ACTIVE, "active");
+        ent.states.add_state(BOSS_STATE_DEFEATED, "defeated");
+        ent.states.allow(Some(BOSS_STATE_ACTIVE), BOSS_STATE_DEFEATED, None);
+        ent.states.get_state_mut(BOSS_STATE_DEFEATED).unwrap().on_enter = Some(hide);
+
+        ent.animator.load(BOSS_ANIMATIONS).unwrap();
+
+        ent.start(BOSS_STATE_ACTIVE).unwrap();
+
+        ent
+    }
+}
+
+
+fn show(ent: &mut AnimatableEntity) {
+    ent.figure.visible = true;
+}
+
+fn hide(ent: &mut AnimatableEntity) {
+    ent.figure.visible = false;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A hero in the air at (10, 10), as the collision step leaves it
+    fn airborne_hero() -> (HeroController, Box<dyn Entity>) {
+        let controller = HeroController::new();
+        let mut hero = controller.new_entity();
+        hero.get_body_mut().unwrap().reset(Position::new(10, 10));
+        (controller, hero)
+    }
+
+    fn with_flags(flags: u32) -> Progression {
+        let mut progression = Progression::new();
+        progression.unlock(flags);
+        progression
+    }
+
+    #[test]
+    fn dash_needs_the_pickup() {
+        for (flags, expected_speed) in [(0, AIR_ACCELERATION), (FLAG_DASH, DASH_SPEED)] {
+            let (mut controller, mut hero) = airborne_hero();
+            controller.input.dash = true;
+            controller.input.horizontal = 1;
+            controller.control_entity(&mut hero, &with_flags(flags));
+
+            let body = hero.get_body().unwrap();
+            assert_eq!(body.velocity.x, expected_speed);
+            assert_eq!(body.is_floating, flags == FLAG_DASH);
+        }
+    }
+
+    #[test]
+    fn wall_jump_needs_the_pickup() {
+        for flags in [0, FLAG_WALL_JUMP] {
+            let (mut controller, mut hero) = airborne_hero();
+            hero.get_body_mut().unwrap().wall_side = 1;
+            controller.input.jump = true;
+            controller.control_entity(&mut hero, &with_flags(flags));
+
+            let body = hero.get_body().unwrap();
+            if flags == FLAG_WALL_JUMP {
+                assert_eq!(body.velocity.x, -WALL_JUMP_VELOCITY_X);
+                assert_eq!(body.velocity.y, -JUMP_VELOCITY + GRAVITY);
+                assert!(!controller.direction_right);
+            } else {
+                assert_eq!(body.velocity.x, 0.);
+                assert_eq!(body.velocity.y, GRAVITY);
+            }
+        }
+    }
+
+    #[test]
+    fn ledge_grab_and_climb_need_the_pickup() {
+        let (mut controller, mut hero) = airborne_hero();
+        hero.get_body_mut().unwrap().ledge_side = 1;
+        controller.control_entity(&mut hero, &Progression::new());
+        assert!(hero.get_body().unwrap().velocity.y > 0.);
+
+        let (mut controller, mut hero) = airborne_hero();
+        let progression = with_flags(FLAG_LEDGE_GRAB);
+        hero.get_body_mut().unwrap().ledge_side = 1;
+        controller.control_entity(&mut hero, &progression);
+
+        let body = hero.get_body().unwrap();
+        assert!(body.is_floating);
+        assert_eq!(body.velocity, Vector::zero());
+
+        // Pressing towards the ledge climbs up and then over it
+        controller.input.horizontal = 1;
+        controller.control_entity(&mut hero, &progression);
+
+        for _ in 0..CLIMB_UP_TICKS + CLIMB_OVER_TICKS {
+            controller.control_entity(&mut hero, &progression);
+        }
+
+        let body = hero.get_body().unwrap();
+        assert_eq!(body.get_cell(), Position::new(10 + CLIMB_OVER_TICKS as i32, 10 - CLIMB_UP_TICKS as i32));
+        assert!(!body.is_floating);
+    }
+}
@@ -0,0 +1,348 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+/// The stick is ignored closer than this to the centre, as worn sticks do not return to zero
+pub const STICK_DEAD_ZONE: f32 = 0.25;
+/// Past the dead zone, a stick axis counts as a direction from this value, so that diagonals work
+const STICK_DIRECTION_THRESHOLD: f32 = 0.5;
+
+
+/// Buttons of the standard mapping of the Gamepad API, named after the Xbox layout
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GamepadButton {
+    A,
+    B,
+    X,
+    Y,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    Home,
+}
+
+impl GamepadButton {
+    /// Arguments:
+    /// index - of Gamepad.buttons in the browser
+    pub fn from_index(index: u32) -> Option<Self> {
+        const BUTTONS: [GamepadButton; 17] = [
+            GamepadButton::A, GamepadButton::B, GamepadButton::X, GamepadButton::Y,
+            GamepadButton::LeftBumper, GamepadButton::RightBumper,
+            GamepadButton::LeftTrigger, GamepadButton::RightTrigger,
+            GamepadButton::Select, GamepadButton::Start,
+            GamepadButton::LeftStick, GamepadButton::RightStick,
+            GamepadButton::DPadUp, GamepadButton::DPadDown, GamepadButton::DPadLeft, GamepadButton::DPadRight,
+            GamepadButton::Home,
+        ];
+
+        BUTTONS.get(index as usize).copied()
+    }
+}
+
+
+/// Axes of the standard mapping, from -1 to 1, negative is left or up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GamepadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+}
+
+impl GamepadAxis {
+    /// Arguments:
+    /// index - of Gamepad.axes in the browser
+    pub fn from_index(index: u32) -> Option<Self> {
+        match index {
+            0 => Some(Self::LeftX),
+            1 => Some(Self::LeftY),
+            2 => Some(Self::RightX),
+            3 => Some(Self::RightY),
+            _ => None,
+        }
+    }
+}
+
+
+/// Turns button and axis events into actions, like the key bindings do for the keyboard:
+///   d-pad or left stick - move and crouch, up and down in menus, choose a key of the virtual keyboard
+///   A - jump, confirm, press the chosen key
+///   B - dash, back, delete a letter
+///   X - sword
+///   Y - open the spell console, cast the spell
+///   Start - pause, resume
+///   Select - speedrun timer
+pub struct Gamepad {
+    /// Buttons held now
+    pressed: Vec<GamepadButton>,
+    /// Buttons pressed since the last get_actions, so that a tap between two updates is not lost
+    new_presses: Vec<GamepadButton>,
+    left_stick: Vector,
+    /// Direction at the last get_actions, menus and the virtual keyboard step once per push
+    last_direction: Position,
+}
+
+impl Default for Gamepad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gamepad {
+    pub fn new() -> Self {
+        Self {
+            pressed: vec![],
+            new_presses: vec![],
+            left_stick: Vector::zero(),
+            last_direction: Position::origin(),
+        }
+    }
+
+    pub fn set_button(&mut self, button: GamepadButton, is_pressed: bool) {
+        let was_pressed = self.pressed.contains(&button);
+
+        if is_pressed && !was_pressed {
+            self.pressed.push(button);
+            self.new_presses.push(button);
+        } else if !is_pressed {
+            self.pressed.retain(|pressed| *pressed != button);
+        }
+    }
+
+    pub fn set_axis(&mut self, axis: GamepadAxis, value: f32) {
+        match axis {
+            GamepadAxis::LeftX => self.left_stick.x = value,
+            GamepadAxis::LeftY => self.left_stick.y = value,
+            // Not used
+            GamepadAxis::RightX | GamepadAxis::RightY => {}
+        }
+    }
+
+    /// Returns: the stick position with the dead zone cut out and the rest stretched back to 0..1,
+    /// so that the response starts smoothly at its edge
+    pub fn get_left_stick(&self) -> Vector {
+        let stick = self.left_stick;
+        let length = (stick.x * stick.x + stick.y * stick.y).sqrt();
+
+        if length <= STICK_DEAD_ZONE {
+            return Vector::zero();
+        }
+
+        let scale = ((length - STICK_DEAD_ZONE) / (1. - STICK_DEAD_ZONE)).min(1.) / length;
+        Vector::new(stick.x * scale, stick.y * scale)
+    }
+
+    /// Returns: -1, 0 or 1 along each axis, from the d-pad or the stick
+    fn get_direction(&self) -> Position {
+        let stick = self.get_left_stick();
+        let axis = |negative, positive, value: f32| {
+            if self.pressed.contains(&negative) || value <= -STICK_DIRECTION_THRESHOLD {
+                -1
+            } else if self.pressed.contains(&positive) || value >= STICK_DIRECTION_THRESHOLD {
+                1
+            } else {
+                0
+            }
+        };
+
+        Position::new(
+            axis(GamepadButton::DPadLeft, GamepadButton::DPadRight, stick.x),
+            axis(GamepadButton::DPadUp, GamepadButton::DPadDown, stick.y),
+        )
+    }
+
+    /// Called once per update, before the tick.
+    /// Returns: the actions to apply in order, held ones are returned on every call while held
+    pub fn get_actions(&mut self, game: &Game) -> Vec<Action> {
+        let presses = std::mem::take(&mut self.new_presses);
+        let is_pressed = |button| presses.contains(&button);
+        let is_held = |button| self.pressed.contains(&button) || presses.contains(&button);
+
+        let direction = self.get_direction();
+        let step = |current: i32, last: i32| if current != last { current } else { 0 };
+        let step = Position::new(step(direction.x, self.last_direction.x), step(direction.y, self.last_direction.y));
+        self.last_direction = direction;
+
+        let mut actions = vec![];
+
+        if game.is_in_menu() {
+            match step.y {
+                -1 => actions.push(Action::MenuUp),
+                1 => actions.push(Action::MenuDown),
+                _ => {}
+            }
+
+            if is_pressed(GamepadButton::A) {
+                actions.push(Action::Confirm);
+            }
+            if is_pressed(GamepadButton::B) || is_pressed(GamepadButton::Start) {
+                actions.push(Action::Back);
+            }
+        } else if game.is_expecting_text() {
+            match step.x {
+                -1 => actions.push(Action::MenuLeft),
+                1 => actions.push(Action::MenuRight),
+                _ => {}
+            }
+            match step.y {
+                -1 => actions.push(Action::MenuUp),
+                1 => actions.push(Action::MenuDown),
+                _ => {}
+            }
+
+            if is_pressed(GamepadButton::A) {
+                actions.push(Action::Confirm);
+            }
+            if is_pressed(GamepadButton::B) {
+                actions.push(Action::Backspace);
+            }
+            if is_pressed(GamepadButton::Y) {
+                actions.push(Action::Enter);
+            }
+            if is_pressed(GamepadButton::Start) {
+                actions.push(Action::Pause);
+            }
+        } else {
+            match direction.x {
+                -1 => actions.push(Action::MoveLeft),
+                1 => actions.push(Action::MoveRight),
+                _ => {}
+            }
+
+            if direction.y == 1 {
+                actions.push(Action::Crouch);
+            }
+            if is_held(GamepadButton::A) {
+                actions.push(Action::Jump);
+            }
+            if is_held(GamepadButton::B) {
+                actions.push(Action::Dash);
+            }
+            if is_held(GamepadButton::X) {
+                actions.push(Action::Attack);
+            }
+            if is_pressed(GamepadButton::Y) {
+                actions.push(Action::StartSpell);
+            }
+            if is_pressed(GamepadButton::Select) {
+                actions.push(Action::ToggleTimer);
+            }
+            if is_pressed(GamepadButton::Start) {
+                actions.push(Action::Pause);
+            }
+        }
+
+        actions
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing_game() -> Game {
+        let mut game = Game::new(Size::new(100, 35));
+        game.process_action(Action::Confirm);
+        game
+    }
+
+    #[test]
+    fn stick_dead_zone() {
+        let game = playing_game();
+        let mut gamepad = Gamepad::new();
+
+        gamepad.set_axis(GamepadAxis::LeftX, 0.2);
+        gamepad.set_axis(GamepadAxis::LeftY, 0.1);
+        assert_eq!(gamepad.get_left_stick(), Vector::zero());
+        assert!(gamepad.get_actions(&game).is_empty());
+
+        gamepad.set_axis(GamepadAxis::LeftX, 1.);
+        gamepad.set_axis(GamepadAxis::LeftY, 0.);
+        assert_eq!(gamepad.get_left_stick(), Vector::new(1., 0.));
+        assert_eq!(gamepad.get_actions(&game), vec![Action::MoveRight]);
+
+        // Held on every update
+        gamepad.set_button(GamepadButton::A, true);
+        assert_eq!(gamepad.get_actions(&game), vec![Action::MoveRight, Action::Jump]);
+        assert_eq!(gamepad.get_actions(&game), vec![Action::MoveRight, Action::Jump]);
+    }
+
+    #[test]
+    fn menu_steps_once_per_push() {
+        let game = Game::new(Size::new(100, 35));
+        let mut gamepad = Gamepad::new();
+
+        gamepad.set_axis(GamepadAxis::LeftY, 0.9);
+        assert_eq!(gamepad.get_actions(&game), vec![Action::MenuDown]);
+        assert!(gamepad.get_actions(&game).is_empty());
+
+        gamepad.set_axis(GamepadAxis::LeftY, 0.);
+        gamepad.get_actions(&game);
+        gamepad.set_button(GamepadButton::DPadDown, true);
+        gamepad.set_button(GamepadButton::A, true);
+        assert_eq!(gamepad.get_actions(&game), vec![Action::MenuDown, Action::Confirm]);
+
+        // A tap between two updates still counts
+        gamepad.set_button(GamepadButton::B, true);
+        gamepad.set_button(GamepadButton::B, false);
+        assert_eq!(gamepad.get_actions(&game), vec![Action::Back]);
+    }
+
+    #[test]
+    fn spell_entry() {
+        fn apply(game: &mut Game, gamepad: &mut Gamepad) {
+            for action in gamepad.get_actions(game) {
+                game.process_action(action);
+            }
+        }
+
+        let mut game = playing_game();
+        let mut gamepad = Gamepad::new();
+
+        gamepad.set_button(GamepadButton::Y, true);
+        apply(&mut game, &mut gamepad);
+        assert!(game.is_expecting_text());
+
+        // Left of 'q' wraps around to the end of the row, one more step is 'p'
+        gamepad.set_button(GamepadButton::DPadLeft, true);
+        apply(&mut game, &mut gamepad);
+        assert_eq!(game.virtual_keyboard.get_selected_key(), VirtualKey::Backspace);
+
+        gamepad.set_button(GamepadButton::DPadLeft, false);
+        apply(&mut game, &mut gamepad);
+        gamepad.set_button(GamepadButton::DPadLeft, true);
+        gamepad.set_button(GamepadButton::A, true);
+        assert_eq!(gamepad.get_actions(&game), vec![Action::MenuLeft, Action::Confirm]);
+        game.process_action(Action::MenuLeft);
+        game.process_action(Action::Confirm);
+        assert_eq!(game.console.get_spell(), "p");
+
+        gamepad.set_button(GamepadButton::Y, false);
+        gamepad.set_button(GamepadButton::Y, true);
+        assert_eq!(gamepad.get_actions(&game), vec![Action::Enter]);
+    }
+}
@@ -0,0 +1,119 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/// A physical key, named like KeyboardEvent.code in browsers, e.g. "KeyA" or "ArrowLeft".
+/// Letters and digits are the keys, not the typed characters, so they ignore the layout and Shift.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// Lowercase 'a' to 'z'
+    Letter(char),
+    /// '0' to '9'
+    Digit(char),
+    ArrowLeft,
+    ArrowRight,
+    ArrowUp,
+    ArrowDown,
+    Space,
+    Enter,
+    Backspace,
+    Escape,
+    Tab,
+}
+
+impl Key {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let single = |rest: &str| {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                (Some(ch), None) => Some(ch),
+                _ => None,
+            }
+        };
+
+        if let Some(letter) = name.strip_prefix("Key").and_then(single) {
+            return letter.is_ascii_uppercase().then(|| Self::Letter(letter.to_ascii_lowercase()));
+        }
+
+        if let Some(digit) = name.strip_prefix("Digit").and_then(single) {
+            return digit.is_ascii_digit().then_some(Self::Digit(digit));
+        }
+
+        match name {
+            "ArrowLeft" => Some(Self::ArrowLeft),
+            "ArrowRight" => Some(Self::ArrowRight),
+            "ArrowUp" => Some(Self::ArrowUp),
+            "ArrowDown" => Some(Self::ArrowDown),
+            "Space" => Some(Self::Space),
+            "Enter" => Some(Self::Enter),
+            "Backspace" => Some(Self::Backspace),
+            "Escape" => Some(Self::Escape),
+            "Tab" => Some(Self::Tab),
+            _ => None,
+        }
+    }
+
+    /// Returns: the name that from_name turns back into the key
+    pub fn get_name(&self) -> String {
+        match self {
+            Self::Letter(letter) => format!("Key{}", letter.to_ascii_uppercase()),
+            Self::Digit(digit) => format!("Digit{}", digit),
+            Self::ArrowLeft => "ArrowLeft".into(),
+            Self::ArrowRight => "ArrowRight".into(),
+            Self::ArrowUp => "ArrowUp".into(),
+            Self::ArrowDown => "ArrowDown".into(),
+            Self::Space => "Space".into(),
+            Self::Enter => "Enter".into(),
+            Self::Backspace => "Backspace".into(),
+            Self::Escape => "Escape".into(),
+            Self::Tab => "Tab".into(),
+        }
+    }
+
+    /// Returns: how the key is shown in the tutorial and the menus, e.g. "A" or "Left"
+    pub fn get_label(&self) -> String {
+        match self {
+            Self::Letter(letter) => letter.to_ascii_uppercase().to_string(),
+            Self::Digit(digit) => digit.to_string(),
+            Self::ArrowLeft => "Left".into(),
+            Self::ArrowRight => "Right".into(),
+            Self::ArrowUp => "Up".into(),
+            Self::ArrowDown => "Down".into(),
+            Self::Space => "SPACE".into(),
+            Self::Enter => "ENTER".into(),
+            Self::Backspace => "BACKSPACE".into(),
+            Self::Escape => "ESC".into(),
+            Self::Tab => "TAB".into(),
+        }
+    }
+
+    /// Returns: the key of a typed character, e.g. in a terminal
+    pub fn from_char(ch: char) -> Option<Self> {
+        match ch.to_ascii_lowercase() {
+            letter @ 'a'..='z' => Some(Self::Letter(letter)),
+            digit @ '0'..='9' => Some(Self::Digit(digit)),
+            ' ' => Some(Self::Space),
+            _ => None,
+        }
+    }
+
+    /// Returns: the letter typed into a spell
+    pub fn get_letter(&self) -> Option<char> {
+        match self {
+            Self::Letter(letter) => Some(*letter),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,130 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+use std::fmt;
+
+/// Maps colour keys to the foreground, background and ATTR_* attributes of cells.
+/// A foreground of None keeps the default colour of the sprite.
+pub type Palette = [(char, Option<Color>, Option<Color>, u32)];
+
+
+#[derive(Debug)]
+pub enum MarkupError {
+    /// Line and column start from 1
+    UnknownKey { line: usize, column: usize, key: char },
+}
+
+impl fmt::Display for MarkupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownKey { line, column, key } =>
+                write!(f, "{}:{}: colour key '{}' is not in the palette", line, column, key),
+        }
+    }
+}
+
+
+pub fn parse_plain(content: &str, color: Color) -> Vec<Vec<Cell>> {
+    content
+        .split('\n')
+        .map(|line| line.chars().map(|glyph| Cell::new(glyph, color)).collect())
+        .collect()
+}
+
+/// Arguments:
+/// content - the characters of the sprite
+/// color_keys - a layer of the same shape as content, each character is a key in the palette.
+/// Spaces and missing characters keep the default colour.
+pub fn parse_markup(content: &str, color_keys: &str, palette: &Palette, color: Color)
+    -> Result<Vec<Vec<Cell>>, MarkupError>
+{
+    let mut cells = parse_plain(content, color);
+    let key_lines: Vec<&str> = color_keys.split('\n').collect();
+
+    for (row, line) in cells.iter_mut().enumerate() {
+        let keys = match key_lines.get(row) {
+            Some(keys) => keys.chars(),
+            None => continue,
+        };
+
+        for (column, (cell, key)) in line.iter_mut().zip(keys).enumerate() {
+            if key == ' ' {
+                continue;
+            }
+
+            match palette.iter().find(|entry| entry.0 == key) {
+                Some((_, fg, bg, attrs)) => {
+                    cell.fg = fg.unwrap_or(color);
+                    cell.bg = *bg;
+                    cell.attrs = *attrs;
+                }
+
+                None => return Err(MarkupError::UnknownKey {
+                    line: row + 1, column: column + 1, key
+                }),
+            }
+        }
+    }
+
+    Ok(cells)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PALETTE: &Palette = &[
+        ('r', Some(Color::red()), None, 0),
+        ('h', None, Some(Color::blue()), ATTR_BOLD),
+    ];
+
+    #[test]
+    fn plain_rows() {
+        let cells = parse_plain("ab\n\nc", Color::green());
+        assert_eq!(cells.iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 0, 1]);
+        assert_eq!(cells[2][0], Cell::new('c', Color::green()));
+    }
+
+    #[test]
+    fn palette_lookups() {
+        let cells = parse_markup("abc", "r h", PALETTE, Color::white()).unwrap();
+
+        assert_eq!(cells[0][0], Cell::new('a', Color::red()));
+        assert_eq!(cells[0][1], Cell::new('b', Color::white()));
+        // No foreground in the palette keeps the default one
+        assert_eq!(cells[0][2], Cell { glyph: 'c', fg: Color::white(), bg: Some(Color::blue()), attrs: ATTR_BOLD });
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let error = parse_markup("abc\nde", "rrr\nrx", PALETTE, Color::white()).unwrap_err();
+        assert!(matches!(error, MarkupError::UnknownKey { line: 2, column: 2, key: 'x' }));
+    }
+
+    #[test]
+    fn unterminated_layers() {
+        // Missing keys keep the default colour, keys past the content are ignored
+        let cells = parse_markup("abc\nde\nf", "r\nhhhhh", PALETTE, Color::white()).unwrap();
+
+        assert_eq!(cells[0][1], Cell::new('b', Color::white()));
+        assert_eq!(cells[1].len(), 2);
+        assert_eq!(cells[1][1].bg, Some(Color::blue()));
+        assert_eq!(cells[2][0], Cell::new('f', Color::white()));
+    }
+}
@@ -0,0 +1,200 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+/// Xorshift generator, so that effects look the same on every run
+pub struct Random {
+    state: u32,
+}
+
+impl Random {
+    pub fn new(seed: u32) -> Self {
+        // Zero is a fixed point of xorshift
+        Self { state: if seed == 0 { 0x9e3779b9 } else { seed } }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// Returns: a number from 0 (inclusive) to 1 (exclusive)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    pub fn range_f32(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    pub fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.next_u32() as usize % items.len()]
+    }
+}
+
+
+/// Describes a burst of particles
+pub struct ParticlePreset {
+    /// Each particle gets a random one
+    pub glyphs: &'static [char],
+    /// The colour changes from start_color to end_color during the lifetime
+    pub start_color: Color,
+    pub end_color: Color,
+    pub count: u32,
+    /// Measured in ticks, each particle lives from min to max
+    pub min_lifetime: u32,
+    pub max_lifetime: u32,
+    /// Measured in cells per tick
+    pub min_speed: f32,
+    pub max_speed: f32,
+    /// Direction of the burst in radians, 0 is to the right, -PI/2 is up
+    pub direction: f32,
+    /// Particles fly at most this many radians away from the direction
+    pub spread: f32,
+    /// Negative values make particles float up
+    pub gravity: f32,
+}
+
+pub struct Particle {
+    pub glyph: char,
+    pub position: Vector,
+    pub velocity: Vector,
+    pub start_color: Color,
+    pub end_color: Color,
+    pub gravity: f32,
+    pub age: u32,
+    pub lifetime: u32,
+}
+
+impl Particle {
+    pub fn get_color(&self) -> Color {
+        self.start_color.blend(self.end_color, self.age as f32 / self.lifetime as f32)
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.age < self.lifetime
+    }
+}
+
+
+pub struct ParticleSystem {
+    pub particles: Vec<Particle>,
+    random: Random,
+}
+
+impl ParticleSystem {
+    pub fn new() -> Self {
+        Self {
+            particles: vec![],
+            random: Random::new(2022),
+        }
+    }
+
+    /// Arguments:
+    /// position - where the particles appear, in world coordinates
+    pub fn spawn_burst(&mut self, preset: &ParticlePreset, position: Vector) {
+        for _ in 0..preset.count {
+            let angle = preset.direction + self.random.range_f32(-preset.spread, preset.spread);
+            let speed = self.random.range_f32(preset.min_speed, preset.max_speed);
+            let lifetime = preset.min_lifetime
+                + self.random.next_u32() % (preset.max_lifetime - preset.min_lifetime + 1);
+
+            self.particles.push(Particle {
+                glyph: self.random.pick(preset.glyphs),
+                position,
+                velocity: Vector::new(angle.cos() * speed, angle.sin() * speed),
+                start_color: preset.start_color,
+                end_color: preset.end_color,
+                gravity: preset.gravity,
+                age: 0,
+                lifetime: lifetime.max(1),
+            });
+        }
+    }
+
+    /// Called once per simulation tick
+    pub fn update(&mut self) {
+        for particle in &mut self.particles {
+            particle.velocity.y += particle.gravity;
+            particle.position.x += particle.velocity.x;
+            particle.position.y += particle.velocity.y;
+            particle.age += 1;
+        }
+
+        self.particles.retain(Particle::is_alive);
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Everything that ends up on the screen
+    fn snapshot(system: &ParticleSystem) -> Vec<(char, Position, Color)> {
+        system.particles
+            .iter()
+            .map(|particle| (particle.glyph, particle.position.to_position(), particle.get_color()))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_particles() {
+        let mut systems = [ParticleSystem::new(), ParticleSystem::new()];
+
+        for system in &mut systems {
+            system.spawn_burst(&SPELL_PARTICLES, Vector::new(10., 20.));
+
+            for tick in 0..10 {
+                system.update();
+                if tick == 3 {
+                    system.spawn_burst(&ENEMY_DEFEAT_PARTICLES, Vector::new(30., 20.));
+                }
+            }
+        }
+
+        assert!(!systems[0].particles.is_empty());
+        assert_eq!(snapshot(&systems[0]), snapshot(&systems[1]));
+        for (first, second) in systems[0].particles.iter().zip(&systems[1].particles) {
+            assert_eq!((first.position, first.velocity, first.age), (second.position, second.velocity, second.age));
+        }
+    }
+
+    #[test]
+    fn random_sequences() {
+        let sequence = |seed| {
+            let mut random = Random::new(seed);
+            (0..5).map(|_| random.next_u32()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(sequence(7), sequence(7));
+        assert_ne!(sequence(7), sequence(8));
+        // Zero would only produce zeros
+        assert!(sequence(0).iter().all(|value| *value != 0));
+
+        let mut random = Random::new(1);
+        assert!((0..1000).map(|_| random.range_f32(-2., 3.)).all(|value| (-2. ..3.).contains(&value)));
+    }
+}
@@ -0,0 +1,375 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+// All values are measured in cells and ticks (one tick is one render() call)
+
+pub const GRAVITY: f32 = 0.1;
+pub const TERMINAL_FALL_SPEED: f32 = 1.0;
+
+/// Gives a jump of about 7 cells: JUMP_VELOCITY^2 / (2 * GRAVITY)
+pub const JUMP_VELOCITY: f32 = 1.2;
+
+/// Upward velocity is multiplied by this when the jump key is released early
+pub const JUMP_CUT_FACTOR: f32 = 0.4;
+
+pub const RUN_SPEED: f32 = 1.0;
+pub const GROUND_ACCELERATION: f32 = 0.35;
+pub const GROUND_FRICTION: f32 = 0.4;
+
+/// Air control
+pub const AIR_ACCELERATION: f32 = 0.15;
+pub const AIR_FRICTION: f32 = 0.05;
+
+/// Number of ticks after walking off a ledge during which a jump is still allowed
+pub const COYOTE_TICKS: u32 = 4;
+
+/// Number of ticks a jump pressed before landing is remembered
+pub const JUMP_BUFFER_TICKS: u32 = 4;
+
+/// Maximal falling speed while pressing against a wall
+pub const WALL_SLIDE_SPEED: f32 = 0.3;
+pub const WALL_JUMP_VELOCITY_X: f32 = 0.9;
+
+/// Number of ticks after a wall jump during which horizontal input is ignored
+pub const WALL_JUMP_LOCK_TICKS: u32 = 6;
+
+pub const DASH_SPEED: f32 = 2.0;
+pub const DASH_TICKS: u32 = 5;
+pub const DASH_COOLDOWN_TICKS: u32 = 30;
+
+/// Climbing onto a ledge: first go up by the hero's height, then step over the edge
+pub const CLIMB_UP_TICKS: u32 = 3;
+pub const CLIMB_OVER_TICKS: u32 = 2;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vector {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Vector {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+
+    pub fn zero() -> Self {
+        Self::new(0., 0.)
+    }
+
+    pub fn from_position(pos: Position) -> Self {
+        Self::new(pos.x as f32, pos.y as f32)
+    }
+
+    /// Returns: the cell this vector points to
+    pub fn to_position(self) -> Position {
+        Position::new(self.x.round() as i32, self.y.round() as i32)
+    }
+}
+
+
+/// Kinematic state of an entity.
+/// The figure is rendered at the rounded position of the body.
+pub struct Body {
+    pub position: Vector,
+    pub velocity: Vector,
+    pub on_ground: bool,
+
+    pub coyote_ticks: u32,
+    pub jump_buffer_ticks: u32,
+
+    /// Whether the body is still going up after a jump, so that the jump can be cut
+    pub is_jumping: bool,
+
+    /// Gravity is not applied, e.g. while dashing or hanging on a ledge
+    pub is_floating: bool,
+
+    /// Falling speed is limited to WALL_SLIDE_SPEED
+    pub is_sliding: bool,
+
+    /// -1 if there is a wall to the left, 1 if to the right, 0 otherwise
+    pub wall_side: i32,
+
+    /// Same as wall_side, but only if the top of the wall is level with the top of the body
+    pub ledge_side: i32,
+}
+
+impl Body {
+    pub fn new(position: Position) -> Self {
+        Self {
+            position: Vector::from_position(position),
+            velocity: Vector::zero(),
+            on_ground: false,
+            coyote_ticks: 0,
+            jump_buffer_ticks: 0,
+            is_jumping: false,
+            is_floating: false,
+            is_sliding: false,
+            wall_side: 0,
+            ledge_side: 0,
+        }
+    }
+
+    pub fn reset(&mut self, position: Position) {
+        *self = Self::new(position);
+    }
+
+    pub fn get_cell(&self) -> Position {
+        self.position.to_position()
+    }
+
+    /// Arguments:
+    /// direction - -1 to run left, 1 to run right, 0 to slow down
+    pub fn run(&mut self, direction: i32) {
+        let (acceleration, friction) = if self.on_ground {
+            (GROUND_ACCELERATION, GROUND_FRICTION)
+        } else {
+            (AIR_ACCELERATION, AIR_FRICTION)
+        };
+
+        if direction != 0 {
+            self.velocity.x += acceleration * direction as f32;
+            self.velocity.x = self.velocity.x.clamp(-RUN_SPEED, RUN_SPEED);
+        } else if self.velocity.x.abs() <= friction {
+            self.velocity.x = 0.;
+        } else {
+            self.velocity.x -= friction * self.velocity.x.signum();
+        }
+    }
+
+    pub fn buffer_jump(&mut self) {
+        self.jump_buffer_ticks = JUMP_BUFFER_TICKS;
+    }
+
+    /// Performs a buffered jump if the body stands on the ground (or has just left it).
+    /// Returns: whether the jump has started
+    pub fn try_jump(&mut self) -> bool {
+        if self.jump_buffer_ticks == 0 {
+            return false;
+        }
+
+        if !self.on_ground && self.coyote_ticks == 0 {
+            return false;
+        }
+
+        self.velocity.y = -JUMP_VELOCITY;
+        self.on_ground = false;
+        self.coyote_ticks = 0;
+        self.jump_buffer_ticks = 0;
+        self.is_jumping = true;
+
+        true
+    }
+
+    /// Jumps away from the wall
+    pub fn wall_jump(&mut self) {
+        self.velocity.x = -self.wall_side as f32 * WALL_JUMP_VELOCITY_X;
+        self.velocity.y = -JUMP_VELOCITY;
+        self.jump_buffer_ticks = 0;
+        self.is_jumping = true;
+    }
+
+    /// Makes the jump lower if the jump key is released before the apex
+    pub fn cut_jump(&mut self) {
+        if self.is_jumping && self.velocity.y < 0. {
+            self.velocity.y *= JUMP_CUT_FACTOR;
+        }
+
+        self.is_jumping = false;
+    }
+
+    /// Applies gravity and integrates velocity into position
+    pub fn integrate(&mut self) {
+        let max_fall_speed = if self.is_sliding { WALL_SLIDE_SPEED } else { TERMINAL_FALL_SPEED };
+
+        if !self.on_ground && !self.is_floating {
+            self.velocity.y = (self.velocity.y + GRAVITY).min(max_fall_speed);
+        }
+
+        if self.velocity.y >= 0. {
+            self.is_jumping = false;
+        }
+
+        self.position.x += self.velocity.x;
+        self.position.y += self.velocity.y;
+
+        if self.jump_buffer_ticks > 0 {
+            self.jump_buffer_ticks -= 1;
+        }
+
+        if self.coyote_ticks > 0 {
+            self.coyote_ticks -= 1;
+        }
+    }
+
+    /// Called when the body has been stopped by an obstacle on the horizontal axis
+    pub fn stop_x(&mut self, cell_x: i32) {
+        self.position.x = cell_x as f32;
+        self.velocity.x = 0.;
+    }
+
+    /// Called when the body has been stopped by an obstacle on the vertical axis
+    pub fn stop_y(&mut self, cell_y: i32) {
+        self.position.y = cell_y as f32;
+        self.velocity.y = 0.;
+        self.is_jumping = false;
+    }
+
+    pub fn set_on_ground(&mut self, on_ground: bool) {
+        if self.on_ground && !on_ground && self.velocity.y >= 0. {
+            self.coyote_ticks = COYOTE_TICKS;
+        }
+
+        self.on_ground = on_ground;
+
+        if on_ground && self.velocity.y > 0. {
+            self.velocity.y = 0.;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{} is not {}", actual, expected);
+    }
+
+    fn standing_body() -> Body {
+        let mut body = Body::new(Position::new(10, 10));
+        body.set_on_ground(true);
+        body
+    }
+
+    #[test]
+    fn coyote_time() {
+        let mut body = standing_body();
+        body.set_on_ground(false);
+
+        for _ in 0..COYOTE_TICKS - 1 {
+            body.integrate();
+        }
+        body.buffer_jump();
+        assert!(body.try_jump());
+        assert_near(body.velocity.y, -JUMP_VELOCITY);
+
+        // Too late
+        let mut body = standing_body();
+        body.set_on_ground(false);
+        for _ in 0..COYOTE_TICKS {
+            body.integrate();
+        }
+        body.buffer_jump();
+        assert!(!body.try_jump());
+    }
+
+    #[test]
+    fn jump_buffering() {
+        let mut body = Body::new(Position::new(10, 10));
+        body.buffer_jump();
+        assert!(!body.try_jump());
+
+        for _ in 0..JUMP_BUFFER_TICKS - 1 {
+            body.integrate();
+        }
+        body.set_on_ground(true);
+        assert!(body.try_jump());
+        assert!(!body.on_ground);
+
+        // Forgotten before landing
+        let mut body = Body::new(Position::new(10, 10));
+        body.buffer_jump();
+        for _ in 0..JUMP_BUFFER_TICKS {
+            body.integrate();
+        }
+        body.set_on_ground(true);
+        assert!(!body.try_jump());
+    }
+
+    #[test]
+    fn cut_jump() {
+        let mut body = standing_body();
+        body.buffer_jump();
+        body.try_jump();
+        body.integrate();
+        assert_near(body.velocity.y, -JUMP_VELOCITY + GRAVITY);
+        assert_near(body.position.y, 10. - JUMP_VELOCITY + GRAVITY);
+
+        body.cut_jump();
+        assert_near(body.velocity.y, (-JUMP_VELOCITY + GRAVITY) * JUMP_CUT_FACTOR);
+
+        // Only once per jump
+        body.cut_jump();
+        assert_near(body.velocity.y, (-JUMP_VELOCITY + GRAVITY) * JUMP_CUT_FACTOR);
+
+        // Falling is not affected
+        let mut body = Body::new(Position::new(10, 10));
+        body.velocity.y = 0.5;
+        body.is_jumping = true;
+        body.cut_jump();
+        assert_near(body.velocity.y, 0.5);
+    }
+
+    #[test]
+    fn terminal_fall_speed() {
+        let mut body = Body::new(Position::new(10, 0));
+
+        for _ in 0..30 {
+            body.integrate();
+        }
+        assert_near(body.velocity.y, TERMINAL_FALL_SPEED);
+
+        body.is_sliding = true;
+        body.integrate();
+        assert_near(body.velocity.y, WALL_SLIDE_SPEED);
+
+        // No gravity while floating
+        body.is_sliding = false;
+        body.is_floating = true;
+        body.velocity.y = 0.;
+        body.integrate();
+        assert_near(body.velocity.y, 0.);
+    }
+
+    #[test]
+    fn air_control() {
+        let mut body = standing_body();
+        body.run(1);
+        assert_near(body.velocity.x, GROUND_ACCELERATION);
+
+        let mut body = Body::new(Position::new(10, 10));
+        body.run(1);
+        assert_near(body.velocity.x, AIR_ACCELERATION);
+        body.integrate();
+        assert_near(body.position.x, 10. + AIR_ACCELERATION);
+
+        for _ in 0..20 {
+            body.run(-1);
+        }
+        assert_near(body.velocity.x, -RUN_SPEED);
+
+        // Slows down slower in the air than on the ground
+        body.run(0);
+        assert_near(body.velocity.x, -RUN_SPEED + AIR_FRICTION);
+        body.set_on_ground(true);
+        body.run(0);
+        assert_near(body.velocity.x, -RUN_SPEED + AIR_FRICTION + GROUND_FRICTION);
+    }
+}
@@ -0,0 +1,338 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+/// Glyphs put into random cells by the glitch effect
+const GLITCH_GLYPHS: [char; 10] = ['▓', '▒', '░', '0', '1', '#', '@', '$', '%', '█'];
+
+/// How long it takes to uncover the room when the gameplay starts or continues
+pub const UNWIPE_TICKS: u32 = 15;
+
+/// How long it takes to cover the screen before a room change or a game over
+pub const WIPE_TICKS: u32 = 15;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScreenEffectKind {
+    /// From the picture to black
+    FadeOut,
+    /// From black to the picture
+    FadeIn,
+    /// A black curtain going from left to right, covering the picture
+    WipeHorizontal,
+    /// Same as WipeHorizontal, but from top to bottom
+    WipeVertical,
+    /// A black curtain uncovering the picture from left to right
+    UnwipeHorizontal,
+    /// Scrambles glyphs and shifts rows.
+    /// Arguments: the part of cells affected, from 0 to 1
+    Glitch(f32),
+    /// Moves the whole picture randomly by up to the given number of cells
+    Shake(i32),
+}
+
+pub struct ScreenEffect {
+    pub kind: ScreenEffectKind,
+    /// Measured in ticks
+    pub duration: u32,
+    pub age: u32,
+}
+
+impl ScreenEffect {
+    pub fn new(kind: ScreenEffectKind, duration: u32) -> Self {
+        Self { kind, duration: duration.max(1), age: 0 }
+    }
+
+    /// Returns: from 0 at the start to 1 at the end
+    pub fn get_progress(&self) -> f32 {
+        (self.age as f32 / self.duration as f32).min(1.)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.age >= self.duration
+    }
+}
+
+
+/// Applies screen effects to the rendered frame, so both backends show them the same way
+pub struct PostProcessor {
+    effects: Vec<ScreenEffect>,
+    random: Random,
+}
+
+impl PostProcessor {
+    pub fn new() -> Self {
+        Self {
+            effects: vec![],
+            random: Random::new(1337),
+        }
+    }
+
+    /// Effects run simultaneously and are applied in the order of requests
+    pub fn request(&mut self, kind: ScreenEffectKind, duration: u32) {
+        self.effects.push(ScreenEffect::new(kind, duration));
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.effects.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.effects.clear();
+    }
+
+    /// Called once per simulation tick
+    pub fn update(&mut self) {
+        for effect in &mut self.effects {
+            effect.age += 1;
+        }
+
+        self.effects.retain(|effect| !effect.is_finished());
+    }
+
+    pub fn apply(&mut self, frame: &mut [Vec<Cell>]) {
+        for effect in &self.effects {
+            let progress = effect.get_progress();
+
+            match effect.kind {
+                ScreenEffectKind::FadeOut => Self::fade(frame, progress),
+                ScreenEffectKind::FadeIn => Self::fade(frame, 1. - progress),
+                ScreenEffectKind::WipeHorizontal => Self::wipe(frame, |x, _, width, _| {
+                    (x as f32) < progress * width as f32
+                }),
+                ScreenEffectKind::WipeVertical => Self::wipe(frame, |_, y, _, height| {
+                    (y as f32) < progress * height as f32
+                }),
+                ScreenEffectKind::UnwipeHorizontal => Self::wipe(frame, |x, _, width, _| {
+                    (x as f32) >= progress * width as f32
+                }),
+                ScreenEffectKind::Glitch(amount) => Self::glitch(frame, amount, &mut self.random),
+                ScreenEffectKind::Shake(amplitude) => {
+                    let mut offset = || {
+                        (self.random.next_u32() % (2 * amplitude as u32 + 1)) as i32 - amplitude
+                    };
+                    let shift = Position::new(offset(), offset());
+                    Self::shift(frame, shift);
+                }
+            }
+        }
+    }
+
+    /// Arguments:
+    /// darkness - 0 leaves the frame as is, 1 makes it black
+    fn fade(frame: &mut [Vec<Cell>], darkness: f32) {
+        for row in frame {
+            for cell in row {
+                cell.fg = cell.fg.blend(Color::black(), darkness);
+                cell.bg = Some(cell.bg.unwrap_or(BACKGROUND_COLOR).blend(Color::black(), darkness));
+            }
+        }
+    }
+
+    /// Arguments:
+    /// is_covered - (x, y, width, height) -> whether the cell is black
+    fn wipe<F: Fn(usize, usize, usize, usize) -> bool>(frame: &mut [Vec<Cell>], is_covered: F) {
+        let height = frame.len();
+
+        for (y, row) in frame.iter_mut().enumerate() {
+            let width = row.len();
+
+            for (x, cell) in row.iter_mut().enumerate() {
+                if is_covered(x, y, width, height) {
+                    *cell = Cell { bg: Some(Color::black()), ..Cell::blank() };
+                }
+            }
+        }
+    }
+
+    fn glitch(frame: &mut [Vec<Cell>], amount: f32, random: &mut Random) {
+        for row in frame.iter_mut() {
+            if random.next_f32() < amount / 2. && !row.is_empty() {
+                let shift = random.next_u32() as usize % 5 % row.len();
+                row.rotate_right(shift);
+            }
+
+            for cell in row.iter_mut() {
+                if random.next_f32() < amount {
+                    cell.glyph = random.pick(&GLITCH_GLYPHS);
+                    cell.fg = random.pick(&[Color::cyan(), Color::magenta(), Color::green()]);
+                }
+            }
+        }
+    }
+
+    fn shift(frame: &mut [Vec<Cell>], shift: Position) {
+        let source = frame.to_vec();
+        let height = frame.len() as i32;
+
+        for (y, row) in frame.iter_mut().enumerate() {
+            let width = row.len() as i32;
+
+            for (x, cell) in row.iter_mut().enumerate() {
+                let from = Position::new(x as i32 - shift.x, y as i32 - shift.y);
+
+                *cell = if from.x >= 0 && from.y >= 0 && from.x < width && from.y < height {
+                    source[from.y as usize][from.x as usize]
+                } else {
+                    Cell::blank()
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rows of 'a', 'b', 'c'... with columns going from '0' up
+    fn small_frame() -> Vec<Vec<Cell>> {
+        (0..3).map(|y| {
+            (0..4).map(|x| {
+                let glyph = if x == 0 { (b'a' + y as u8) as char } else { (b'0' + x as u8) as char };
+                Cell::new(glyph, Color::white())
+            }).collect()
+        }).collect()
+    }
+
+    /// Returns: the frame after the only effect has run for the given number of ticks
+    fn apply_effect(kind: ScreenEffectKind, duration: u32, ticks: u32) -> Vec<Vec<Cell>> {
+        let mut post_process = PostProcessor::new();
+        post_process.request(kind, duration);
+
+        for _ in 0..ticks {
+            post_process.update();
+        }
+
+        let mut frame = small_frame();
+        post_process.apply(&mut frame);
+        frame
+    }
+
+    fn glyphs(frame: &[Vec<Cell>]) -> Vec<String> {
+        frame.iter().map(|row| row.iter().map(|cell| cell.glyph).collect()).collect()
+    }
+
+    #[test]
+    fn fades() {
+        let half = Color::white().blend(Color::black(), 0.5);
+        let half_bg = BACKGROUND_COLOR.blend(Color::black(), 0.5);
+
+        for kind in [ScreenEffectKind::FadeOut, ScreenEffectKind::FadeIn] {
+            let frame = apply_effect(kind, 4, 2);
+            assert_eq!(glyphs(&frame), glyphs(&small_frame()));
+
+            for cell in frame.iter().flatten() {
+                assert_eq!(cell.fg, half);
+                assert_eq!(cell.bg, Some(half_bg));
+            }
+        }
+
+        let frame = apply_effect(ScreenEffectKind::FadeIn, 4, 0);
+        for cell in frame.iter().flatten() {
+            assert_eq!(cell.fg, Color::black());
+            assert_eq!(cell.bg, Some(Color::black()));
+        }
+    }
+
+    #[test]
+    fn wipes() {
+        let covered = Cell { bg: Some(Color::black()), ..Cell::blank() };
+
+        let frame = apply_effect(ScreenEffectKind::WipeHorizontal, 4, 1);
+        for (row, source) in frame.iter().zip(small_frame()) {
+            assert_eq!(row[..1], [covered]);
+            assert_eq!(row[1..], source[1..]);
+        }
+
+        let frame = apply_effect(ScreenEffectKind::WipeVertical, 3, 2);
+        assert_eq!(frame[..2], [vec![covered; 4], vec![covered; 4]]);
+        assert_eq!(frame[2], small_frame()[2]);
+
+        // Nothing is covered yet at the start
+        for kind in [ScreenEffectKind::WipeHorizontal, ScreenEffectKind::WipeVertical] {
+            assert_eq!(apply_effect(kind, 4, 0), small_frame());
+        }
+    }
+
+    #[test]
+    fn unwipe() {
+        let frame = apply_effect(ScreenEffectKind::UnwipeHorizontal, 4, 2);
+        let covered = Cell { bg: Some(Color::black()), ..Cell::blank() };
+
+        for (row, source) in frame.iter().zip(small_frame()) {
+            assert_eq!(row[..2], source[..2]);
+            assert_eq!(row[2..], [covered, covered]);
+        }
+    }
+
+    #[test]
+    fn glitch() {
+        assert_eq!(apply_effect(ScreenEffectKind::Glitch(0.), 4, 1), small_frame());
+
+        let frame = apply_effect(ScreenEffectKind::Glitch(1.), 4, 1);
+        assert_eq!(frame, apply_effect(ScreenEffectKind::Glitch(1.), 4, 1));
+
+        for cell in frame.iter().flatten() {
+            assert!(GLITCH_GLYPHS.contains(&cell.glyph));
+            assert!([Color::cyan(), Color::magenta(), Color::green()].contains(&cell.fg));
+        }
+    }
+
+    #[test]
+    fn shake() {
+        assert_eq!(apply_effect(ScreenEffectKind::Shake(0), 4, 1), small_frame());
+
+        // The same seed as in PostProcessor
+        let mut random = Random::new(1337);
+        let dx = (random.next_u32() % 3) as i32 - 1;
+        let dy = (random.next_u32() % 3) as i32 - 1;
+
+        let source = small_frame();
+        let frame = apply_effect(ScreenEffectKind::Shake(1), 4, 1);
+
+        for (y, row) in frame.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                let from = Position::new(x as i32 - dx, y as i32 - dy);
+                let expected = source
+                    .get(from.y as usize)
+                    .and_then(|row| row.get(from.x as usize))
+                    .copied()
+                    .unwrap_or(Cell::blank());
+
+                assert_eq!(*cell, expected, "at {}, {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn effects_expire() {
+        let mut post_process = PostProcessor::new();
+        post_process.request(ScreenEffectKind::FadeOut, 2);
+        post_process.request(ScreenEffectKind::Shake(1), 3);
+
+        post_process.update();
+        post_process.update();
+        assert!(post_process.is_active());
+
+        post_process.update();
+        assert!(!post_process.is_active());
+
+        let mut frame = small_frame();
+        post_process.apply(&mut frame);
+        assert_eq!(frame, small_frame());
+    }
+}
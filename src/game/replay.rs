@@ -0,0 +1,380 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+use std::fmt;
+
+pub const REPLAY_VERSION: u32 = 2;
+const REPLAY_HEADER: &str = "cybersem-replay";
+
+/// A checksum of the game state is recorded once per this number of ticks
+pub const CHECKSUM_INTERVAL: u32 = 30;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplayEvent {
+    Action(Action),
+    Resize(Size),
+}
+
+
+#[derive(Debug)]
+pub enum ReplayError {
+    UnsupportedVersion(String),
+    /// Line starts from 1
+    InvalidLine { line: usize, text: String },
+    /// The state of the game differs from the recorded one
+    Desync { tick: u32, expected: u32, actual: u32 },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnsupportedVersion(header) =>
+                write!(f, "not a replay of version {}: '{}'", REPLAY_VERSION, header),
+            Self::InvalidLine { line, text } =>
+                write!(f, "{}: cannot parse '{}'", line, text),
+            Self::Desync { tick, expected, actual } =>
+                write!(f, "desync at tick {}: checksum {:08x} instead of {:08x}", tick, actual, expected),
+        }
+    }
+}
+
+
+/// Input of a play-through and checksums of the resulting states.
+/// Ticks count from the start of the game, events of tick N are applied before the (N+1)th update.
+pub struct Replay {
+    /// Size of the screen at the start, the simulation depends on it
+    pub size: Size,
+    /// Bindings at the start, they are shown on the tutorial sign
+    pub bindings: KeyBindings,
+    pub events: Vec<(u32, ReplayEvent)>,
+    /// Ticks and checksums of the state after them
+    pub checksums: Vec<(u32, u32)>,
+    /// Number of ticks recorded
+    pub length: u32,
+}
+
+impl Replay {
+    pub fn new(size: Size, bindings: KeyBindings) -> Self {
+        Self {
+            size,
+            bindings,
+            events: vec![],
+            checksums: vec![],
+            length: 0,
+        }
+    }
+
+    /// The text format has a line per event:
+    ///   cybersem-replay 2
+    ///   size 100 35
+    ///   length 120
+    ///   bind KeyA left
+    ///   12 action right
+    ///   30 checksum 1a2b3c4d
+    ///   40 resize 80 30
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            format!("{} {}", REPLAY_HEADER, REPLAY_VERSION),
+            format!("size {} {}", self.size.width, self.size.height),
+            format!("length {}", self.length),
+        ];
+
+        for (key, action) in &self.bindings.bindings {
+            lines.push(format!("bind {} {}", key.get_name(), action.get_code()));
+        }
+
+        let mut events = self.events.iter().peekable();
+        let mut checksums = self.checksums.iter().peekable();
+
+        // Merged by tick in the order of playback: the checksum of a tick is verified
+        // right after the tick, the events of the tick are applied before the next one
+        loop {
+            let is_event_next = match (events.peek(), checksums.peek()) {
+                (Some((event_tick, _)), Some((checksum_tick, _))) => event_tick < checksum_tick,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+
+            if is_event_next {
+                let (tick, event) = events.next().unwrap();
+
+                lines.push(match event {
+                    ReplayEvent::Action(action) => format!("{} action {}", tick, action.get_code()),
+                    ReplayEvent::Resize(size) => format!("{} resize {} {}", tick, size.width, size.height),
+                });
+            } else {
+                let (tick, checksum) = checksums.next().unwrap();
+                lines.push(format!("{} checksum {:08x}", tick, checksum));
+            }
+        }
+
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, ReplayError> {
+        let mut lines = text.lines().enumerate();
+
+        let header = lines.next().map(|(_, line)| line).unwrap_or("");
+        if header != format!("{} {}", REPLAY_HEADER, REPLAY_VERSION) {
+            return Err(ReplayError::UnsupportedVersion(header.into()));
+        }
+
+        let mut replay = Self::new(Size::new(0, 0), KeyBindings::empty());
+
+        for (index, line) in lines {
+            let invalid = || ReplayError::InvalidLine { line: index + 1, text: line.into() };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let number = |word: &str| word.parse::<u32>().map_err(|_| invalid());
+
+            match words.as_slice() {
+                [] => {}
+
+                ["size", width, height] => {
+                    replay.size = Size::new(number(width)? as i32, number(height)? as i32);
+                }
+
+                ["length", length] => {
+                    replay.length = number(length)?;
+                }
+
+                ["bind", key, code] => {
+                    let key = Key::from_name(key).ok_or_else(invalid)?;
+                    let action = Action::from_code(code).ok_or_else(invalid)?;
+                    replay.bindings.bind(key, action).map_err(|_| invalid())?;
+                }
+
+                [tick, "action", code] => {
+                    let action = Action::from_code(code).ok_or_else(invalid)?;
+                    replay.events.push((number(tick)?, ReplayEvent::Action(action)));
+                }
+
+                [tick, "resize", width, height] => {
+                    let size = Size::new(number(width)? as i32, number(height)? as i32);
+                    replay.events.push((number(tick)?, ReplayEvent::Resize(size)));
+                }
+
+                [tick, "checksum", checksum] => {
+                    let checksum = u32::from_str_radix(checksum, 16).map_err(|_| invalid())?;
+                    replay.checksums.push((number(tick)?, checksum));
+                }
+
+                _ => return Err(invalid()),
+            }
+        }
+
+        if replay.bindings.bindings.is_empty() {
+            replay.bindings = KeyBindings::new();
+        }
+
+        Ok(replay)
+    }
+}
+
+
+/// Feeds a replay into a game created with the size of the replay
+pub struct ReplayPlayer {
+    pub replay: Replay,
+    next_event: usize,
+    next_checksum: usize,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> Self {
+        Self { replay, next_event: 0, next_checksum: 0 }
+    }
+
+    /// Called before each tick instead of processing the input
+    pub fn apply_events(&mut self, game: &mut Game) {
+        while let Some((tick, event)) = self.replay.events.get(self.next_event) {
+            if *tick > game.ticks {
+                break;
+            }
+
+            match *event {
+                ReplayEvent::Action(action) => game.process_action(action),
+                ReplayEvent::Resize(size) => game.set_size(size),
+            }
+
+            self.next_event += 1;
+        }
+    }
+
+    /// Called after each tick
+    pub fn verify(&mut self, game: &Game) -> Result<(), ReplayError> {
+        while let Some(&(tick, expected)) = self.replay.checksums.get(self.next_checksum) {
+            if tick > game.ticks {
+                break;
+            }
+
+            self.next_checksum += 1;
+
+            if tick < game.ticks {
+                continue;
+            }
+
+            let actual = game.get_checksum();
+            if actual != expected {
+                return Err(ReplayError::Desync { tick, expected, actual });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_finished(&self, game: &Game) -> bool {
+        game.ticks >= self.replay.length
+    }
+}
+
+
+/// FNV-1a, stable across platforms and runs unlike the hasher of the standard library
+pub struct Checksum {
+    pub value: u32,
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checksum {
+    pub fn new() -> Self {
+        Self { value: 0x811c9dc5 }
+    }
+
+    pub fn add_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.value ^= *byte as u32;
+            self.value = self.value.wrapping_mul(0x01000193);
+        }
+    }
+
+    pub fn add_u32(&mut self, value: u32) {
+        self.add_bytes(&value.to_le_bytes());
+    }
+
+    pub fn add_i32(&mut self, value: i32) {
+        self.add_bytes(&value.to_le_bytes());
+    }
+
+    pub fn add_f32(&mut self, value: f32) {
+        self.add_bytes(&value.to_bits().to_le_bytes());
+    }
+
+    pub fn add_bool(&mut self, value: bool) {
+        self.add_bytes(&[value as u8]);
+    }
+
+    pub fn add_str(&mut self, value: &str) {
+        self.add_u32(value.len() as u32);
+        self.add_bytes(value.as_bytes());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_round_trip() {
+        let mut bindings = KeyBindings::new();
+        bindings.rebind(Action::Attack, Key::Letter('j')).unwrap();
+
+        let mut replay = Replay::new(Size::new(100, 35), bindings);
+        replay.length = 61;
+        replay.events.push((0, ReplayEvent::Action(Action::MoveRight)));
+        replay.events.push((30, ReplayEvent::Action(Action::Letter('x'))));
+        replay.events.push((40, ReplayEvent::Resize(Size::new(80, 30))));
+        replay.checksums.push((30, 0xdeadbeef));
+        replay.checksums.push((60, 0x1234));
+
+        let text = replay.to_text();
+        let parsed = Replay::from_text(&text).unwrap();
+
+        assert_eq!(parsed.size, replay.size);
+        assert_eq!(parsed.bindings, replay.bindings);
+        assert_eq!(parsed.length, 61);
+        assert_eq!(parsed.events, replay.events);
+        assert_eq!(parsed.checksums, replay.checksums);
+        assert_eq!(parsed.to_text(), text);
+        assert!(text.find("30 checksum").unwrap() < text.find("30 action").unwrap());
+    }
+
+    #[test]
+    fn checksum_goes_before_the_events_of_its_tick() {
+        let mut game = Game::new(Size::new(100, 35));
+        game.start_recording();
+        game.process_action(Action::Confirm);
+
+        while game.ticks < CHECKSUM_INTERVAL {
+            game.render();
+        }
+
+        game.process_action(Action::MoveRight);
+        game.render();
+
+        let replay = game.take_recording().unwrap();
+        assert_eq!(replay.events[1], (CHECKSUM_INTERVAL, ReplayEvent::Action(Action::MoveRight)));
+        assert_eq!(replay.checksums, [(CHECKSUM_INTERVAL, replay.checksums[0].1)]);
+
+        let mut game = Game::from_replay(&replay);
+        let mut player = ReplayPlayer::new(replay);
+
+        while game.ticks < CHECKSUM_INTERVAL {
+            player.apply_events(&mut game);
+            game.render();
+        }
+
+        // The checksum matches the state before the action of the same tick
+        assert_eq!(player.next_event, 1);
+        player.verify(&game).unwrap();
+        assert_eq!(player.next_checksum, 1);
+
+        player.apply_events(&mut game);
+        assert_eq!(player.next_event, 2);
+    }
+
+    #[test]
+    fn rejects_other_versions_and_garbage() {
+        assert!(matches!(Replay::from_text("cybersem-replay 1\n"), Err(ReplayError::UnsupportedVersion(_))));
+        assert!(matches!(
+            Replay::from_text("cybersem-replay 2\nsize 100 35\n5 action fly\n"),
+            Err(ReplayError::InvalidLine { line: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn every_action_has_a_code() {
+        let actions = [
+            Action::MoveLeft, Action::MoveRight, Action::Jump, Action::Crouch, Action::Dash,
+            Action::Attack, Action::StartSpell, Action::Letter('q'), Action::Backspace,
+            Action::Enter, Action::ToggleTimer, Action::Pause, Action::MenuUp, Action::MenuDown,
+            Action::MenuLeft, Action::MenuRight, Action::Confirm, Action::Back, Action::BindKey(Key::ArrowUp),
+            Action::Quit,
+        ];
+
+        for action in actions {
+            assert_eq!(Action::from_code(&action.get_code()), Some(action));
+        }
+    }
+}
@@ -0,0 +1,404 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+use std::fmt;
+
+pub const SAVE_VERSION: u32 = 1;
+const SAVE_HEADER: &str = "cybersem-save";
+
+/// Runners save the game once per this number of ticks, about every 9 seconds
+pub const AUTOSAVE_INTERVAL: u32 = 300;
+
+
+#[derive(Debug)]
+pub enum SaveError {
+    UnsupportedVersion(String),
+    /// Line starts from 1
+    InvalidLine { line: usize, text: String },
+    /// Reading or writing failed, e.g. the storage is full
+    Storage(String),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnsupportedVersion(header) =>
+                write!(f, "not a save of version {} or older: '{}'", SAVE_VERSION, header),
+            Self::InvalidLine { line, text } =>
+                write!(f, "{}: cannot parse '{}'", line, text),
+            Self::Storage(message) =>
+                write!(f, "{}", message),
+        }
+    }
+}
+
+
+/// The progress of a play-through, everything else starts over on load
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveData {
+    /// Whether the game has been started, so that the title screen offers to continue it
+    pub has_progress: bool,
+    pub room: usize,
+    pub hero_position: Position,
+    pub hero_facing_right: bool,
+    pub hero_health: u32,
+    /// HERO_STATE_* of the hero state machine
+    pub hero_state: StateID,
+    pub hero_velocity: Vector,
+    pub view_position: Position,
+    /// FLAG_* of the unlocked abilities
+    pub progression_flags: u32,
+    /// Indices in the list of enemies, in the order of construction
+    pub defeated_enemies: Vec<usize>,
+    pub is_boss_defeated: bool,
+    /// Indices in the list of pickups, in the order of construction
+    pub collected_pickups: Vec<usize>,
+    pub discovered_spells: Vec<String>,
+    pub stats: Stats,
+    /// The splits of the play-through and the best ones of all runs
+    pub speedrun: SpeedrunTimer,
+}
+
+impl Default for SaveData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SaveData {
+    /// Returns: the state at the start of the game
+    pub fn new() -> Self {
+        Self {
+            has_progress: false,
+            room: 0,
+            hero_position: Position::origin(),
+            hero_facing_right: true,
+            hero_health: HERO_MAX_HEALTH,
+            hero_state: HERO_STATE_NORMAL,
+            hero_velocity: Vector::zero(),
+            view_position: Position::origin(),
+            progression_flags: 0,
+            defeated_enemies: vec![],
+            is_boss_defeated: false,
+            collected_pickups: vec![],
+            discovered_spells: vec![],
+            stats: Stats::new(),
+            speedrun: SpeedrunTimer::new(),
+        }
+    }
+
+    /// The text format has a line per field, missing fields keep the values of SaveData::new:
+    ///   cybersem-save 1
+    ///   progress started
+    ///   room 0
+    ///   hero 12 26 right 10
+    ///   state 3
+    ///   velocity 0.4 -0.7
+    ///   view 0 0
+    ///   flags 3
+    ///   defeated 0 1
+    ///   boss active
+    ///   pickups 0 1
+    ///   spells android
+    ///   stats 1234 2 3 1
+    ///   timer shown
+    ///   split dash 400 380
+    ///   split boss 1200 -
+    ///   best dash 380
+    ///   best boss 1150
+    /// The split and best lines repeat, a split without a previous best has '-'.
+    pub fn to_text(&self) -> String {
+        let list = |items: &[usize]| items.iter().map(|item| format!(" {}", item)).collect::<String>();
+
+        let lines = [
+            format!("{} {}", SAVE_HEADER, SAVE_VERSION),
+            format!("progress {}", if self.has_progress { "started" } else { "none" }),
+            format!("room {}", self.room),
+            format!(
+                "hero {} {} {} {}",
+                self.hero_position.x,
+                self.hero_position.y,
+                if self.hero_facing_right { "right" } else { "left" },
+                self.hero_health
+            ),
+            format!("state {}", self.hero_state),
+            format!("velocity {} {}", self.hero_velocity.x, self.hero_velocity.y),
+            format!("view {} {}", self.view_position.x, self.view_position.y),
+            format!("flags {}", self.progression_flags),
+            format!("defeated{}", list(&self.defeated_enemies)),
+            format!("boss {}", if self.is_boss_defeated { "defeated" } else { "active" }),
+            format!("pickups{}", list(&self.collected_pickups)),
+            format!("spells{}", self.discovered_spells.iter().map(|spell| format!(" {}", spell)).collect::<String>()),
+            format!(
+                "stats {} {} {} {}",
+                self.stats.play_ticks,
+                self.stats.deaths,
+                self.stats.spells_cast,
+                self.stats.enemies_defeated
+            ),
+            format!("timer {}", if self.speedrun.is_visible { "shown" } else { "hidden" }),
+        ];
+
+        let splits = self.speedrun.splits.iter().map(|split| format!(
+            "split {} {} {}",
+            split.code,
+            split.ticks,
+            split.previous_best.map_or("-".into(), |best| best.to_string())
+        ));
+        let best_splits = self.speedrun.best_splits.iter().map(|(code, ticks)| format!("best {} {}", code, ticks));
+
+        let lines: Vec<String> = lines.into_iter().chain(splits).chain(best_splits).collect();
+        lines.join("\n") + "\n"
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, SaveError> {
+        let mut lines = text.lines();
+        let header = lines.next().unwrap_or("");
+
+        let version = header
+            .strip_prefix(SAVE_HEADER)
+            .and_then(|version| version.trim().parse::<u32>().ok())
+            .ok_or_else(|| SaveError::UnsupportedVersion(header.into()))?;
+
+        let lines = Self::migrate(version, lines.map(String::from).collect())
+            .ok_or_else(|| SaveError::UnsupportedVersion(header.into()))?;
+
+        let mut data = Self::new();
+
+        for (index, line) in lines.iter().enumerate() {
+            let invalid = || SaveError::InvalidLine { line: index + 2, text: line.clone() };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let number = |word: &str| word.parse::<i32>().map_err(|_| invalid());
+            let unsigned = |word: &str| word.parse::<u32>().map_err(|_| invalid());
+            let float = |word: &str| word.parse::<f32>().ok().filter(|value| value.is_finite()).ok_or_else(invalid);
+            let index_list = |words: &[&str]| -> Result<Vec<usize>, SaveError> {
+                words.iter().map(|word| word.parse::<usize>().map_err(|_| invalid())).collect()
+            };
+
+            match words.as_slice() {
+                [] => {}
+
+                ["progress", "started"] => data.has_progress = true,
+                ["progress", "none"] => data.has_progress = false,
+
+                ["room", room] => data.room = room.parse().map_err(|_| invalid())?,
+
+                ["hero", x, y, facing, health] => {
+                    data.hero_position = Position::new(number(x)?, number(y)?);
+                    data.hero_facing_right = match *facing {
+                        "right" => true,
+                        "left" => false,
+                        _ => return Err(invalid()),
+                    };
+                    data.hero_health = unsigned(health)?;
+                }
+
+                ["state", state] => data.hero_state = state.parse().map_err(|_| invalid())?,
+
+                ["velocity", x, y] => data.hero_velocity = Vector::new(float(x)?, float(y)?),
+
+                ["view", x, y] => data.view_position = Position::new(number(x)?, number(y)?),
+
+                ["flags", flags] => data.progression_flags = unsigned(flags)?,
+
+                ["defeated", ids @ ..] => data.defeated_enemies = index_list(ids)?,
+
+                ["boss", "active"] => data.is_boss_defeated = false,
+                ["boss", "defeated"] => data.is_boss_defeated = true,
+
+                ["pickups", ids @ ..] => data.collected_pickups = index_list(ids)?,
+
+                ["spells", spells @ ..] => {
+                    data.discovered_spells = spells.iter().map(|spell| spell.to_string()).collect();
+                }
+
+                ["stats", ticks, deaths, spells, enemies] => {
+                    data.stats = Stats {
+                        play_ticks: unsigned(ticks)?,
+                        deaths: unsigned(deaths)?,
+                        spells_cast: unsigned(spells)?,
+                        enemies_defeated: unsigned(enemies)?,
+                    };
+                }
+
+                ["timer", "shown"] => data.speedrun.is_visible = true,
+                ["timer", "hidden"] => data.speedrun.is_visible = false,
+
+                ["split", code, ticks, previous_best] => {
+                    data.speedrun.splits.push(Split {
+                        code: code.to_string(),
+                        ticks: unsigned(ticks)?,
+                        previous_best: match *previous_best {
+                            "-" => None,
+                            best => Some(unsigned(best)?),
+                        },
+                    });
+                }
+
+                ["best", code, ticks] => data.speedrun.best_splits.push((code.to_string(), unsigned(ticks)?)),
+
+                // Fields of newer versions of the same format
+                _ => {}
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Converts the lines of an older version to the current one.
+    /// When the format changes, bump SAVE_VERSION and add a step from the previous version here,
+    /// so that the steps are applied one after another.
+    /// Returns: None if the version is newer than the game
+    fn migrate(version: u32, lines: Vec<String>) -> Option<Vec<String>> {
+        match version {
+            SAVE_VERSION => Some(lines),
+            _ => None,
+        }
+    }
+}
+
+
+/// Where a runner keeps the save between sessions
+pub trait SaveStorage {
+    /// Returns: None if nothing has been saved yet
+    fn read(&self) -> Result<Option<String>, SaveError>;
+    fn write(&mut self, text: &str) -> Result<(), SaveError>;
+    fn remove(&mut self) -> Result<(), SaveError>;
+
+    fn load(&self) -> Result<Option<SaveData>, SaveError> {
+        match self.read()? {
+            Some(text) => SaveData::from_text(&text).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&mut self, data: &SaveData) -> Result<(), SaveError> {
+        self.write(&data.to_text())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> SaveData {
+        SaveData {
+            has_progress: true,
+            room: 0,
+            hero_position: Position::new(57, 20),
+            hero_facing_right: false,
+            hero_health: 7,
+            hero_state: HERO_STATE_FALLING,
+            hero_velocity: Vector::new(0.25, -0.7),
+            view_position: Position::new(12, 0),
+            progression_flags: FLAG_DASH | FLAG_WALL_JUMP,
+            defeated_enemies: vec![0],
+            is_boss_defeated: true,
+            collected_pickups: vec![0, 1],
+            discovered_spells: vec!["android".into()],
+            stats: Stats { play_ticks: 1234, deaths: 2, spells_cast: 3, enemies_defeated: 1 },
+            speedrun: SpeedrunTimer {
+                splits: vec![
+                    Split { code: "dash".into(), ticks: 400, previous_best: Some(380) },
+                    Split { code: "wall_jump".into(), ticks: 700, previous_best: None },
+                ],
+                best_splits: vec![("dash".into(), 380), ("wall_jump".into(), 700)],
+                is_visible: true,
+            },
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let data = example();
+        assert_eq!(SaveData::from_text(&data.to_text()).unwrap(), data);
+        assert_eq!(SaveData::from_text(&SaveData::new().to_text()).unwrap(), SaveData::new());
+    }
+
+    #[test]
+    fn missing_and_unknown_fields() {
+        let data = SaveData::from_text("cybersem-save 1\nflags 1\nweather rain\n").unwrap();
+        assert_eq!(data, SaveData { progression_flags: 1, ..SaveData::new() });
+    }
+
+    #[test]
+    fn rejects_newer_versions_and_garbage() {
+        assert!(matches!(SaveData::from_text("cybersem-save 2\n"), Err(SaveError::UnsupportedVersion(_))));
+        assert!(matches!(SaveData::from_text("cybersem-save 0\n"), Err(SaveError::UnsupportedVersion(_))));
+        assert!(matches!(SaveData::from_text("something else\n"), Err(SaveError::UnsupportedVersion(_))));
+        assert!(matches!(
+            SaveData::from_text("cybersem-save 1\nhero 1 2 up 3\n"),
+            Err(SaveError::InvalidLine { line: 2, .. })
+        ));
+
+        // Counters and indices are never negative
+        for line in ["room -1", "hero 1 2 left -3", "flags -1", "stats 10 -2 0 0", "split dash 400 -1", "best dash -380", "state -1"] {
+            let text = format!("cybersem-save 1\n{}\n", line);
+            assert!(matches!(SaveData::from_text(&text), Err(SaveError::InvalidLine { line: 2, .. })), "{}", line);
+        }
+
+        // Velocities are finite
+        for line in ["velocity 0.5 NaN", "velocity inf 0"] {
+            let text = format!("cybersem-save 1\n{}\n", line);
+            assert!(matches!(SaveData::from_text(&text), Err(SaveError::InvalidLine { line: 2, .. })), "{}", line);
+        }
+    }
+
+    #[test]
+    fn restart_keeps_best_splits() {
+        let mut game = Game::new(Size::new(100, 35));
+        game.load_save_data(&example());
+        game.restart();
+
+        let data = game.get_save_data();
+        assert_eq!(data.speedrun, SpeedrunTimer { splits: vec![], ..example().speedrun });
+        assert_eq!(data.progression_flags, 0);
+        assert!(data.defeated_enemies.is_empty());
+        assert!(data.collected_pickups.is_empty());
+        assert_eq!(data.stats.play_ticks, 0);
+    }
+
+    #[test]
+    fn finished_game_is_not_continued() {
+        let mut game = Game::new(Size::new(100, 35));
+        game.load_save_data(&example());
+        game.finish_ending();
+        assert!(game.take_autosave_request());
+
+        let data = game.get_save_data();
+        assert!(!data.has_progress);
+        assert!(data.speedrun.splits.is_empty());
+        assert_eq!(data.speedrun.best_splits, example().speedrun.best_splits);
+    }
+
+    #[test]
+    fn game_round_trip() {
+        let mut game = Game::new(Size::new(100, 35));
+        game.load_save_data(&example());
+        assert_eq!(game.get_save_data(), example());
+
+        // Nothing changes the loaded state before the first update
+        let mut other = Game::new(Size::new(100, 35));
+        other.load_save_data(&game.get_save_data());
+        other.render();
+        assert!(!other.entities[other.boss_id].get_figure().visible);
+        assert!(!other.entities[other.hostile_ids[0]].get_figure().visible);
+        assert!(other.progression.has(FLAG_DASH));
+    }
+}
@@ -0,0 +1,341 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+/// Roles of the authors in Cargo.toml, in the same order
+const CREDIT_ROLES: [&str; 2] = ["Code", "Text art & game design"];
+
+const PANEL_BORDER_COLOR: Color = Color::new(0, 128, 255);
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scene {
+    Title,
+    Playing,
+    /// The scripted sequence after reaching the end sign, the input is ignored
+    Ending,
+    /// The simulation is frozen
+    Paused,
+    GameOver,
+    Victory,
+    Credits,
+    /// Rebinding the keys
+    Controls,
+}
+
+impl Scene {
+    /// Returns: whether the world is updated, otherwise the scene has a menu
+    pub fn is_simulated(&self) -> bool {
+        matches!(self, Self::Playing | Self::Ending)
+    }
+
+    /// Arguments:
+    /// has_progress - whether there is a game to continue
+    pub fn get_menu_items(&self, has_progress: bool) -> Vec<MenuItem> {
+        let mut items = match self {
+            Self::Title if has_progress =>
+                vec![MenuItem::Continue, MenuItem::NewGame, MenuItem::Controls, MenuItem::Credits],
+            Self::Title => vec![MenuItem::NewGame, MenuItem::Controls, MenuItem::Credits],
+            Self::Playing | Self::Ending => return vec![],
+            Self::Paused => vec![MenuItem::Resume, MenuItem::Controls, MenuItem::TitleScreen],
+            Self::GameOver => vec![MenuItem::TryAgain, MenuItem::TitleScreen],
+            Self::Victory => vec![MenuItem::Credits, MenuItem::TitleScreen],
+            Self::Credits => vec![MenuItem::Back],
+            Self::Controls => BINDABLE_ACTIONS.iter()
+                .map(|action| MenuItem::Rebind(*action))
+                .chain([MenuItem::ResetControls, MenuItem::Back])
+                .collect(),
+        };
+
+        // A browser tab cannot be closed by the game
+        if cfg!(feature = "terminal_backend") && matches!(self, Self::Title | Self::Paused) {
+            items.push(MenuItem::Quit);
+        }
+
+        items
+    }
+
+    /// Returns: the lines above the menu
+    pub fn get_heading(&self) -> Vec<String> {
+        let heading = match self {
+            Self::Title => TITLE_LOGO,
+            Self::Playing | Self::Ending => "",
+            Self::Paused => "PAUSED",
+            Self::GameOver => "GAME OVER",
+            Self::Victory => "VICTORY",
+            Self::Credits => "CREDITS",
+            Self::Controls => "CONTROLS",
+        };
+
+        heading.split('\n').map(String::from).collect()
+    }
+
+    pub fn get_text(&self) -> Vec<String> {
+        match self {
+            Self::GameOver => vec!["The emissary has been deleted.".into()],
+            Self::Victory => vec!["Cyberspace is safe again.".into()],
+            Self::Credits => get_credits(),
+            _ => vec![],
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MenuItem {
+    Continue,
+    NewGame,
+    Resume,
+    TryAgain,
+    Credits,
+    Controls,
+    /// Waits for a key to bind to the action
+    Rebind(Action),
+    ResetControls,
+    TitleScreen,
+    Back,
+    Quit,
+}
+
+impl MenuItem {
+    /// Returns: the label of a fixed item, Game::get_menu_label adds the keys to Rebind
+    pub fn get_label(&self) -> &'static str {
+        match self {
+            Self::Continue => "Continue",
+            Self::NewGame => "New game",
+            Self::Resume => "Resume",
+            Self::TryAgain => "Try again",
+            Self::Credits => "Credits",
+            Self::Controls => "Controls",
+            Self::Rebind(action) => get_action_label(*action),
+            Self::ResetControls => "Reset to defaults",
+            Self::TitleScreen => "Title screen",
+            Self::Back => "Back",
+            Self::Quit => "Quit",
+        }
+    }
+}
+
+
+/// The top scene gets the input and is drawn over the frozen world
+pub struct SceneStack {
+    scenes: Vec<Scene>,
+    /// Index of the selected menu item of the top scene
+    pub selected: usize,
+}
+
+impl Default for SceneStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneStack {
+    pub fn new() -> Self {
+        Self { scenes: vec![Scene::Title], selected: 0 }
+    }
+
+    pub fn top(&self) -> Scene {
+        *self.scenes.last().unwrap()
+    }
+
+    pub fn push(&mut self, scene: Scene) {
+        self.scenes.push(scene);
+        self.selected = 0;
+    }
+
+    /// The bottom scene stays
+    pub fn pop(&mut self) {
+        if self.scenes.len() > 1 {
+            self.scenes.pop();
+        }
+        self.selected = 0;
+    }
+
+    pub fn replace(&mut self, scene: Scene) {
+        self.scenes.pop();
+        self.push(scene);
+    }
+
+    /// Leaves only the given scene
+    pub fn reset(&mut self, scene: Scene) {
+        self.scenes.clear();
+        self.push(scene);
+    }
+
+    /// Arguments:
+    /// step - -1 for the previous item, 1 for the next one
+    pub fn move_selection(&mut self, step: i32, item_count: usize) {
+        if item_count == 0 {
+            return;
+        }
+
+        let count = item_count as i32;
+        self.selected = (self.selected as i32 + step).rem_euclid(count) as usize;
+    }
+}
+
+
+/// Returns: "Role  Name" lines for the authors in Cargo.toml, without e-mails
+fn get_credits() -> Vec<String> {
+    let authors: Vec<&str> = env!("CARGO_PKG_AUTHORS")
+        .split(':')
+        .map(|author| author.split(" <").next().unwrap().trim())
+        .collect();
+
+    let role_width = CREDIT_ROLES.iter().map(|role| role.chars().count()).max().unwrap_or(0);
+
+    authors
+        .iter()
+        .enumerate()
+        .map(|(index, author)| {
+            let role = CREDIT_ROLES.get(index).copied().unwrap_or("");
+            format!("{:width$}   {}", role, author, width = role_width)
+        })
+        .collect()
+}
+
+/// Arguments:
+/// amount - 0 keeps the frame, 1 leaves only the background colour
+pub fn dim_frame(frame: &mut [Vec<Cell>], amount: f32) {
+    for cell in frame.iter_mut().flatten() {
+        let background = cell.bg.unwrap_or(BACKGROUND_COLOR);
+        cell.fg = cell.fg.blend(background, amount);
+        cell.bg = cell.bg.map(|bg| bg.blend(BACKGROUND_COLOR, amount));
+    }
+}
+
+/// Writes the text over the cells with the page background, cutting it at the edges
+pub fn put_text(frame: &mut [Vec<Cell>], position: Position, text: &str, color: Color, attrs: u32) {
+    let row = match frame.get_mut(position.y as usize) {
+        Some(row) if position.y >= 0 => row,
+        _ => return,
+    };
+
+    for (index, glyph) in text.chars().enumerate() {
+        let x = position.x + index as i32;
+
+        if x < 0 {
+            continue;
+        }
+
+        if let Some(cell) = row.get_mut(x as usize) {
+            *cell = Cell { glyph, fg: color, bg: Some(BACKGROUND_COLOR), attrs };
+        }
+    }
+}
+
+/// Draws a framed box in the middle of the frame with the heading, the text and the menu
+pub fn draw_panel(frame: &mut [Vec<Cell>], size: Size, heading: &[String], text: &[String],
+    item_lines: &[String], selected: usize)
+{
+    let width_of = |line: &String| line.chars().count();
+
+    let content_width = heading.iter().chain(text).chain(item_lines)
+        .map(width_of)
+        .max()
+        .unwrap_or(0) as i32 + 4; // For the selection marks
+
+    let mut rows = heading.len() as i32 + 1;
+    if !text.is_empty() { rows += text.len() as i32 + 1; }
+    rows += item_lines.len() as i32;
+
+    let panel_size = Size::new(content_width + 4, rows + 2);
+    let origin = Position::new((size.width - panel_size.width) / 2, (size.height - panel_size.height) / 2);
+
+    let horizontal = "═".repeat(panel_size.width as usize - 2);
+    let empty = format!("║{}║", " ".repeat(panel_size.width as usize - 2));
+
+    put_text(frame, origin, &format!("╔{}╗", horizontal), PANEL_BORDER_COLOR, 0);
+    for y in 1..panel_size.height - 1 {
+        put_text(frame, origin + Position::new(0, y), &empty, PANEL_BORDER_COLOR, 0);
+    }
+    put_text(frame, origin + Position::new(0, panel_size.height - 1), &format!("╚{}╝", horizontal), PANEL_BORDER_COLOR, 0);
+
+    let centered = |line: &String| origin.x + (panel_size.width - width_of(line) as i32) / 2;
+    let mut y = origin.y + 1;
+
+    // Lines of the heading keep their alignment, e.g. in the logo
+    let heading_width = heading.iter().map(width_of).max().unwrap_or(0) as i32;
+    let heading_x = origin.x + (panel_size.width - heading_width) / 2;
+
+    for line in heading {
+        put_text(frame, Position::new(heading_x, y), line, Color::magenta(), ATTR_BOLD);
+        y += 1;
+    }
+    y += 1;
+
+    if !text.is_empty() {
+        for line in text {
+            put_text(frame, Position::new(centered(line), y), line, Color::cyan(), 0);
+            y += 1;
+        }
+        y += 1;
+    }
+
+    for (index, line) in item_lines.iter().enumerate() {
+        if index == selected {
+            let marked = format!("> {} <", line);
+            put_text(frame, Position::new(centered(&marked), y), &marked, Color::yellow(), ATTR_BOLD);
+        } else {
+            put_text(frame, Position::new(centered(line), y), line, Color::white(), 0);
+        }
+        y += 1;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn game_over_then_continue() {
+        let mut game = Game::new(Size::new(100, 35));
+        game.process_action(Action::Confirm);
+        game.hero_controller.health = 1;
+
+        // Walking right ends on the spikes
+        for _ in 0..100 {
+            if game.hero_controller.health == 0 {
+                break;
+            }
+            game.process_action(Action::MoveRight);
+            game.render();
+        }
+        assert_eq!(game.hero_controller.health, 0);
+
+        // The game over comes when the wipe has covered the screen
+        let death_ticks = game.ticks;
+        while game.scenes.top() == Scene::Playing && game.ticks < death_ticks + 100 {
+            game.render();
+        }
+        assert_eq!(game.scenes.top(), Scene::GameOver);
+        assert_eq!(game.ticks - death_ticks, WIPE_TICKS);
+
+        // Title screen, then Continue
+        game.process_action(Action::MenuDown);
+        game.process_action(Action::Confirm);
+        assert_eq!(game.scenes.top(), Scene::Title);
+        assert_eq!(game.scenes.top().get_menu_items(game.has_progress)[0], MenuItem::Continue);
+        game.process_action(Action::Confirm);
+
+        assert_eq!(game.scenes.top(), Scene::Playing);
+        assert_eq!(game.hero_controller.health, HERO_MAX_HEALTH);
+        assert_eq!(game.get_save_data().hero_health, HERO_MAX_HEALTH);
+    }
+}
@@ -0,0 +1,138 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+pub const SETTINGS_VERSION: u32 = 1;
+const SETTINGS_HEADER: &str = "cybersem-settings";
+
+
+/// Preferences that stay when the progress is reset, kept apart from the save
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub bindings: KeyBindings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Self {
+            bindings: KeyBindings::new(),
+        }
+    }
+
+    /// The text format has a line per binding, the default bindings are used if there are none:
+    ///   cybersem-settings 1
+    ///   bind KeyA left
+    ///   bind ArrowLeft left
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![format!("{} {}", SETTINGS_HEADER, SETTINGS_VERSION)];
+
+        for (key, action) in &self.bindings.bindings {
+            lines.push(format!("bind {} {}", key.get_name(), action.get_code()));
+        }
+
+        lines.join("\n") + "\n"
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, SaveError> {
+        let mut lines = text.lines();
+        let header = lines.next().unwrap_or("");
+
+        let is_supported = header
+            .strip_prefix(SETTINGS_HEADER)
+            .and_then(|version| version.trim().parse::<u32>().ok())
+            .is_some_and(|version| version <= SETTINGS_VERSION);
+
+        if !is_supported {
+            return Err(SaveError::UnsupportedVersion(header.into()));
+        }
+
+        let mut bindings = KeyBindings::empty();
+
+        for (index, line) in lines.enumerate() {
+            let invalid = || SaveError::InvalidLine { line: index + 2, text: line.into() };
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            match words.as_slice() {
+                [] => {}
+
+                ["bind", key, code] => {
+                    let key = Key::from_name(key).ok_or_else(invalid)?;
+                    let action = Action::from_code(code)
+                        .filter(|action| BINDABLE_ACTIONS.contains(action))
+                        .ok_or_else(invalid)?;
+
+                    bindings.bind(key, action).map_err(|_| invalid())?;
+                }
+
+                // Settings of newer versions of the same format
+                _ => {}
+            }
+        }
+
+        if bindings.bindings.is_empty() {
+            bindings = KeyBindings::new();
+        }
+
+        Ok(Self { bindings })
+    }
+
+    /// Returns: None if nothing has been saved yet
+    pub fn load(storage: &impl SaveStorage) -> Result<Option<Self>, SaveError> {
+        match storage.read()? {
+            Some(text) => Self::from_text(&text).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save(&self, storage: &mut impl SaveStorage) -> Result<(), SaveError> {
+        storage.write(&self.to_text())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_round_trip() {
+        let mut settings = Settings::new();
+        settings.bindings.rebind(Action::Attack, Key::Letter('j')).unwrap();
+
+        assert_eq!(Settings::from_text(&settings.to_text()).unwrap(), settings);
+        assert_eq!(Settings::from_text("cybersem-settings 1\n").unwrap(), Settings::new());
+    }
+
+    #[test]
+    fn rejects_conflicts_and_unknown_keys() {
+        assert!(matches!(
+            Settings::from_text("cybersem-settings 1\nbind KeyA left\nbind KeyA right\n"),
+            Err(SaveError::InvalidLine { line: 3, .. })
+        ));
+        assert!(matches!(
+            Settings::from_text("cybersem-settings 1\nbind F13 left\n"),
+            Err(SaveError::InvalidLine { line: 2, .. })
+        ));
+        assert!(matches!(Settings::from_text("cybersem-settings 2\n"), Err(SaveError::UnsupportedVersion(_))));
+    }
+}
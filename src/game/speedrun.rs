@@ -0,0 +1,215 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+/// Splits faster than the best one
+const GOLD_COLOR: Color = Color::new(255, 200, 0);
+const BEHIND_COLOR: Color = Color::new(230, 40, 40);
+const TIMER_COLOR: Color = Color::white();
+const SPLIT_NAME_COLOR: Color = Color::new(160, 160, 160);
+
+/// Only the latest splits fit in the HUD
+const SHOWN_SPLITS: usize = 4;
+const SPLIT_NAME_WIDTH: usize = 16;
+
+
+/// Returns: the split reached by collecting a pickup with the given FLAG_*
+pub fn get_pickup_split(flags: u32) -> String {
+    match flags {
+        FLAG_DASH => "dash".into(),
+        FLAG_WALL_JUMP => "wall_jump".into(),
+        FLAG_LEDGE_GRAB => "ledge_grab".into(),
+        _ => format!("pickup_{}", flags),
+    }
+}
+
+/// Returns: the split reached by leaving the room or finishing the game in it
+pub fn get_room_split(room: usize) -> String {
+    format!("room_{}", room)
+}
+
+/// Returns: the name of a split for the HUD
+pub fn get_split_label(code: &str) -> String {
+    match code {
+        "dash" => "Dash chip".into(),
+        "wall_jump" => "Wall jump chip".into(),
+        "ledge_grab" => "Ledge grab chip".into(),
+        "boss" => "Boss".into(),
+        _ => match code.strip_prefix("room_").and_then(|room| room.parse::<usize>().ok()) {
+            Some(room) => format!("Room {}", room + 1),
+            None => code.into(),
+        },
+    }
+}
+
+/// Returns: "+S.hh" or "-M:SS.hh" for differences of a minute or more
+pub fn format_delta(ticks: i64) -> String {
+    let sign = if ticks < 0 { '-' } else { '+' };
+    let hundredths = ticks.unsigned_abs() * TICK_MILLIS as u64 / 10;
+
+    if hundredths >= 6000 {
+        format!("{}{}", sign, format_time(ticks.unsigned_abs() as u32, true))
+    } else {
+        format!("{}{}.{:02}", sign, hundredths / 100, hundredths % 100)
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Split {
+    /// E.g. "dash" or "room_0", see get_split_label
+    pub code: String,
+    /// Gameplay ticks since the start of the run
+    pub ticks: u32,
+    /// The best time of the split before this run, None if reached for the first time
+    pub previous_best: Option<u32>,
+}
+
+impl Split {
+    /// Returns: negative if ahead of the best time
+    pub fn get_delta(&self) -> Option<i64> {
+        self.previous_best.map(|best| self.ticks as i64 - best as i64)
+    }
+}
+
+
+/// Times the run by gameplay ticks and compares the splits with the best ones
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpeedrunTimer {
+    /// Splits of the current run in the order of reaching them
+    pub splits: Vec<Split>,
+    /// The best time of each split over all runs, in ticks
+    pub best_splits: Vec<(String, u32)>,
+    /// Whether the HUD shows the timer
+    pub is_visible: bool,
+}
+
+impl Default for SpeedrunTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpeedrunTimer {
+    pub fn new() -> Self {
+        Self {
+            splits: vec![],
+            best_splits: vec![],
+            is_visible: false,
+        }
+    }
+
+    /// Forgets the splits of the current run, the best ones stay
+    pub fn reset_run(&mut self) {
+        self.splits.clear();
+    }
+
+    pub fn get_best(&self, code: &str) -> Option<u32> {
+        self.best_splits.iter().find(|(best, _)| best == code).map(|(_, ticks)| *ticks)
+    }
+
+    /// Records the split unless it has already been reached in this run, e.g. before a death,
+    /// and makes it the best one if it is faster
+    pub fn split(&mut self, code: &str, ticks: u32) {
+        if self.splits.iter().any(|split| split.code == code) {
+            return;
+        }
+
+        let previous_best = self.get_best(code);
+
+        self.splits.push(Split { code: code.into(), ticks, previous_best });
+
+        match self.best_splits.iter_mut().find(|(best, _)| best == code) {
+            Some((_, best_ticks)) => *best_ticks = (*best_ticks).min(ticks),
+            None => self.best_splits.push((code.into(), ticks)),
+        }
+    }
+
+    /// Draws the latest splits and the running time in the top right corner
+    pub fn draw(&self, frame: &mut [Vec<Cell>], size: Size, ticks: u32) {
+        if !self.is_visible {
+            return;
+        }
+
+        let shown = &self.splits[self.splits.len().saturating_sub(SHOWN_SPLITS)..];
+        let mut lines: Vec<Vec<(String, Color, u32)>> = vec![];
+
+        for split in shown {
+            let mut line = vec![
+                (format!("{:width$}", get_split_label(&split.code), width = SPLIT_NAME_WIDTH), SPLIT_NAME_COLOR, 0),
+                (format!("{:>9}", format_time(split.ticks, true)), TIMER_COLOR, 0),
+            ];
+
+            if let Some(delta) = split.get_delta() {
+                let color = if delta <= 0 { GOLD_COLOR } else { BEHIND_COLOR };
+                line.push((format!(" {:>9}", format_delta(delta)), color, ATTR_BOLD));
+            }
+
+            lines.push(line);
+        }
+
+        lines.push(vec![(format_time(ticks, true), TIMER_COLOR, ATTR_BOLD)]);
+
+        let line_width = |line: &Vec<(String, Color, u32)>| {
+            line.iter().map(|(text, _, _)| text.chars().count() as i32).sum::<i32>()
+        };
+
+        for (y, line) in lines.iter().enumerate() {
+            // Right-aligned with a space from the edge
+            let mut x = size.width - 1 - line_width(line);
+
+            for (text, color, attrs) in line {
+                put_text(frame, Position::new(x, y as i32), text, *color, *attrs);
+                x += text.chars().count() as i32;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_formats() {
+        assert_eq!(format_delta(-40), "-1.20");
+        assert_eq!(format_delta(0), "+0.00");
+        assert_eq!(format_delta(2100), "+1:03.00");
+    }
+
+    #[test]
+    fn splits_compare_with_the_best() {
+        let mut timer = SpeedrunTimer::new();
+        timer.split("dash", 300);
+        timer.split("dash", 200);
+        assert_eq!(timer.splits.len(), 1);
+        assert_eq!(timer.splits[0].get_delta(), None);
+
+        timer.reset_run();
+        timer.split("dash", 250);
+        timer.split("boss", 900);
+        assert_eq!(timer.splits[0].get_delta(), Some(-50));
+        assert_eq!(timer.get_best("dash"), Some(250));
+
+        timer.reset_run();
+        timer.split("dash", 260);
+        assert_eq!(timer.splits[0].get_delta(), Some(10));
+        assert_eq!(timer.get_best("dash"), Some(250));
+        assert_eq!(timer.get_best("boss"), Some(900));
+    }
+}
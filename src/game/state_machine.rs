@@ -0,0 +1,278 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Called with the owner of the state machine when a state is entered or exited
+pub type StateHook<T> = fn(&mut T);
+
+/// A transition is only allowed if its guard returns true
+pub type StateGuard<T> = fn(&T) -> bool;
+
+/// The exit hook of the current state and the enter hook of the next one
+pub type TransitionHooks<T> = (Option<StateHook<T>>, Option<StateHook<T>>);
+
+
+/// The owner is expected to play the animation clip named after the state
+pub struct State<T> {
+    pub name: &'static str,
+    pub on_enter: Option<StateHook<T>>,
+    pub on_exit: Option<StateHook<T>>,
+}
+
+struct Transition<T> {
+    /// None means any state
+    from: Option<StateID>,
+    to: StateID,
+    guard: Option<StateGuard<T>>,
+}
+
+
+#[derive(Debug)]
+pub enum StateError {
+    /// The entity does not have states at all
+    NoStates,
+    UnknownState(StateID),
+    NotAllowed { from: &'static str, to: &'static str },
+    Rejected { from: &'static str, to: &'static str },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoStates => write!(f, "entity has no states"),
+            Self::UnknownState(id) => write!(f, "unknown state #{}", id),
+            Self::NotAllowed { from, to } => write!(f, "transition {} -> {} is not allowed", from, to),
+            Self::Rejected { from, to } => write!(f, "transition {} -> {} was rejected by its guard", from, to),
+        }
+    }
+}
+
+impl StateError {
+    /// Returns: whether the owner has simply stayed in its state,
+    /// as opposed to the states not being set up
+    pub fn is_refusal(&self) -> bool {
+        matches!(self, Self::NotAllowed { .. } | Self::Rejected { .. })
+    }
+}
+
+
+/// Named states and the transitions allowed between them.
+/// T is the owner, which is passed to guards and hooks.
+pub struct StateMachine<T> {
+    states: HashMap<StateID, State<T>>,
+    transitions: Vec<Transition<T>>,
+    current: StateID,
+}
+
+impl<T> StateMachine<T> {
+    pub fn new() -> Self {
+        Self {
+            states: HashMap::new(),
+            transitions: vec![],
+            current: 0,
+        }
+    }
+
+    pub fn add_state(&mut self, id: StateID, name: &'static str) {
+        self.states.insert(id, State {
+            name,
+            on_enter: None,
+            on_exit: None,
+        });
+    }
+
+    pub fn has_states(&self) -> bool {
+        !self.states.is_empty()
+    }
+
+    pub fn get_state(&self, id: StateID) -> Result<&State<T>, StateError> {
+        self.states.get(&id).ok_or(StateError::UnknownState(id))
+    }
+
+    pub fn get_state_mut(&mut self, id: StateID) -> Result<&mut State<T>, StateError> {
+        self.states.get_mut(&id).ok_or(StateError::UnknownState(id))
+    }
+
+    pub fn get_current(&self) -> StateID {
+        self.current
+    }
+
+    pub fn get_current_state(&self) -> &State<T> {
+        &self.states[&self.current]
+    }
+
+    /// Sets the initial state without checking transitions or calling hooks
+    pub fn start(&mut self, id: StateID) -> Result<(), StateError> {
+        self.get_state(id)?;
+        self.current = id;
+        Ok(())
+    }
+
+    /// Arguments:
+    /// from - None allows the transition from any state
+    pub fn allow(&mut self, from: Option<StateID>, to: StateID, guard: Option<StateGuard<T>>) {
+        self.transitions.push(Transition { from, to, guard });
+    }
+
+    /// Allows transitions from every state in `from` to every state in `to`
+    pub fn allow_all(&mut self, from: &[StateID], to: &[StateID]) {
+        for from_id in from {
+            for to_id in to {
+                self.allow(Some(*from_id), *to_id, None);
+            }
+        }
+    }
+
+    /// Checks whether the owner may go to the given state.
+    /// Returns: the exit hook of the current state and the enter hook of the new state,
+    /// or None if the owner already is in the given state
+    pub fn check_transition(&self, to: StateID, owner: &T) -> Result<Option<TransitionHooks<T>>, StateError> {
+        let to_state = self.get_state(to)?;
+        let from_state = self.get_state(self.current)?;
+
+        if to == self.current {
+            return Ok(None);
+        }
+
+        let mut rejected = false;
+
+        for transition in &self.transitions {
+            if transition.to != to {
+                continue;
+            }
+
+            if transition.from.is_some() && transition.from != Some(self.current) {
+                continue;
+            }
+
+            match transition.guard {
+                Some(guard) if !guard(owner) => rejected = true,
+                _ => return Ok(Some((from_state.on_exit, to_state.on_enter))),
+            }
+        }
+
+        if rejected {
+            Err(StateError::Rejected { from: from_state.name, to: to_state.name })
+        } else {
+            Err(StateError::NotAllowed { from: from_state.name, to: to_state.name })
+        }
+    }
+
+    /// Must only be called after a successful check_transition()
+    pub fn finish_transition(&mut self, to: StateID) {
+        self.current = to;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOSED: StateID = 0;
+    const OPEN: StateID = 1;
+    const LOCKED: StateID = 2;
+
+    /// The hooks append their digits to position.x: 1 for entering OPEN, 2 for exiting it
+    fn log(door: &AnimatableEntity) -> i32 {
+        door.figure.position.x
+    }
+
+    /// A door that can only be unlocked with the key, which is the defeatable flag
+    fn door() -> AnimatableEntity {
+        let mut door = AnimatableEntity::new(Position::origin());
+        let states = &mut door.states;
+
+        states.add_state(CLOSED, "closed");
+        states.add_state(OPEN, "open");
+        states.add_state(LOCKED, "locked");
+
+        states.allow_all(&[CLOSED], &[OPEN, LOCKED]);
+        states.allow(Some(OPEN), CLOSED, None);
+        states.allow(Some(LOCKED), CLOSED, Some(|door: &AnimatableEntity| door.defeatable));
+
+        states.get_state_mut(OPEN).unwrap().on_enter = Some(|door| door.figure.position.x = log(door) * 10 + 1);
+        states.get_state_mut(OPEN).unwrap().on_exit = Some(|door| door.figure.position.x = log(door) * 10 + 2);
+
+        door.start(CLOSED).unwrap();
+        door
+    }
+
+    #[test]
+    fn allowed_transitions_call_hooks() {
+        let mut door = door();
+
+        door.set_state(OPEN).unwrap();
+        assert_eq!(door.states.get_current_state().name, "open");
+
+        // Staying in the same state calls nothing
+        door.set_state(OPEN).unwrap();
+
+        door.set_state(CLOSED).unwrap();
+        assert_eq!(log(&door), 12);
+    }
+
+    #[test]
+    fn rejected_and_unknown_transitions() {
+        let mut door = door();
+
+        door.set_state(OPEN).unwrap();
+        assert!(matches!(door.set_state(LOCKED), Err(StateError::NotAllowed { from: "open", to: "locked" })));
+        assert!(matches!(door.set_state(7), Err(StateError::UnknownState(7))));
+        assert_eq!(door.get_state(), OPEN);
+
+        door.set_state(CLOSED).unwrap();
+        door.set_state(LOCKED).unwrap();
+
+        let error = door.set_state(CLOSED).unwrap_err();
+        assert!(matches!(error, StateError::Rejected { from: "locked", to: "closed" }));
+        assert!(error.is_refusal());
+        assert_eq!(door.get_state(), LOCKED);
+
+        door.defeatable = true;
+        door.set_state(CLOSED).unwrap();
+        assert_eq!(door.get_state(), CLOSED);
+        assert_eq!(log(&door), 12);
+    }
+
+    #[test]
+    fn transitions_from_any_state() {
+        let mut door = door();
+        door.states.allow(None, LOCKED, None);
+
+        door.set_state(OPEN).unwrap();
+        door.set_state(LOCKED).unwrap();
+        assert_eq!(door.get_state(), LOCKED);
+
+        // start() skips the checks and the hooks
+        door.start(OPEN).unwrap();
+        assert_eq!(log(&door), 12);
+        assert!(door.start(7).is_err());
+    }
+
+    #[test]
+    fn entities_without_states() {
+        let mut sign: Box<dyn Entity> = Box::new(StaticEntity::new("sign".into(), Color::white(), Position::origin()));
+        let error = sign.set_state(OPEN).unwrap_err();
+
+        assert!(matches!(error, StateError::NoStates));
+        assert!(!error.is_refusal());
+    }
+}
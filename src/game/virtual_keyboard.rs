@@ -0,0 +1,208 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+/// Letters of the rows, like on a physical keyboard
+const KEYBOARD_ROWS: [&str; 3] = ["qwertyuiop", "asdfghjkl", "zxcvbnm"];
+/// Each row starts this much further to the right than the previous one
+const ROW_STAGGER: i32 = 2;
+const KEY_GAP: i32 = 1;
+
+const KEY_COLOR: Color = Color::new(0, 200, 200);
+const KEY_BACKGROUND_COLOR: Color = Color::new(20, 50, 70);
+const SELECTED_KEY_COLOR: Color = Color::black();
+const SELECTED_KEY_BACKGROUND_COLOR: Color = Color::yellow();
+const HINT_COLOR: Color = Color::new(160, 160, 160);
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VirtualKey {
+    Letter(char),
+    Backspace,
+    /// Casts the spell
+    Enter,
+}
+
+impl VirtualKey {
+    /// Returns: the label with the padding, which is the width of the key
+    pub fn get_label(&self) -> String {
+        match self {
+            Self::Letter(letter) => format!(" {} ", letter),
+            Self::Backspace => " DEL ".into(),
+            Self::Enter => " CAST ".into(),
+        }
+    }
+
+    /// Returns: the action of the same key of a physical keyboard
+    pub fn get_action(&self) -> Action {
+        match self {
+            Self::Letter(letter) => Action::Letter(*letter),
+            Self::Backspace => Action::Backspace,
+            Self::Enter => Action::Enter,
+        }
+    }
+}
+
+
+/// Lets gamepads and touch screens type spells: directions choose a key, and a single button presses it
+pub struct VirtualKeyboard {
+    /// Index of the key in the row and index of the row
+    pub selected: Position,
+}
+
+impl Default for VirtualKeyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualKeyboard {
+    pub fn new() -> Self {
+        Self { selected: Position::origin() }
+    }
+
+    /// Returns: the keys of each row with their offsets from the left edge of the keyboard
+    pub fn get_rows() -> Vec<Vec<(i32, VirtualKey)>> {
+        let row_ends = [Some(VirtualKey::Backspace), Some(VirtualKey::Enter), None];
+
+        KEYBOARD_ROWS
+            .iter()
+            .zip(row_ends)
+            .enumerate()
+            .map(|(y, (letters, end))| {
+                let mut x = y as i32 * ROW_STAGGER;
+
+                letters.chars()
+                    .map(VirtualKey::Letter)
+                    .chain(end)
+                    .map(|key| {
+                        let key_x = x;
+                        x += key.get_label().chars().count() as i32 + KEY_GAP;
+                        (key_x, key)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    pub fn get_selected_key(&self) -> VirtualKey {
+        let rows = Self::get_rows();
+        rows[self.selected.y as usize][self.selected.x as usize].1
+    }
+
+    /// Wraps around the edges, moving to a shorter row keeps the selection on its last key
+    ///
+    /// Arguments:
+    /// step - -1, 0 or 1 along each axis
+    pub fn move_selection(&mut self, step: Position) {
+        let rows = Self::get_rows();
+
+        if step.y != 0 {
+            self.selected.y = (self.selected.y + step.y).rem_euclid(rows.len() as i32);
+            self.selected.x = self.selected.x.min(rows[self.selected.y as usize].len() as i32 - 1);
+        }
+
+        if step.x != 0 {
+            let row_length = rows[self.selected.y as usize].len() as i32;
+            self.selected.x = (self.selected.x + step.x).rem_euclid(row_length);
+        }
+    }
+
+    /// Arguments:
+    /// offset - of the cell from the top left corner of the keyboard
+    pub fn get_key_at(offset: Position) -> Option<VirtualKey> {
+        let rows = Self::get_rows();
+        let row = rows.get(usize::try_from(offset.y).ok()?)?;
+
+        row.iter()
+            .find(|(x, key)| (*x..*x + key.get_label().chars().count() as i32).contains(&offset.x))
+            .map(|(_, key)| *key)
+    }
+
+    /// Draws the keys with the top left corner at the position and the hint to the right of the first row
+    pub fn draw(&self, frame: &mut [Vec<Cell>], position: Position) {
+        let mut hint_x = 0;
+
+        for (y, row) in Self::get_rows().iter().enumerate() {
+            for (index, (x, key)) in row.iter().enumerate() {
+                let is_selected = self.selected == Position::new(index as i32, y as i32);
+                let (fg, bg) = if is_selected {
+                    (SELECTED_KEY_COLOR, SELECTED_KEY_BACKGROUND_COLOR)
+                } else {
+                    (KEY_COLOR, KEY_BACKGROUND_COLOR)
+                };
+
+                let label = key.get_label();
+                let attrs = if is_selected { ATTR_BOLD } else { 0 };
+
+                // Keys have their own background, so that the gaps separate them
+                for (offset, glyph) in label.chars().enumerate() {
+                    let cell_position = position + Position::new(x + offset as i32, y as i32);
+
+                    if cell_position.x < 0 || cell_position.y < 0 {
+                        continue;
+                    }
+
+                    if let Some(cell) = frame.get_mut(cell_position.y as usize)
+                        .and_then(|row| row.get_mut(cell_position.x as usize))
+                    {
+                        *cell = Cell { glyph, fg, bg: Some(bg), attrs };
+                    }
+                }
+
+                if y == 0 {
+                    hint_x = x + label.chars().count() as i32;
+                }
+            }
+        }
+
+        put_text(frame, position + Position::new(hint_x + 2, 0), VIRTUAL_KEYBOARD_HINT, HINT_COLOR, 0);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn navigation_wraps_and_clamps() {
+        let mut keyboard = VirtualKeyboard::new();
+        assert_eq!(keyboard.get_selected_key(), VirtualKey::Letter('q'));
+
+        keyboard.move_selection(Position::new(-1, 0));
+        assert_eq!(keyboard.get_selected_key(), VirtualKey::Backspace);
+
+        // The rows below are shorter
+        keyboard.move_selection(Position::new(0, 1));
+        assert_eq!(keyboard.get_selected_key(), VirtualKey::Enter);
+        keyboard.move_selection(Position::new(0, 1));
+        assert_eq!(keyboard.get_selected_key(), VirtualKey::Letter('m'));
+
+        keyboard.move_selection(Position::new(0, 1));
+        assert_eq!(keyboard.get_selected_key(), VirtualKey::Letter('u'));
+    }
+
+    #[test]
+    fn keys_at_cells() {
+        assert_eq!(VirtualKeyboard::get_key_at(Position::new(0, 0)), Some(VirtualKey::Letter('q')));
+        assert_eq!(VirtualKeyboard::get_key_at(Position::new(3, 0)), None);
+        assert_eq!(VirtualKeyboard::get_key_at(Position::new(4, 2)), Some(VirtualKey::Letter('z')));
+        assert_eq!(VirtualKeyboard::get_key_at(Position::new(40, 0)), Some(VirtualKey::Backspace));
+        assert_eq!(VirtualKeyboard::get_key_at(Position::new(0, 3)), None);
+    }
+}
@@ -0,0 +1,177 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::game::*;
+
+/// One step of a scripted play-through
+#[derive(Clone, Copy, Debug)]
+pub enum Step {
+    /// Applies the action once, e.g. a letter of a spell
+    Press(Action),
+    /// Applies the action before each of the given number of ticks, as if the key was held
+    Hold(Action, u32),
+    /// Advances the game without input
+    Wait(u32),
+    /// Types each letter with Press
+    Type(&'static str),
+    /// Saves the current frame as text
+    Capture,
+}
+
+/// Runs the game without a screen or a keyboard, e.g. for tests
+pub struct GameRunner {
+    pub game: Box<Game>,
+    /// Frames saved by Step::Capture, as returned by dump_frame
+    pub captures: Vec<String>,
+}
+
+impl GameRunner {
+    pub fn new(size: Size) -> Self {
+        Self {
+            game: Box::new(Game::new(size)),
+            captures: vec![],
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.game.render();
+    }
+
+    /// Plays the replay in a new game
+    /// Returns: the runner at the end of the replay or the first desync
+    pub fn play(replay: Replay) -> Result<Self, ReplayError> {
+        let mut runner = Self {
+            game: Box::new(Game::from_replay(&replay)),
+            captures: vec![],
+        };
+        let mut player = ReplayPlayer::new(replay);
+
+        while !player.is_finished(&runner.game) {
+            player.apply_events(&mut runner.game);
+            runner.tick();
+            player.verify(&runner.game)?;
+        }
+
+        Ok(runner)
+    }
+
+    pub fn run(&mut self, steps: &[Step]) {
+        for step in steps {
+            match *step {
+                Step::Press(action) => self.game.process_action(action),
+
+                Step::Hold(action, ticks) => {
+                    for _ in 0..ticks {
+                        self.game.process_action(action);
+                        self.tick();
+                    }
+                }
+
+                Step::Wait(ticks) => {
+                    for _ in 0..ticks {
+                        self.tick();
+                    }
+                }
+
+                Step::Type(text) => {
+                    for letter in text.chars() {
+                        self.game.process_action(Action::Letter(letter));
+                    }
+                }
+
+                Step::Capture => {
+                    let dump = self.dump_frame();
+                    self.captures.push(dump);
+                }
+            }
+        }
+    }
+
+    /// Returns: the frame as text, in three parts separated by blank lines:
+    /// the glyphs, a style key for each cell and the styles of the keys.
+    /// Rows are framed with '|', so that trailing spaces survive editors.
+    /// Invisible cells have the style key '.'.
+    pub fn dump_frame(&self) -> String {
+        let mut styles: Vec<(Color, Option<Color>, u32)> = vec![];
+        let mut glyphs = String::new();
+        let mut keys = String::new();
+
+        for row in &self.game.frame {
+            glyphs.push('|');
+            keys.push('|');
+
+            for cell in row {
+                glyphs.push(cell.glyph);
+
+                let is_invisible = cell.glyph == ' ' && cell.bg.is_none() && !cell.has_attr(ATTR_UNDERLINE);
+
+                if is_invisible {
+                    keys.push('.');
+                    continue;
+                }
+
+                let style = (cell.fg, cell.bg, cell.attrs);
+                let index = match styles.iter().position(|known| *known == style) {
+                    Some(index) => index,
+                    None => {
+                        styles.push(style);
+                        styles.len() - 1
+                    }
+                };
+
+                keys.push(Self::get_style_key(index));
+            }
+
+            glyphs.push_str("|\n");
+            keys.push_str("|\n");
+        }
+
+        let mut legend = String::new();
+
+        for (index, (fg, bg, attrs)) in styles.iter().enumerate() {
+            legend.push_str(&format!("{} {}", Self::get_style_key(index), Self::hex_color(*fg)));
+
+            if let Some(bg) = bg {
+                legend.push_str(&format!(" on {}", Self::hex_color(*bg)));
+            }
+
+            for (attr, name) in [(ATTR_BOLD, "bold"), (ATTR_DIM, "dim"), (ATTR_UNDERLINE, "underline"), (ATTR_BLINK, "blink")] {
+                if attrs & attr != 0 {
+                    legend.push(' ');
+                    legend.push_str(name);
+                }
+            }
+
+            legend.push('\n');
+        }
+
+        format!("tick {}\n{}\n{}\n{}", self.game.ticks, glyphs, keys, legend)
+    }
+
+    /// Letters and digits first, then whatever comes after Latin-1 punctuation
+    fn get_style_key(index: usize) -> char {
+        const KEYS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+        match KEYS.chars().nth(index) {
+            Some(key) => key,
+            None => char::from_u32(0xc0 + (index - KEYS.len()) as u32).unwrap_or('?'),
+        }
+    }
+
+    fn hex_color(color: Color) -> String {
+        format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+    }
+}
@@ -0,0 +1,276 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Compares frames of scripted play-throughs with the files in tests/golden.
+//! After an intended change of the picture, run the tests with UPDATE_GOLDEN=1
+//! for both backends and review the difference of the files.
+//! Some texts differ between the backends, so each one has its own directory.
+
+use crate::game::*;
+
+use super::{GameRunner, Step, Step::*};
+
+use std::{env, fs, path::PathBuf};
+
+#[cfg(feature = "terminal_backend")]
+const BACKEND: &str = "terminal";
+
+#[cfg(feature = "wasm_backend")]
+const BACKEND: &str = "browser";
+
+/// Same as the browser
+const SCREEN_SIZE: Size = Size { width: 100, height: 35 };
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(BACKEND)
+        .join(format!("{}.txt", name))
+}
+
+fn check_golden(name: &str, size: Size, steps: &[Step]) {
+    let mut runner = GameRunner::new(size);
+    runner.run(steps);
+
+    check_captures(name, &runner.captures);
+}
+
+fn check_captures(name: &str, captures: &[String]) {
+    let actual = captures.join("\n");
+    let path = golden_path(name);
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("{} is missing, run with UPDATE_GOLDEN=1 to create it", path.display()));
+
+    if actual == expected {
+        return;
+    }
+
+    let line = actual.lines()
+        .zip(expected.lines())
+        .position(|(actual, expected)| actual != expected)
+        .unwrap_or_else(|| actual.lines().count().min(expected.lines().count()));
+
+    panic!(
+        "{} differs at line {}:\nexpected: {}\nactual:   {}",
+        path.display(),
+        line + 1,
+        expected.lines().nth(line).unwrap_or("<end>"),
+        actual.lines().nth(line).unwrap_or("<end>")
+    );
+}
+
+/// The first item of the title menu when there is no progress
+const NEW_GAME: Step = Press(Action::Confirm);
+
+/// Lets the wipe uncover the room after the gameplay starts
+const UNWIPE: Step = Wait(15);
+
+#[test]
+fn title_screen() {
+    check_golden("title_screen", SCREEN_SIZE, &[
+        Wait(1),
+        Capture,
+    ]);
+}
+
+#[test]
+fn tutorial_screen() {
+    check_golden("tutorial_screen", SCREEN_SIZE, &[
+        NEW_GAME,
+        UNWIPE,
+        Capture,
+    ]);
+}
+
+#[test]
+fn jump_arc() {
+    check_golden("jump_arc", SCREEN_SIZE, &[
+        NEW_GAME,
+        // Fall onto the floor first
+        Wait(30),
+        Hold(Action::Jump, 3),
+        Capture,
+        Wait(3),
+        Capture,
+        Wait(6),
+        Capture,
+        Wait(20),
+        Capture,
+    ]);
+}
+
+/// Walking right from the spawn point hits the first spikes at this tick
+const SPIKE_DEATH_TICKS: u32 = 19;
+
+#[test]
+fn spike_death() {
+    check_golden("spike_death", SCREEN_SIZE, &[
+        NEW_GAME,
+        Wait(30),
+        Hold(Action::MoveRight, SPIKE_DEATH_TICKS),
+        Capture,
+        Wait(3),
+        Capture,
+    ]);
+}
+
+#[test]
+fn boss_spell() {
+    let mut runner = GameRunner::new(SCREEN_SIZE);
+
+    // At the entrance of the boss room
+    runner.game.load_save_data(&SaveData {
+        has_progress: true,
+        room: 1,
+        hero_position: Position::new(0, 26),
+        ..SaveData::new()
+    });
+
+    runner.run(&[
+        // Continue
+        Press(Action::Confirm),
+        Wait(30),
+        Press(Action::StartSpell),
+        Type("android"),
+        Wait(1),
+        Capture,
+        Press(Action::Enter),
+        Wait(1),
+        Capture,
+        Wait(30),
+        Capture,
+    ]);
+
+    check_captures("boss_spell", &runner.captures);
+}
+
+#[test]
+fn pause_menu() {
+    // The tick stays the same while paused
+    check_golden("pause_menu", SCREEN_SIZE, &[
+        NEW_GAME,
+        Wait(30),
+        Press(Action::Pause),
+        Wait(10),
+        Capture,
+        // To the title screen, which can continue the game now
+        Press(Action::MenuDown),
+        Press(Action::Confirm),
+        Wait(1),
+        Capture,
+    ]);
+}
+
+#[test]
+fn ending() {
+    let mut runner = GameRunner::new(SCREEN_SIZE);
+
+    // Past the boss, a few steps before the end sign
+    runner.game.load_save_data(&SaveData {
+        has_progress: true,
+        room: 1,
+        hero_position: Position::new(65, 26),
+        is_boss_defeated: true,
+        stats: Stats { play_ticks: 4321, deaths: 3, spells_cast: 1, enemies_defeated: 3 },
+        ..SaveData::new()
+    });
+
+    runner.run(&[
+        // Continue
+        Press(Action::Confirm),
+        Hold(Action::MoveRight, 20),
+        Capture,
+        Wait(60),
+        Capture,
+        Wait(200),
+        Capture,
+    ]);
+
+    check_captures("ending", &runner.captures);
+}
+
+#[test]
+fn speedrun_splits() {
+    let mut runner = GameRunner::new(SCREEN_SIZE);
+
+    // Behind the best time at the dash chip, ahead at the wall jump one
+    runner.game.load_save_data(&SaveData {
+        has_progress: true,
+        hero_position: Position::new(45, 26),
+        collected_pickups: vec![0],
+        stats: Stats { play_ticks: 600, ..Stats::new() },
+        speedrun: SpeedrunTimer {
+            splits: vec![Split { code: "dash".into(), ticks: 400, previous_best: Some(380) }],
+            best_splits: vec![("dash".into(), 380), ("wall_jump".into(), 700)],
+            is_visible: true,
+        },
+        ..SaveData::new()
+    });
+
+    runner.run(&[
+        // Continue
+        Press(Action::Confirm),
+        Hold(Action::MoveRight, 20),
+        Capture,
+    ]);
+
+    check_captures("speedrun_splits", &runner.captures);
+}
+
+#[test]
+fn controls_menu() {
+    let down = |count| vec![Press(Action::MenuDown); count];
+
+    let steps: Vec<Step> = [
+        // Title screen > Controls > Attack
+        down(1),
+        vec![Press(Action::Confirm)],
+        down(4),
+        vec![Press(Action::Confirm), Press(Action::BindKey(Key::Letter('j')))],
+        // W is taken by the jump
+        down(1),
+        vec![Press(Action::Confirm), Press(Action::BindKey(Key::Letter('w'))), Wait(1), Capture],
+        // The tutorial sign shows the new key
+        vec![Press(Action::Back), NEW_GAME, UNWIPE, Capture],
+    ].concat();
+
+    check_golden("controls_menu", SCREEN_SIZE, &steps);
+}
+
+#[test]
+fn virtual_keyboard() {
+    check_golden("virtual_keyboard", SCREEN_SIZE, &[
+        NEW_GAME,
+        UNWIPE,
+        // From 'q' to 's', then to the cast key
+        Press(Action::StartSpell),
+        Press(Action::MenuDown),
+        Press(Action::MenuRight),
+        Press(Action::Confirm),
+        Press(Action::MenuLeft),
+        Press(Action::MenuLeft),
+        Wait(1),
+        Capture,
+    ]);
+}
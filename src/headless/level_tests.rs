@@ -0,0 +1,64 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+//! Checks that the level cannot be passed without the abilities it needs
+
+use crate::game::*;
+
+use super::{GameRunner, Step, Step::*};
+
+const SCREEN_SIZE: Size = Size { width: 100, height: 35 };
+
+/// Returns: the state after running and jumping at the firewall in the first room for a while
+fn run_at_firewall(progression_flags: u32) -> SaveData {
+    let mut runner = GameRunner::new(SCREEN_SIZE);
+
+    runner.game.load_save_data(&SaveData {
+        has_progress: true,
+        hero_position: Position::new(160, 26),
+        progression_flags,
+        ..SaveData::new()
+    });
+
+    // Continue
+    runner.run(&[Press(Action::Confirm)]);
+
+    // Jumping while holding the direction also climbs onto a grabbed ledge
+    let step: [Step; 2] = [Press(Action::Jump), Hold(Action::MoveRight, 1)];
+    for _ in 0..80 {
+        runner.run(&step);
+    }
+
+    runner.game.get_save_data()
+}
+
+#[test]
+fn firewall_needs_the_ledge_grab() {
+    assert_eq!(run_at_firewall(0).room, 0);
+    assert_eq!(run_at_firewall(FLAG_DASH | FLAG_WALL_JUMP).room, 0);
+    assert_eq!(run_at_firewall(FLAG_LEDGE_GRAB).room, 1);
+}
+
+#[test]
+fn leaving_a_room_records_a_split() {
+    assert!(run_at_firewall(0).speedrun.splits.is_empty());
+
+    let splits = run_at_firewall(FLAG_LEDGE_GRAB).speedrun.splits;
+    assert_eq!(splits.len(), 1);
+    assert_eq!(splits[0].code, get_room_split(0));
+    assert_eq!(splits[0].previous_best, None);
+}
@@ -0,0 +1,6 @@
+mod game_runner;
+mod golden_tests;
+mod level_tests;
+mod replay_tests;
+
+pub use game_runner::*;
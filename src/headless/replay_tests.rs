@@ -0,0 +1,69 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::game::*;
+
+use super::{GameRunner, Step, Step::*};
+
+const SCREEN_SIZE: Size = Size { width: 100, height: 35 };
+
+/// Starts a new game, walks, jumps onto the spikes, resizes the screen and casts a spell
+const PLAY_THROUGH: [Step; 10] = [
+    Press(Action::Confirm),
+    Wait(10),
+    Hold(Action::MoveRight, 12),
+    Hold(Action::Jump, 4),
+    Hold(Action::MoveRight, 20),
+    Press(Action::StartSpell),
+    Type("android"),
+    Press(Action::Enter),
+    Wait(40),
+    Capture,
+];
+
+fn record() -> (Replay, String) {
+    let mut runner = GameRunner::new(SCREEN_SIZE);
+    runner.game.start_recording();
+    runner.run(&PLAY_THROUGH[..5]);
+    runner.game.set_size(Size::new(90, 32));
+    runner.run(&PLAY_THROUGH[5..]);
+
+    (runner.game.take_recording().unwrap(), runner.captures.pop().unwrap())
+}
+
+#[test]
+fn replay_reproduces_the_play_through() {
+    let (replay, last_frame) = record();
+    assert_eq!(replay.checksums.len(), 2);
+
+    let replay = Replay::from_text(&replay.to_text()).unwrap();
+    let played = GameRunner::play(replay).unwrap();
+
+    assert_eq!(played.dump_frame(), last_frame);
+}
+
+#[test]
+fn replay_detects_desync() {
+    let (mut replay, _) = record();
+
+    // The hero does not start walking, so the state differs from the first checksum after it
+    replay.events.retain(|(_, event)| *event != ReplayEvent::Action(Action::MoveRight));
+
+    match GameRunner::play(replay) {
+        Err(ReplayError::Desync { tick, .. }) => assert_eq!(tick, 30),
+        result => panic!("expected a desync, got {:?}", result.err()),
+    }
+}
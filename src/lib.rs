@@ -4,7 +4,9 @@
 #![cfg(feature="wasm_backend")]
 
 pub mod game;
-pub mod special_key_codes;
+
+#[cfg(test)]
+mod headless;
 
 mod wasm;
 pub use wasm::GameRunner;
\ No newline at end of file
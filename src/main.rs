@@ -1,10 +1,11 @@
 #![allow(unused)]
 #![allow(dead_code)]
 #![cfg(feature="terminal_backend")]
-#![deprecated]
 
 pub mod game;
-pub mod special_key_codes;
+
+#[cfg(test)]
+mod headless;
 
 mod terminal;
 use terminal::GameRunner;
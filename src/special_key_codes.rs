@@ -1,2 +0,0 @@
-pub const KEY_BACKSPACE: char = '`';
-pub const KEY_ENTER: char = '\n';
\ No newline at end of file
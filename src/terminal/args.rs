@@ -0,0 +1,33 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/// Returns: the value of "--name VALUE" or "--name=VALUE" in the command line arguments
+pub fn get_flag_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let flag = format!("--{}", name);
+    let prefix = format!("--{}=", name);
+
+    let position = args.iter().position(|arg| *arg == flag || arg.starts_with(&prefix))?;
+    let arg = &args[position];
+
+    match arg.strip_prefix(&prefix) {
+        Some(value) => Some(value),
+        None => args.get(position + 1).map(String::as_str),
+    }
+}
+
+pub fn has_flag(args: &[String], name: &str) -> bool {
+    args.iter().any(|arg| *arg == format!("--{}", name))
+}
@@ -0,0 +1,253 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::game::Color;
+
+use super::args::get_flag_value;
+
+use crossterm::style;
+
+/// Channel values of the 6x6x6 cube of the 256-colour palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The 16 basic colours as xterm shows them by default
+const ANSI_16_COLORS: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// Same order as ANSI_16_COLORS
+const ANSI_16_NAMES: [style::Color; 16] = [
+    style::Color::Black,
+    style::Color::DarkRed,
+    style::Color::DarkGreen,
+    style::Color::DarkYellow,
+    style::Color::DarkBlue,
+    style::Color::DarkMagenta,
+    style::Color::DarkCyan,
+    style::Color::Grey,
+    style::Color::DarkGrey,
+    style::Color::Red,
+    style::Color::Green,
+    style::Color::Yellow,
+    style::Color::Blue,
+    style::Color::Magenta,
+    style::Color::Cyan,
+    style::Color::White,
+];
+
+/// How many colours the terminal can show
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorDepth {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+    /// Only attributes, the terminal's default colours
+    Monochrome,
+}
+
+impl ColorDepth {
+    /// Uses the --colors flag if given, the environment otherwise
+    pub fn detect() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+
+        if let Some(depth) = Self::from_args(&args) {
+            return depth;
+        }
+
+        let var = |name| std::env::var(name).ok();
+
+        if var("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return Self::Monochrome;
+        }
+
+        Self::from_environment(var("COLORTERM").as_deref(), var("TERM").as_deref())
+    }
+
+    /// Accepts "--colors VALUE" and "--colors=VALUE"
+    pub fn from_args(args: &[String]) -> Option<Self> {
+        Self::parse(get_flag_value(args, "colors")?)
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "truecolor" | "24bit" | "rgb" => Some(Self::TrueColor),
+            "256" => Some(Self::Ansi256),
+            "16" => Some(Self::Ansi16),
+            "mono" | "monochrome" | "none" => Some(Self::Monochrome),
+            _ => None,
+        }
+    }
+
+    /// Arguments:
+    /// colorterm, term - values of the COLORTERM and TERM variables
+    pub fn from_environment(colorterm: Option<&str>, term: Option<&str>) -> Self {
+        if matches!(colorterm, Some("truecolor") | Some("24bit")) {
+            return Self::TrueColor;
+        }
+
+        match term {
+            Some(term) if term.contains("truecolor") || term.contains("direct") => Self::TrueColor,
+            Some(term) if term.contains("256color") => Self::Ansi256,
+            Some("dumb") => Self::Monochrome,
+            _ => Self::Ansi16,
+        }
+    }
+
+    /// Returns: None if the terminal's default colour should be used
+    pub fn convert(&self, color: Color) -> Option<style::Color> {
+        match self {
+            Self::TrueColor => Some(style::Color::Rgb { r: color.r, g: color.g, b: color.b }),
+            Self::Ansi256 => Some(style::Color::AnsiValue(to_ansi_256(color))),
+            Self::Ansi16 => Some(ANSI_16_NAMES[to_ansi_16(color) as usize]),
+            Self::Monochrome => None,
+        }
+    }
+}
+
+
+fn distance(color: Color, other: (u8, u8, u8)) -> u32 {
+    let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    channel(color.r, other.0) + channel(color.g, other.1) + channel(color.b, other.2)
+}
+
+fn nearest_cube_index(value: u8) -> usize {
+    (0..CUBE_LEVELS.len())
+        .min_by_key(|&index| (CUBE_LEVELS[index] as i32 - value as i32).abs())
+        .unwrap()
+}
+
+/// Returns: the nearest colour of the 6x6x6 cube or the grayscale ramp,
+/// the 16 basic colours are skipped because terminals redefine them
+pub fn to_ansi_256(color: Color) -> u8 {
+    let (r, g, b) = (
+        nearest_cube_index(color.r),
+        nearest_cube_index(color.g),
+        nearest_cube_index(color.b),
+    );
+    let cube = (CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]);
+    let cube_index = 16 + 36 * r + 6 * g + b;
+
+    // The ramp goes from 8 to 238 in steps of 10
+    let average = (color.r as u32 + color.g as u32 + color.b as u32) / 3;
+    let gray_step = (average.saturating_sub(3) / 10).min(23);
+    let gray_level = (8 + 10 * gray_step) as u8;
+    let gray_index = 232 + gray_step as usize;
+
+    if distance(color, (gray_level, gray_level, gray_level)) < distance(color, cube) {
+        gray_index as u8
+    } else {
+        cube_index as u8
+    }
+}
+
+pub fn to_ansi_16(color: Color) -> u8 {
+    (0..ANSI_16_COLORS.len())
+        .min_by_key(|&index| distance(color, ANSI_16_COLORS[index]))
+        .unwrap() as u8
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn cube_corners() {
+        assert_eq!(to_ansi_256(Color::new(0, 0, 0)), 16);
+        assert_eq!(to_ansi_256(Color::new(255, 0, 0)), 196);
+        assert_eq!(to_ansi_256(Color::new(0, 255, 0)), 46);
+        assert_eq!(to_ansi_256(Color::new(0, 0, 255)), 21);
+        assert_eq!(to_ansi_256(Color::new(255, 255, 255)), 231);
+        assert_eq!(to_ansi_256(Color::new(255, 0, 255)), 201);
+    }
+
+    #[test]
+    fn cube_rounds_to_nearest_level() {
+        // 100 is closest to 95, 200 to 215, so the cube coordinates are (1, 0, 4)
+        assert_eq!(to_ansi_256(Color::new(100, 0, 200)), 56);
+    }
+
+    #[test]
+    fn grays_use_the_ramp() {
+        assert_eq!(to_ansi_256(Color::new(8, 8, 8)), 232);
+        assert_eq!(to_ansi_256(Color::new(128, 128, 128)), 244);
+        assert_eq!(to_ansi_256(Color::new(238, 238, 238)), 255);
+        // The background of the game is a dark gray with a tint
+        assert_eq!(to_ansi_256(Color::new(0x1e, 0x1a, 0x25)), 234);
+    }
+
+    #[test]
+    fn basic_colors_map_to_themselves() {
+        for (index, &(r, g, b)) in ANSI_16_COLORS.iter().enumerate() {
+            assert_eq!(to_ansi_16(Color::new(r, g, b)) as usize, index);
+        }
+    }
+
+    #[test]
+    fn game_colors_in_16() {
+        assert_eq!(ANSI_16_NAMES[to_ansi_16(Color::magenta()) as usize], style::Color::Magenta);
+        assert_eq!(ANSI_16_NAMES[to_ansi_16(Color::cyan()) as usize], style::Color::Cyan);
+        assert_eq!(ANSI_16_NAMES[to_ansi_16(Color::new(0x1e, 0x1a, 0x25)) as usize], style::Color::Black);
+        assert_eq!(ANSI_16_NAMES[to_ansi_16(Color::new(140, 0, 0)) as usize], style::Color::DarkRed);
+    }
+
+    #[test]
+    fn convert_per_depth() {
+        let color = Color::new(255, 0, 0);
+
+        assert_eq!(ColorDepth::TrueColor.convert(color), Some(style::Color::Rgb { r: 255, g: 0, b: 0 }));
+        assert_eq!(ColorDepth::Ansi256.convert(color), Some(style::Color::AnsiValue(196)));
+        assert_eq!(ColorDepth::Ansi16.convert(color), Some(style::Color::Red));
+        assert_eq!(ColorDepth::Monochrome.convert(color), None);
+    }
+
+    #[test]
+    fn environment_detection() {
+        assert_eq!(ColorDepth::from_environment(Some("truecolor"), Some("xterm")), ColorDepth::TrueColor);
+        assert_eq!(ColorDepth::from_environment(None, Some("xterm-256color")), ColorDepth::Ansi256);
+        assert_eq!(ColorDepth::from_environment(None, Some("xterm-direct")), ColorDepth::TrueColor);
+        assert_eq!(ColorDepth::from_environment(None, Some("linux")), ColorDepth::Ansi16);
+        assert_eq!(ColorDepth::from_environment(None, Some("dumb")), ColorDepth::Monochrome);
+        assert_eq!(ColorDepth::from_environment(None, None), ColorDepth::Ansi16);
+    }
+
+    #[test]
+    fn flag_parsing() {
+        assert_eq!(ColorDepth::from_args(&args(&["cybersem", "--colors", "256"])), Some(ColorDepth::Ansi256));
+        assert_eq!(ColorDepth::from_args(&args(&["cybersem", "--colors=mono"])), Some(ColorDepth::Monochrome));
+        assert_eq!(ColorDepth::from_args(&args(&["cybersem", "--colors"])), None);
+        assert_eq!(ColorDepth::from_args(&args(&["cybersem"])), None);
+    }
+}
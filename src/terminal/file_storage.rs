@@ -0,0 +1,87 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::game::*;
+
+use std::{
+    env,
+    fs,
+    io,
+    path::PathBuf,
+};
+
+pub const SAVE_FILE_NAME: &str = "save.txt";
+pub const SETTINGS_FILE_NAME: &str = "settings.txt";
+
+/// Keeps a file in $XDG_DATA_HOME/cybersem, which defaults to ~/.local/share
+pub struct FileStorage {
+    pub path: Option<PathBuf>,
+}
+
+impl FileStorage {
+    /// Arguments:
+    /// file_name - SAVE_FILE_NAME or SETTINGS_FILE_NAME
+    pub fn new(file_name: &str) -> Self {
+        let data_dir = env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")));
+
+        Self {
+            path: data_dir.map(|dir| dir.join("cybersem").join(file_name)),
+        }
+    }
+
+    fn get_path(&self) -> Result<&PathBuf, SaveError> {
+        self.path.as_ref().ok_or_else(|| SaveError::Storage("neither XDG_DATA_HOME nor HOME is set".into()))
+    }
+
+    fn storage_error(&self, error: io::Error) -> SaveError {
+        let path = self.path.as_ref().map(|path| path.display().to_string()).unwrap_or_default();
+        SaveError::Storage(format!("{}: {}", path, error))
+    }
+}
+
+impl SaveStorage for FileStorage {
+    fn read(&self) -> Result<Option<String>, SaveError> {
+        match fs::read_to_string(self.get_path()?) {
+            Ok(text) => Ok(Some(text)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(self.storage_error(error)),
+        }
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), SaveError> {
+        let path = self.get_path()?.clone();
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|error| self.storage_error(error))?;
+        }
+
+        // Written next to the save and renamed, so that a crash does not leave half a file
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, text).map_err(|error| self.storage_error(error))?;
+        fs::rename(&temporary, &path).map_err(|error| self.storage_error(error))
+    }
+
+    fn remove(&mut self) -> Result<(), SaveError> {
+        match fs::remove_file(self.get_path()?) {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(self.storage_error(error)),
+        }
+    }
+}
@@ -15,12 +15,18 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::game::*;
-use crate::special_key_codes::*;
+
+use super::args::{get_flag_value, has_flag};
+use super::color_depth::ColorDepth;
+use super::file_storage::{FileStorage, SAVE_FILE_NAME, SETTINGS_FILE_NAME};
+use super::screen::Screen;
 
 use std :: {
+    fs,
     io::{self, Write},
-    thread,
-    time::Duration
+    panic,
+    process,
+    time::{Duration, Instant}
 };
 
 
@@ -36,16 +42,166 @@ use crossterm :: {
 
 
 
+/// Same as the update interval in the browser
+const TICK_DURATION: Duration = Duration::from_millis(30);
+
+/// Terminals only report key presses (and their auto-repeat), not releases,
+/// so a held action is assumed to last this many ticks after the first press.
+/// Short, so that a tap moves the hero by a cell. Holding the key relies on the auto-repeat.
+const FIRST_PRESS_HELD_TICKS: u32 = 2;
+
+/// Same as FIRST_PRESS_HELD_TICKS for the jump, which is cut short once released.
+/// Covers the initial auto-repeat delay, which is 250-600 ms on common systems,
+/// so that holding the key gives the full jump.
+const JUMP_FIRST_PRESS_HELD_TICKS: u32 = 20;
+
+/// Same as FIRST_PRESS_HELD_TICKS, but after an auto-repeated press.
+/// Covers the auto-repeat interval, which is much shorter than the initial delay.
+const REPEAT_HELD_TICKS: u32 = 8;
+
+/// With CYBERSEM_STATS set, the terminal title shows the output stats about once a second
+const STATS_TITLE_FRAMES: u64 = 33;
+
 pub struct GameRunner {
     game: Box<Game>,
+    /// Actions with the number of ticks they are still considered held
+    held_actions: Vec<(Action, u32)>,
+    screen: Screen,
+    is_cursor_visible: bool,
+
+    /// Where the replay is saved on exit, from --record FILE
+    recording_path: Option<String>,
+    /// Plays back --replay FILE instead of the keyboard input
+    player: Option<ReplayPlayer>,
+    replay_error: Option<ReplayError>,
+
+    /// None if the progress is not saved, e.g. during a replay
+    storage: Option<FileStorage>,
+    /// None during a replay, which uses the recorded bindings
+    settings_storage: Option<FileStorage>,
+    /// The last failure, reported on exit
+    save_error: Option<SaveError>,
+    /// From CYBERSEM_STATS
+    is_showing_stats: bool,
 }
 
 impl GameRunner {
     pub fn new() -> Self {
-        Self {
-            game: Box::new(
-                Game::new(Self::static_get_size())
-            ),
+        let args: Vec<String> = std::env::args().collect();
+
+        let player = get_flag_value(&args, "replay").map(|path| {
+            let replay = fs::read_to_string(path)
+                .map_err(|error| error.to_string())
+                .and_then(|text| Replay::from_text(&text).map_err(|error| error.to_string()));
+
+            match replay {
+                Ok(replay) => ReplayPlayer::new(replay),
+                Err(error) => {
+                    eprintln!("Cannot load the replay {}: {}", path, error);
+                    process::exit(1);
+                }
+            }
+        });
+
+        // The simulation depends on the size, so a replay keeps the recorded one
+        let game = match &player {
+            Some(player) => Game::from_replay(&player.replay),
+            None => Game::new(Self::static_get_size()),
+        };
+
+        let recording_path = get_flag_value(&args, "record").map(String::from);
+
+        // Replays start from a new game and must not overwrite the progress
+        let storage = if player.is_none() && recording_path.is_none() {
+            Some(FileStorage::new(SAVE_FILE_NAME))
+        } else {
+            None
+        };
+
+        let settings_storage = if player.is_none() {
+            Some(FileStorage::new(SETTINGS_FILE_NAME))
+        } else {
+            None
+        };
+
+        let mut game_runner = Self {
+            game: Box::new(game),
+            held_actions: vec![],
+            screen: Screen::new(ColorDepth::detect()),
+            is_cursor_visible: false,
+            recording_path,
+            player,
+            replay_error: None,
+            storage,
+            settings_storage,
+            save_error: None,
+            is_showing_stats: std::env::var_os("CYBERSEM_STATS").is_some(),
+        };
+
+        game_runner.load_settings();
+        game_runner.load(has_flag(&args, "new-game"));
+        game_runner
+    }
+
+    /// Arguments:
+    /// is_new_game - the save is replaced by a new game with the same best splits
+    fn load(&mut self, is_new_game: bool) {
+        let storage = match &mut self.storage {
+            Some(storage) => storage,
+            None => return,
+        };
+
+        let result = match storage.load() {
+            Ok(Some(data)) if is_new_game => {
+                self.game.load_save_data(&data);
+                self.game.restart();
+                storage.save(&self.game.get_save_data())
+            }
+
+            Ok(Some(data)) => {
+                self.game.load_save_data(&data);
+                Ok(())
+            }
+
+            Ok(None) => Ok(()),
+
+            // A broken save would not be loaded anyway
+            Err(_) if is_new_game => storage.remove(),
+
+            Err(error) => Err(error),
+        };
+
+        if let Err(error) = result {
+            self.save_error = Some(error);
+        }
+    }
+
+    fn load_settings(&mut self) {
+        let result = match &self.settings_storage {
+            Some(storage) => Settings::load(storage),
+            None => return,
+        };
+
+        match result {
+            Ok(Some(settings)) => self.game.load_settings(&settings),
+            Ok(None) => {}
+            Err(error) => self.save_error = Some(error),
+        }
+    }
+
+    fn save_settings(&mut self) {
+        if let Some(storage) = &mut self.settings_storage {
+            if let Err(error) = self.game.get_settings().save(storage) {
+                self.save_error = Some(error);
+            }
+        }
+    }
+
+    fn save(&mut self) {
+        if let Some(storage) = &mut self.storage {
+            if let Err(error) = storage.save(&self.game.get_save_data()) {
+                self.save_error = Some(error);
+            }
         }
     }
 
@@ -59,135 +215,251 @@ impl GameRunner {
     }
 
     fn clear(&self) {
-        queue!(io::stdout(), terminal::Clear(terminal::ClearType::Purge));
+        execute!(io::stdout(), terminal::Clear(terminal::ClearType::All));
     }
 
-    fn cleanup(&self) {
+    fn setup() {
+        terminal::enable_raw_mode();
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide);
+    }
+
+    /// Also called from the panic hook, so that the panic message is readable
+    fn restore() {
         execute!(
             io::stdout(),
-            cursor::MoveTo(0, 0),
-            terminal::Clear(terminal::ClearType::All),
-            terminal::Clear(terminal::ClearType::Purge)
+            style::SetAttribute(style::Attribute::Reset),
+            style::ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
         );
+        terminal::disable_raw_mode();
     }
 
-    fn flush(&self) {
-        io::stdout().flush();
-    }
+    fn queue_cursor(&mut self, out: &mut Vec<u8>) {
+        if !self.game.is_expecting_text() {
+            if self.is_cursor_visible {
+                queue!(out, cursor::Hide);
+                self.is_cursor_visible = false;
+            }
+            return;
+        }
+
+        self.is_cursor_visible = true;
 
-    fn update_cursor(&self) {
         queue!(
-            io::stdout(),
+            out,
             cursor::MoveTo(
                 self.game.cursor_position.x as u16,
                 self.game.cursor_position.y as u16
-            )
+            ),
+            cursor::Show
         );
     }
 
-    fn draw(&self) {
-        let mut x = 0usize;
-        let mut y = 0usize;
+    /// Writes the changes since the previous frame at once
+    fn draw(&mut self) {
+        let mut out = vec![];
+
+        self.screen.draw(&self.game.frame, &mut out);
+        self.queue_cursor(&mut out);
 
-        queue!(io::stdout(), cursor::Hide);
-        
-        for line in &self.game.symbol_buffer {
-            queue!(io::stdout(), cursor::MoveTo(0, y as u16));
+        io::stdout().write_all(&out);
+        io::stdout().flush();
 
-            x = 0;
+        self.screen.stats.record(out.len());
+
+        let stats = &self.screen.stats;
+        if self.is_showing_stats && stats.frames.is_multiple_of(STATS_TITLE_FRAMES) {
+            execute!(
+                io::stdout(),
+                terminal::SetTitle(format!(
+                    "Frame: {} cells, {} bytes, average: {} bytes",
+                    stats.last_frame_cells,
+                    stats.last_frame_bytes,
+                    stats.get_average_bytes()
+                ))
+            );
+        }
+    }
 
-            for symbol in line {
+    /// Terminals report characters rather than physical keys, so letters depend on the layout
+    fn get_key(code: event::KeyCode) -> Option<Key> {
+        match code {
+            event::KeyCode::Char(ch) => Key::from_char(ch),
+            event::KeyCode::Enter => Some(Key::Enter),
+            event::KeyCode::Backspace => Some(Key::Backspace),
+            event::KeyCode::Esc => Some(Key::Escape),
+            event::KeyCode::Tab => Some(Key::Tab),
+            event::KeyCode::Left => Some(Key::ArrowLeft),
+            event::KeyCode::Right => Some(Key::ArrowRight),
+            event::KeyCode::Up => Some(Key::ArrowUp),
+            event::KeyCode::Down => Some(Key::ArrowDown),
+            _ => None,
+        }
+    }
 
-                if *symbol != ' ' {
-                    let color = &self.game.color_buffer[y][x];
+    fn get_key_action(&self, key_event: event::KeyEvent) -> Option<Action> {
+        let ctrl = key_event.modifiers.contains(event::KeyModifiers::CONTROL);
+        self.game.get_key_action(Self::get_key(key_event.code)?, ctrl)
+    }
 
-                    queue!(
-                        io::stdout(),
-                        style::SetForegroundColor(
-                            style::Color::Rgb { r: color.r, g: color.g, b: color.b }
-                        )
-                    );
+    fn handle_event(&mut self, event: event::Event) {
+        let is_replaying = self.player.is_some();
+
+        match event {
+            event::Event::Key(key_event) => {
+                let action = match self.get_key_action(key_event) {
+                    Some(action) => action,
+                    None => return,
+                };
+
+                // Only quitting is allowed during a replay
+                if is_replaying {
+                    if action == Action::Quit {
+                        self.game.stopped = true;
+                    }
+                    return;
                 }
 
-                write!(io::stdout(), "{}", symbol);
+                if !action.is_held() {
+                    self.game.process_action(action);
+                    return;
+                }
+
+                match self.held_actions.iter_mut().find(|(held, _)| *held == action) {
+                    Some((_, ticks)) => *ticks = (*ticks).max(REPEAT_HELD_TICKS),
+                    None => {
+                        let ticks = if action == Action::Jump { JUMP_FIRST_PRESS_HELD_TICKS } else { FIRST_PRESS_HELD_TICKS };
+                        self.held_actions.push((action, ticks));
+                    }
+                }
+            }
 
-                x += 1;
+            event::Event::Resize(width, height) => {
+                if !is_replaying {
+                    self.game.set_size(Size::new(width.into(), height.into()));
+                }
+                self.clear();
+                self.screen.invalidate();
             }
 
-            y += 1;
+            _ => {}
+        }
+    }
+
+    fn apply_held_actions(&mut self) {
+        if self.game.is_expecting_text() || self.game.is_in_menu() {
+            self.held_actions.clear();
         }
 
-        queue!(io::stdout(), cursor::Show);
+        for (action, ticks) in &mut self.held_actions {
+            self.game.process_action(*action);
+            *ticks -= 1;
+        }
 
+        self.held_actions.retain(|(_, ticks)| *ticks > 0);
     }
 
-    pub fn run(&mut self) {
-        terminal::enable_raw_mode();
+    fn tick(&mut self) {
+        let player = match &mut self.player {
+            Some(player) => player,
+            None => {
+                self.apply_held_actions();
+                self.game.render();
 
-        loop {
-
-            if event::poll(Duration::from_millis(30)).unwrap() {
-                match event::read().unwrap() {
-                    event::Event::Key(key_event) => {
-                        match key_event.code {
-                            event::KeyCode::Char(ch) => {
-                                if key_event.modifiers.contains(event::KeyModifiers::CONTROL) {
-                                    self.game.process_key(ch, true);
-                                } else {
-                                    self.game.process_key(ch, false);
-                                }
-                            }
-
-                            event::KeyCode::Left => {
-                                self.game.process_key('[', false);
-                            }
-
-                            event::KeyCode::Right => {
-                                self.game.process_key(']', false);
-                            }
-
-                            event::KeyCode::Up => {
-                                self.game.process_key('/', false);
-                            }
-
-                            event::KeyCode::Down => {
-                                self.game.process_key('.', false);
-                            }
-
-                            event::KeyCode::Enter => {
-                                self.game.process_key(KEY_ENTER, false);
-                            }
-
-                            event::KeyCode::Backspace => {
-                                self.game.process_key(KEY_BACKSPACE, false);
-                            }
-
-                            _ => {}
-                        }
-                    }
+                if self.game.take_autosave_request() {
+                    self.save();
+                }
 
-                    _ => {}
+                if self.game.take_settings_save_request() {
+                    self.save_settings();
                 }
+                return;
             }
+        };
+
+        player.apply_events(&mut self.game);
+        self.game.render();
 
-            if self.game.stopped {
-                break;
+        if let Err(error) = player.verify(&self.game) {
+            self.replay_error = Some(error);
+            self.game.stopped = true;
+        }
+
+        if player.is_finished(&self.game) {
+            self.game.stopped = true;
+        }
+    }
+
+    /// Saves the recording or reports the result of the replay
+    fn finish_replay(&mut self) {
+        if let (Some(path), Some(recording)) = (&self.recording_path, self.game.take_recording()) {
+            match fs::write(path, recording.to_text()) {
+                Ok(()) => eprintln!("Replay of {} ticks saved to {}", recording.length, path),
+                Err(error) => eprintln!("Cannot save the replay to {}: {}", path, error),
             }
+        }
 
+        if let Some(player) = &self.player {
+            match &self.replay_error {
+                Some(error) => eprintln!("Replay failed: {}", error),
+                None if player.is_finished(&self.game) =>
+                    eprintln!("Replay finished after {} ticks, no desync", self.game.ticks),
+                None => eprintln!("Replay stopped at tick {}", self.game.ticks),
+            }
+        }
+    }
+
+    pub fn run(&mut self) {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            Self::restore();
+            default_hook(info);
+        }));
+
+        Self::setup();
+
+        if self.player.is_none() {
             self.game.set_size(self.get_size());
-            self.game.render();
-            self.clear();
-            self.draw();
-            self.update_cursor();
-            self.flush();
+        }
 
-            // thread::sleep(Duration::from_millis(64));
+        if self.recording_path.is_some() {
+            self.game.start_recording();
         }
 
-        self.cleanup();
-        self.flush();
+        let mut next_tick = Instant::now();
 
-        terminal::disable_raw_mode();
-        // self.flush();
+        while !self.game.stopped {
+            let timeout = next_tick.saturating_duration_since(Instant::now());
+
+            if event::poll(timeout).unwrap() {
+                self.handle_event(event::read().unwrap());
+                continue;
+            }
+
+            // Do not try to catch up if the terminal is too slow
+            next_tick = (next_tick + TICK_DURATION).max(Instant::now());
+
+            self.tick();
+            self.draw();
+        }
+
+        Self::restore();
+        let _ = panic::take_hook();
+
+        self.save();
+        self.finish_replay();
+
+        if let Some(error) = &self.save_error {
+            eprintln!("Cannot load or save the progress: {}", error);
+        }
+
+        if self.is_showing_stats {
+            eprintln!(
+                "Frames: {}, average output: {} bytes per frame",
+                self.screen.stats.frames,
+                self.screen.stats.get_average_bytes()
+            );
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,2 +1,7 @@
 pub mod game_runner;
+mod args;
+mod color_depth;
+mod file_storage;
+mod screen;
+
 pub use game_runner::*;
\ No newline at end of file
@@ -0,0 +1,266 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::game::*;
+
+use super::color_depth::ColorDepth;
+
+use std::io::Write;
+
+use crossterm :: {
+    cursor,
+    queue,
+    style,
+};
+
+
+/// Colours and attributes the terminal currently uses for new text
+#[derive(Clone, Copy, PartialEq)]
+struct TextStyle {
+    fg: Color,
+    bg: Option<Color>,
+    attrs: u32,
+}
+
+impl TextStyle {
+    fn of(cell: &Cell) -> Self {
+        Self { fg: cell.fg, bg: cell.bg, attrs: cell.attrs }
+    }
+}
+
+
+pub struct FrameStats {
+    pub frames: u64,
+    pub total_bytes: u64,
+    pub last_frame_bytes: usize,
+    /// Cells written by the last Screen::draw()
+    pub last_frame_cells: usize,
+}
+
+impl FrameStats {
+    pub fn new() -> Self {
+        Self { frames: 0, total_bytes: 0, last_frame_bytes: 0, last_frame_cells: 0 }
+    }
+
+    pub fn record(&mut self, bytes: usize) {
+        self.frames += 1;
+        self.total_bytes += bytes as u64;
+        self.last_frame_bytes = bytes;
+    }
+
+    pub fn get_average_bytes(&self) -> u64 {
+        self.total_bytes.checked_div(self.frames).unwrap_or(0)
+    }
+}
+
+
+/// Remembers what is shown in the terminal, so that only changed cells are written
+pub struct Screen {
+    previous_frame: Vec<Vec<Cell>>,
+    /// None if unknown, e.g. before the first frame
+    style: Option<TextStyle>,
+    color_depth: ColorDepth,
+    pub stats: FrameStats,
+}
+
+impl Screen {
+    pub fn new(color_depth: ColorDepth) -> Self {
+        Self {
+            previous_frame: vec![],
+            style: None,
+            color_depth,
+            stats: FrameStats::new(),
+        }
+    }
+
+    /// Makes the next draw() write every cell, e.g. after the terminal has been resized or cleared
+    pub fn invalidate(&mut self) {
+        self.previous_frame.clear();
+        self.style = None;
+    }
+
+    /// Writes the commands that turn the previous frame into the given one
+    pub fn draw<W: Write>(&mut self, frame: &[Vec<Cell>], out: &mut W) {
+        let is_same_shape = self.previous_frame.len() == frame.len()
+            && self.previous_frame.iter().zip(frame).all(|(old, new)| old.len() == new.len());
+
+        if !is_same_shape {
+            self.previous_frame.clear();
+        }
+
+        // Where the terminal cursor is after the last written glyph
+        let mut cursor_position: Option<(usize, usize)> = None;
+        let mut written_cells = 0;
+
+        for (y, row) in frame.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                let is_changed = match self.previous_frame.get(y) {
+                    Some(old_row) => old_row[x] != *cell,
+                    None => true,
+                };
+
+                if !is_changed {
+                    continue;
+                }
+
+                if cursor_position != Some((x, y)) {
+                    queue!(out, cursor::MoveTo(x as u16, y as u16));
+                }
+
+                let mut style = TextStyle::of(cell);
+
+                // The foreground of a space is invisible, keep the current one
+                if cell.glyph == ' ' && !cell.has_attr(ATTR_UNDERLINE) {
+                    if let Some(current) = self.style {
+                        style.fg = current.fg;
+                    }
+                }
+
+                self.set_style(style, out);
+                write!(out, "{}", cell.glyph);
+
+                cursor_position = Some((x + 1, y));
+                written_cells += 1;
+            }
+        }
+
+        self.previous_frame = frame.to_vec();
+        self.stats.last_frame_cells = written_cells;
+    }
+
+    /// Only emits the escapes for what differs from the current style
+    fn set_style<W: Write>(&mut self, new: TextStyle, out: &mut W) {
+        let old = match self.style {
+            Some(old) if old.attrs == new.attrs => old,
+
+            // SGR reset also resets the colours, so attributes go first
+            _ => {
+                Self::queue_attributes(new.attrs, out);
+                queue!(out, style::SetForegroundColor(self.terminal_color(new.fg)));
+                queue!(out, style::SetBackgroundColor(self.terminal_background(new.bg)));
+                self.style = Some(new);
+                return;
+            }
+        };
+
+        if old.fg != new.fg {
+            queue!(out, style::SetForegroundColor(self.terminal_color(new.fg)));
+        }
+
+        if old.bg != new.bg {
+            queue!(out, style::SetBackgroundColor(self.terminal_background(new.bg)));
+        }
+
+        self.style = Some(new);
+    }
+
+    fn queue_attributes<W: Write>(attrs: u32, out: &mut W) {
+        queue!(out, style::SetAttribute(style::Attribute::Reset));
+
+        let mapping = [
+            (ATTR_BOLD, style::Attribute::Bold),
+            (ATTR_DIM, style::Attribute::Dim),
+            (ATTR_UNDERLINE, style::Attribute::Underlined),
+            (ATTR_BLINK, style::Attribute::SlowBlink),
+        ];
+
+        for (attr, terminal_attr) in mapping {
+            if attrs & attr != 0 {
+                queue!(out, style::SetAttribute(terminal_attr));
+            }
+        }
+    }
+
+    fn terminal_color(&self, color: Color) -> style::Color {
+        self.color_depth.convert(color).unwrap_or(style::Color::Reset)
+    }
+
+    fn terminal_background(&self, color: Option<Color>) -> style::Color {
+        match color {
+            Some(color) => self.terminal_color(color),
+            None => style::Color::Reset,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_of(rows: &[&str]) -> Vec<Vec<Cell>> {
+        rows.iter()
+            .map(|row| row.chars().map(|glyph| Cell::new(glyph, Color::white())).collect())
+            .collect()
+    }
+
+    /// Returns: what the screen writes for the frame
+    fn draw(screen: &mut Screen, frame: &[Vec<Cell>]) -> Vec<u8> {
+        let mut out = vec![];
+        screen.draw(frame, &mut out);
+        out
+    }
+
+    #[test]
+    fn unchanged_frame_writes_nothing() {
+        let mut screen = Screen::new(ColorDepth::TrueColor);
+        let frame = frame_of(&["abc", "def"]);
+
+        assert!(!draw(&mut screen, &frame).is_empty());
+        assert_eq!(screen.stats.last_frame_cells, 6);
+
+        assert!(draw(&mut screen, &frame).is_empty());
+        assert_eq!(screen.stats.last_frame_cells, 0);
+    }
+
+    #[test]
+    fn changed_cell_writes_cursor_move_and_glyph() {
+        let mut screen = Screen::new(ColorDepth::TrueColor);
+        draw(&mut screen, &frame_of(&["abc", "def"]));
+
+        let out = draw(&mut screen, &frame_of(&["abc", "dXf"]));
+        let mut expected = vec![];
+        queue!(expected, cursor::MoveTo(1, 1));
+        write!(expected, "X");
+
+        assert_eq!(out, expected);
+        assert_eq!(screen.stats.last_frame_cells, 1);
+    }
+
+    #[test]
+    fn invalidate_redraws_everything() {
+        let mut screen = Screen::new(ColorDepth::TrueColor);
+        let frame = frame_of(&["abc", "def"]);
+        let first = draw(&mut screen, &frame);
+
+        screen.invalidate();
+
+        assert_eq!(draw(&mut screen, &frame), first);
+        assert_eq!(screen.stats.last_frame_cells, 6);
+    }
+
+    #[test]
+    fn average_bytes() {
+        let mut stats = FrameStats::new();
+        assert_eq!(stats.get_average_bytes(), 0);
+
+        stats.record(100);
+        stats.record(50);
+        assert_eq!(stats.frames, 2);
+        assert_eq!(stats.last_frame_bytes, 50);
+        assert_eq!(stats.get_average_bytes(), 75);
+    }
+}
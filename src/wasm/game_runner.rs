@@ -18,25 +18,78 @@
 #![allow(non_snake_case)]
 
 use crate::game::*;
-use crate::special_key_codes::*;
+use crate::game::ascii_art::{BACKGROUND_COLOR, WORLD_HEIGHT, WORLD_MIN_WIDTH};
+
+use super::local_storage::{LocalStorage, SAVE_KEY, SETTINGS_KEY};
+use super::text_runs::{split_text_runs, TextRun};
+
+use std::collections::HashMap;
 
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::console;
 
+/// Blinking glyphs are shown for this number of updates and then hidden for the same number
+const BLINK_PERIOD_TICKS: u32 = 15;
+
+/// The font is scaled so that at least this many cells fit into the canvas.
+/// Leaves room for the console below the world.
+const MIN_COLUMNS: i32 = WORLD_MIN_WIDTH + 20;
+const MIN_ROWS: i32 = WORLD_HEIGHT + 5;
+
+/// Font metrics are measured at this size and scaled
+const REFERENCE_FONT_SIZE: f64 = 100.;
+
+/// Weight of the latest frame in the average frame time
+const FRAME_TIME_SMOOTHING: f64 = 0.1;
+
 #[wasm_bindgen]
 pub struct GameRunner {
+    canvas: web_sys::HtmlCanvasElement,
     ctx: web_sys::CanvasRenderingContext2d,
+    performance: web_sys::Performance,
+    /// Measured in device pixels
     canvas_size: Size,
     game: Box<Game>,
+    blink_ticks: u32,
+
+    font: String,
+    bold_font: String,
+    /// Size of a cell in device pixels
+    font_width: f64,
+    font_height: f64,
+
+    /// CSS strings of the colours used so far
+    color_styles: HashMap<Color, JsValue>,
+    /// What the context currently uses, to skip redundant calls
+    fill_color: Option<Color>,
+    is_font_bold: bool,
+
+    /// Measured in milliseconds
+    last_frame_time: f64,
+    average_frame_time: f64,
+
+    /// Replaces the keyboard and gamepad input while Some
+    player: Option<ReplayPlayer>,
+    gamepad: Gamepad,
+
+    storage: LocalStorage,
+    settings_storage: LocalStorage,
 }
 
 
+impl Default for GameRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[wasm_bindgen]
 impl GameRunner {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Self {
-        let document = web_sys::window().unwrap().document().unwrap();
+        let window = web_sys::window().unwrap();
+        let document = window.document().unwrap();
         let canvas = document.get_element_by_id("canvas").unwrap();
         let canvas: web_sys::HtmlCanvasElement = canvas
             .dyn_into::<web_sys::HtmlCanvasElement>()
@@ -50,10 +103,107 @@ impl GameRunner {
             .dyn_into::<web_sys::CanvasRenderingContext2d>()
             .unwrap();
 
-        Self {
+        let mut game_runner = Self {
+            canvas,
             ctx: context,
-            canvas_size: Size::new(900, 600),
-            game: Box::new(Game::new(Size::new(100, 35)))
+            performance: window.performance().unwrap(),
+            canvas_size: Size::new(0, 0),
+            game: Box::new(Game::new(Size::new(MIN_COLUMNS, MIN_ROWS))),
+            blink_ticks: 0,
+            font: String::new(),
+            bold_font: String::new(),
+            font_width: 0.,
+            font_height: 0.,
+            color_styles: HashMap::new(),
+            fill_color: None,
+            is_font_bold: false,
+            last_frame_time: 0.,
+            average_frame_time: 0.,
+            player: None,
+            gamepad: Gamepad::new(),
+            storage: LocalStorage::new(SAVE_KEY),
+            settings_storage: LocalStorage::new(SETTINGS_KEY),
+        };
+
+        match Settings::load(&game_runner.settings_storage) {
+            Ok(Some(settings)) => game_runner.game.load_settings(&settings),
+            Ok(None) => {}
+            Err(error) => console::error_1(&format!("Cannot load the settings: {}", error).into()),
+        }
+
+        match game_runner.storage.load() {
+            Ok(Some(data)) => game_runner.game.load_save_data(&data),
+            Ok(None) => {}
+            Err(error) => console::error_1(&format!("Cannot load the progress: {}", error).into()),
+        }
+
+        game_runner.resize();
+        game_runner
+    }
+
+    /// Saves the progress, also done periodically.
+    /// Does nothing during a recording or a replay, as they start from a new game.
+    pub fn save(&mut self) {
+        if self.player.is_some() || self.game.recording.is_some() {
+            return;
+        }
+
+        if let Err(error) = self.storage.save(&self.game.get_save_data()) {
+            console::error_1(&format!("Cannot save the progress: {}", error).into());
+        }
+    }
+
+    fn save_settings(&mut self) {
+        if let Err(error) = self.game.get_settings().save(&mut self.settings_storage) {
+            console::error_1(&format!("Cannot save the settings: {}", error).into());
+        }
+    }
+
+    /// Replaces the game with a new one that keeps the settings
+    fn restart(&mut self) {
+        let settings = self.game.get_settings();
+        *self.game = Game::new(self.game.size);
+        self.game.load_settings(&settings);
+    }
+
+    /// Fits the canvas resolution, the font and the game size to the size of the canvas on the page.
+    /// Called on window resize, which also happens when the device pixel ratio changes.
+    pub fn resize(&mut self) {
+        let pixel_ratio = web_sys::window().unwrap().device_pixel_ratio();
+
+        let width = (self.canvas.client_width() as f64 * pixel_ratio).round();
+        let height = (self.canvas.client_height() as f64 * pixel_ratio).round();
+
+        // Changing the resolution also resets the context state
+        self.canvas.set_width(width as u32);
+        self.canvas.set_height(height as u32);
+        self.canvas_size = Size::new(width as i32, height as i32);
+        self.fill_color = None;
+        self.is_font_bold = false;
+
+        self.ctx.set_font(&format!("{}px monospace", REFERENCE_FONT_SIZE));
+        let font_metrics = self.ctx.measure_text("W").unwrap();
+        let width_ratio = font_metrics.width() / REFERENCE_FONT_SIZE;
+        let height_ratio = font_metrics.font_bounding_box_ascent() / REFERENCE_FONT_SIZE;
+
+        let font_size = f64::min(
+            width / (MIN_COLUMNS as f64 * width_ratio),
+            height / (MIN_ROWS as f64 * height_ratio),
+        ).max(1.);
+
+        self.font = format!("{}px monospace", font_size);
+        self.bold_font = format!("bold {}px monospace", font_size);
+        self.font_width = font_size * width_ratio;
+        self.font_height = font_size * height_ratio;
+        self.ctx.set_font(&self.font);
+
+        // Rounding errors must not make the grid smaller than the minimum
+        let columns = ((width / self.font_width) as i32).max(MIN_COLUMNS);
+        let rows = ((height / self.font_height) as i32).max(MIN_ROWS);
+
+        // The simulation of a replay depends on the recorded size
+        if self.player.is_none() {
+            self.game.set_size(Size::new(columns, rows));
         }
     }
 
@@ -62,43 +212,238 @@ impl GameRunner {
     }
 
     pub fn update(&mut self) {
-        self.game.render();
-        self.draw();        
+        let start = self.performance.now();
+
+        self.tick();
+        self.draw();
+
+        self.last_frame_time = self.performance.now() - start;
+        self.average_frame_time += (self.last_frame_time - self.average_frame_time) * FRAME_TIME_SMOOTHING;
+    }
+
+    /// Returns: whether the key is handled again on the next update while it is held,
+    /// otherwise an action is applied once per press
+    /// Arguments:
+    /// key - KeyboardEvent.code, e.g. "KeyA"
+    pub fn handle_key(&mut self, key: &str) -> bool {
+        let action = match Key::from_name(key).and_then(|key| self.game.get_key_action(key, false)) {
+            Some(action) => action,
+            None => return false,
+        };
+
+        if self.player.is_none() {
+            self.game.process_action(action);
+        }
+
+        action.is_held()
+    }
+
+    /// Arguments:
+    /// index - of Gamepad.buttons in the standard mapping
+    pub fn handle_gamepad_button(&mut self, index: u32, is_pressed: bool) {
+        if let Some(button) = GamepadButton::from_index(index) {
+            self.gamepad.set_button(button, is_pressed);
+        }
+    }
+
+    /// Arguments:
+    /// index - of Gamepad.axes in the standard mapping
+    /// value - from -1 to 1
+    pub fn handle_gamepad_axis(&mut self, index: u32, value: f32) {
+        if let Some(axis) = GamepadAxis::from_index(index) {
+            self.gamepad.set_axis(axis, value);
+        }
+    }
+
+    /// Types spells on the virtual keyboard and opens the spell console on touch screens
+    ///
+    /// Arguments:
+    /// x, y - of the tap relative to the canvas, in CSS pixels
+    pub fn handle_tap(&mut self, x: f64, y: f64) {
+        let pixel_ratio = web_sys::window().unwrap().device_pixel_ratio();
+
+        // Text is drawn above its alphabetic baseline, so a row ends at the baseline
+        let cell = Position::new(
+            (x * pixel_ratio / self.font_width).floor() as i32,
+            (y * pixel_ratio / self.font_height).floor() as i32 + 1,
+        );
+
+        if let Some(action) = self.game.get_tap_action(cell) {
+            if self.player.is_none() {
+                self.game.process_action(action);
+            }
+        }
+    }
+
+    /// Restarts the game and records the input from now on
+    pub fn start_recording(&mut self) {
+        self.save();
+        self.player = None;
+        self.restart();
+        self.game.start_recording();
+    }
+
+    /// Returns: the replay file, if recording
+    pub fn stop_recording(&mut self) -> Option<String> {
+        self.game.take_recording().map(|replay| replay.to_text())
     }
 
-    pub fn handle_key(&mut self, key: char) {
-        self.game.process_key(key, false);
-    } 
+    /// Restarts the game and plays the replay file instead of the keyboard input.
+    /// The grid keeps the recorded size, so the picture may be cut or have empty space.
+    pub fn play_replay(&mut self, text: &str) -> Result<(), JsValue> {
+        let replay = Replay::from_text(text).map_err(|error| JsValue::from(error.to_string()))?;
+
+        self.save();
+        *self.game = Game::from_replay(&replay);
+        self.player = Some(ReplayPlayer::new(replay));
+
+        Ok(())
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.player.is_some()
+    }
 
     pub fn is_expecting_text(&self) -> bool {
         self.game.is_expecting_text()
     }
 
+    /// Returns: how long the last update took, in milliseconds
+    pub fn get_frame_time(&self) -> f64 {
+        self.last_frame_time
+    }
+
+    /// Returns: smoothed time of recent updates, in milliseconds
+    pub fn get_average_frame_time(&self) -> f64 {
+        self.average_frame_time
+    }
+
+    fn tick(&mut self) {
+        // Presses are taken even during a replay, so that they do not pile up
+        for action in self.gamepad.get_actions(&self.game) {
+            if self.player.is_none() {
+                self.game.process_action(action);
+            }
+        }
+
+        let player = match &mut self.player {
+            Some(player) => player,
+            None => {
+                self.game.render();
+
+                if self.game.take_autosave_request() {
+                    self.save();
+                }
+
+                if self.game.take_settings_save_request() {
+                    self.save_settings();
+                }
+                return;
+            }
+        };
+
+        player.apply_events(&mut self.game);
+        self.game.render();
+
+        let result = player.verify(&self.game);
+
+        if let Err(error) = &result {
+            console::error_1(&format!("Replay failed: {}", error).into());
+        } else if player.is_finished(&self.game) {
+            console::log_1(&format!("Replay finished after {} ticks, no desync", self.game.ticks).into());
+        }
+
+        if result.is_err() || player.is_finished(&self.game) {
+            self.player = None;
+        }
+    }
+
     fn draw(&mut self) {
-        self.ctx.clear_rect(0., 0., self.canvas_size.width as f64, self.canvas_size.height as f64);
-        self.ctx.set_font("14px monospace");
+        self.blink_ticks = (self.blink_ticks + 1) % (BLINK_PERIOD_TICKS * 2);
+        let is_blink_hidden = self.blink_ticks >= BLINK_PERIOD_TICKS;
 
-        let font_metrics = self.ctx.measure_text("W").unwrap();
-        let font_width = font_metrics.width();
-        let font_height = font_metrics.font_bounding_box_ascent();
+        self.set_fill_color(BACKGROUND_COLOR);
+        self.ctx.fill_rect(0., 0., self.canvas_size.width as f64, self.canvas_size.height as f64);
+
+        // All backgrounds go first, so that they do not cover the glyphs of the row above
+        for y in 0..self.game.size.height as usize {
+            self.draw_backgrounds(y);
+        }
 
         for y in 0..self.game.size.height as usize {
-            // console::log_1(&JsValue::from(format!("{}", y)));
+            self.draw_text(y, is_blink_hidden);
+        }
+    }
 
-            for x in 0..self.game.size.width as usize {
-                let color = &self.game.color_buffer[y][x];
-                let symbol = self.game.symbol_buffer[y][x];
+    /// Fills runs of cells with the same background by one rectangle each
+    fn draw_backgrounds(&mut self, y: usize) {
+        let width = self.game.size.width as usize;
+        let mut x = 0;
 
-                self.ctx.set_fill_style(&JsValue::from(
-                    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
-                ));
+        while x < width {
+            let background = self.game.frame[y][x].bg;
+            let start = x;
+
+            while x < width && self.game.frame[y][x].bg == background {
+                x += 1;
+            }
 
-                self.ctx.fill_text(
-                    symbol.to_string().as_str(),
-                    x as f64 * font_width,
-                    y as f64 * font_height
+            if let Some(background) = background {
+                self.set_fill_color(background);
+
+                // Text is drawn above its alphabetic baseline
+                self.ctx.fill_rect(
+                    start as f64 * self.font_width,
+                    (y as f64 - 1.) * self.font_height,
+                    (x - start) as f64 * self.font_width,
+                    self.font_height
                 );
             }
         }
     }
-}
\ No newline at end of file
+
+    fn draw_text(&mut self, y: usize, is_blink_hidden: bool) {
+        for run in split_text_runs(&self.game.frame[y], is_blink_hidden) {
+            self.draw_run(&run, y);
+        }
+    }
+
+    fn draw_run(&mut self, run: &TextRun, y: usize) {
+        self.set_fill_color(run.style.color);
+        self.set_font_bold(run.style.is_bold);
+
+        let run_x = run.x as f64 * self.font_width;
+        let run_y = y as f64 * self.font_height;
+
+        self.ctx.fill_text(&run.text, run_x, run_y);
+
+        if run.style.is_underlined {
+            let length = run.text.chars().count() as f64;
+            self.ctx.fill_rect(run_x, run_y, length * self.font_width, 1.);
+        }
+    }
+
+    fn set_fill_color(&mut self, color: Color) {
+        if self.fill_color == Some(color) {
+            return;
+        }
+
+        let style = self.color_styles
+            .entry(color)
+            .or_insert_with(|| Self::css_color(color));
+
+        self.ctx.set_fill_style(style);
+        self.fill_color = Some(color);
+    }
+
+    fn set_font_bold(&mut self, is_bold: bool) {
+        if self.is_font_bold != is_bold {
+            self.ctx.set_font(if is_bold { &self.bold_font } else { &self.font });
+            self.is_font_bold = is_bold;
+        }
+    }
+
+    fn css_color(color: Color) -> JsValue {
+        JsValue::from(format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b))
+    }
+}
@@ -0,0 +1,62 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::game::*;
+
+use wasm_bindgen::JsValue;
+
+pub const SAVE_KEY: &str = "cybersem-save";
+pub const SETTINGS_KEY: &str = "cybersem-settings";
+
+/// Keeps an item in the localStorage of the page
+pub struct LocalStorage {
+    /// None if the browser denies access, e.g. in private mode
+    storage: Option<web_sys::Storage>,
+    key: &'static str,
+}
+
+impl LocalStorage {
+    /// Arguments:
+    /// key - SAVE_KEY or SETTINGS_KEY
+    pub fn new(key: &'static str) -> Self {
+        Self {
+            storage: web_sys::window().and_then(|window| window.local_storage().ok().flatten()),
+            key,
+        }
+    }
+
+    fn get_storage(&self) -> Result<&web_sys::Storage, SaveError> {
+        self.storage.as_ref().ok_or_else(|| SaveError::Storage("localStorage is not available".into()))
+    }
+
+    fn storage_error(error: JsValue) -> SaveError {
+        SaveError::Storage(format!("{:?}", error))
+    }
+}
+
+impl SaveStorage for LocalStorage {
+    fn read(&self) -> Result<Option<String>, SaveError> {
+        self.get_storage()?.get_item(self.key).map_err(Self::storage_error)
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), SaveError> {
+        self.get_storage()?.set_item(self.key, text).map_err(Self::storage_error)
+    }
+
+    fn remove(&mut self) -> Result<(), SaveError> {
+        self.get_storage()?.remove_item(self.key).map_err(Self::storage_error)
+    }
+}
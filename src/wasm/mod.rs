@@ -1,3 +1,5 @@
 mod game_runner;
+mod local_storage;
+mod text_runs;
 
 pub use game_runner::*;
\ No newline at end of file
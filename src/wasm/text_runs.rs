@@ -0,0 +1,159 @@
+// CyberspaceEmissary, a terminal game
+// Copyright 2022 Mark Lagodych
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use crate::game::*;
+use crate::game::ascii_art::BACKGROUND_COLOR;
+
+/// Cells of a text run share these, so that the run is drawn with a single fill_text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextStyle {
+    pub color: Color,
+    pub is_bold: bool,
+    pub is_underlined: bool,
+}
+
+impl TextStyle {
+    fn of(cell: &Cell) -> Self {
+        Self {
+            color: if cell.has_attr(ATTR_DIM) {
+                cell.bg.unwrap_or(BACKGROUND_COLOR).blend(cell.fg, 0.5)
+            } else {
+                cell.fg
+            },
+            is_bold: cell.has_attr(ATTR_BOLD),
+            is_underlined: cell.has_attr(ATTR_UNDERLINE),
+        }
+    }
+}
+
+/// Consecutive glyphs of a row drawn at once
+#[derive(Debug, PartialEq)]
+pub struct TextRun {
+    pub x: usize,
+    pub text: String,
+    pub style: TextStyle,
+    /// Spaces after the text, added only if the run continues
+    pending_spaces: usize,
+}
+
+/// Groups glyphs of the same style into runs.
+/// Non-ASCII glyphs get runs of their own, because the fallback font may have a different width.
+///
+/// Arguments:
+/// is_blink_hidden - blinking glyphs are drawn as spaces
+pub fn split_text_runs(row: &[Cell], is_blink_hidden: bool) -> Vec<TextRun> {
+    let mut runs = vec![];
+    let mut run: Option<TextRun> = None;
+
+    for (x, cell) in row.iter().enumerate() {
+        if cell.glyph == ' ' || (cell.has_attr(ATTR_BLINK) && is_blink_hidden) {
+            if let Some(run) = &mut run {
+                run.pending_spaces += 1;
+            }
+            continue;
+        }
+
+        let style = TextStyle::of(cell);
+
+        match &mut run {
+            // Spaces of an underlined run would get underlined
+            Some(current) if current.style == style && cell.glyph.is_ascii()
+                && (current.pending_spaces == 0 || !style.is_underlined) =>
+            {
+                current.text.push_str(&" ".repeat(current.pending_spaces));
+                current.text.push(cell.glyph);
+                current.pending_spaces = 0;
+            }
+
+            _ => {
+                runs.extend(run.take());
+
+                let new_run = TextRun { x, text: cell.glyph.to_string(), style, pending_spaces: 0 };
+
+                if cell.glyph.is_ascii() {
+                    run = Some(new_run);
+                } else {
+                    runs.push(new_run);
+                }
+            }
+        }
+    }
+
+    runs.extend(run);
+    runs
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row_of(text: &str, color: Color) -> Vec<Cell> {
+        text.chars().map(|glyph| Cell::new(glyph, color)).collect()
+    }
+
+    /// Returns: (x, text) of the runs
+    fn split(row: &[Cell]) -> Vec<(usize, String)> {
+        split_text_runs(row, false).into_iter().map(|run| (run.x, run.text)).collect()
+    }
+
+    #[test]
+    fn same_style_joins() {
+        let row = row_of("  ab c  ", Color::white());
+        assert_eq!(split(&row), [(2, "ab c".to_string())]);
+    }
+
+    #[test]
+    fn color_change_splits() {
+        let mut row = row_of("ab", Color::white());
+        row.extend(row_of(" cd", Color::red()));
+
+        let runs = split_text_runs(&row, false);
+        assert_eq!(runs.len(), 2);
+        assert_eq!((runs[0].x, runs[0].text.as_str(), runs[0].style.color), (0, "ab", Color::white()));
+        assert_eq!((runs[1].x, runs[1].text.as_str(), runs[1].style.color), (3, "cd", Color::red()));
+    }
+
+    #[test]
+    fn attributes_split() {
+        let mut row = row_of("ab", Color::white());
+        row[1].attrs = ATTR_BOLD;
+        assert_eq!(split(&row), [(0, "a".to_string()), (1, "b".to_string())]);
+
+        // Spaces are not underlined
+        let mut row = row_of("a b", Color::white());
+        for cell in &mut row {
+            cell.attrs = ATTR_UNDERLINE;
+        }
+        assert_eq!(split(&row), [(0, "a".to_string()), (2, "b".to_string())]);
+    }
+
+    #[test]
+    fn wide_glyphs_stand_alone() {
+        let row = row_of("a█b", Color::white());
+        assert_eq!(split(&row), [(0, "a".to_string()), (1, "█".to_string()), (2, "b".to_string())]);
+    }
+
+    #[test]
+    fn hidden_blinking_glyphs() {
+        let mut row = row_of("a_b", Color::white());
+        row[1].attrs = ATTR_BLINK;
+
+        assert_eq!(split_text_runs(&row, true)[0].text, "a b");
+        assert_eq!(split_text_runs(&row, false)[0].text, "a_b");
+    }
+}
@@ -23,6 +23,9 @@ var game_runner;
 async function load_wasm() {
     await init_wasm();
     game_runner = new GameRunner();
+
+    // For replays and frame times from the developer console
+    window.game_runner = game_runner;
 }
 await load_wasm();
 
@@ -36,6 +39,20 @@ window.onload = ()=>{
         pressed_keys.add(key_event.code);
     };
 
+    // Autosaves may be up to a few seconds old when the tab is closed
+    window.onpagehide = ()=>{
+        game_runner.save();
+    };
+
+    window.onresize = ()=>{
+        game_runner.resize();
+    };
+
+    // Touch screens type spells on the virtual keyboard
+    document.getElementById('canvas').onpointerdown = (pointer_event)=>{
+        game_runner.handle_tap(pointer_event.offsetX, pointer_event.offsetY);
+    };
+
     window.onkeyup = (key_event)=>{
         if (pressed_keys.has(key_event.code)) {
             pressed_keys.delete(key_event.code);
@@ -45,29 +62,50 @@ window.onload = ()=>{
     new Audio('./web/assets/music.mp3').play();
 };
 
-function update() {
-    pressed_keys.forEach((key) => {
-        if (game_runner.is_expecting_text()) {
-            if (key == 'Space' || key.startsWith('Key') || key == 'Backspace')
-                pressed_keys.delete(key); // prevent from continuous handling of a single key
+// Buttons and axes of the first gamepad with the standard mapping are passed to the game,
+// which maps them to actions with a dead zone of the stick.
+// Buttons are passed on change, while axes are passed on every update.
+var gamepad_buttons = [];
+
+function poll_gamepad() {
+    var gamepad = Array.from(navigator.getGamepads ? navigator.getGamepads() : [])
+        .find((gamepad) => gamepad && gamepad.connected && gamepad.mapping === 'standard');
+
+    // A disconnected gamepad releases everything
+    if (!gamepad) {
+        if (gamepad_buttons.length > 0) {
+            gamepad_buttons.forEach((is_pressed, index) => {
+                if (is_pressed)
+                    game_runner.handle_gamepad_button(index, false);
+            });
+            [0, 1, 2, 3].forEach((index) => game_runner.handle_gamepad_axis(index, 0));
+            gamepad_buttons = [];
         }
-        
-        switch (key) {
-            case 'ArrowRight':  game_runner.handle_key('d'); break;
-            case 'ArrowLeft':   game_runner.handle_key('a'); break;
-            case 'ArrowUp':     game_runner.handle_key('w'); break;
-            case 'ArrowDown':   game_runner.handle_key('s'); break;
-            case 'Backspace': game_runner.handle_key('`'); break;
-            case 'Enter': game_runner.handle_key('\n'); break;
-            case 'Space': game_runner.handle_key(' '); break;
-            default: {
-                if (key.startsWith("Key"))
-                    game_runner.handle_key(key.slice(3).toLowerCase());
-                break;
-            }
+        return;
+    }
+
+    gamepad.buttons.forEach((button, index) => {
+        if (gamepad_buttons[index] !== button.pressed) {
+            gamepad_buttons[index] = button.pressed;
+            game_runner.handle_gamepad_button(index, button.pressed);
         }
     });
 
+    gamepad.axes.forEach((value, index) => {
+        game_runner.handle_gamepad_axis(index, value);
+    });
+}
+
+function update() {
+    poll_gamepad();
+
+    // Keys are passed as KeyboardEvent.code, the game maps them to actions with its bindings.
+    // Only movement repeats while held, e.g. a letter of a spell is typed once per press.
+    pressed_keys.forEach((key) => {
+        if (!game_runner.handle_key(key))
+            pressed_keys.delete(key);
+    });
+
     game_runner.update();
 
     if (game_runner.has_stopped()) {